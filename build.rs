@@ -0,0 +1,34 @@
+use std::{env, fs, path::Path};
+
+#[path = "src/render/shader_compiler.rs"]
+mod shader_compiler;
+
+const SHADER_DIR: &str = "src/shaders";
+
+/// Compiles the shaders into `embedded_shaders.rs` in `OUT_DIR`, the table of them that `render::shaders`
+/// includes. The SPIR-V is written as words, bytes from `include_bytes!` wouldn't be aligned for them.
+fn main() {
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+    println!("cargo:rerun-if-changed=src/render/shader_compiler.rs");
+
+    // Compiled at runtime instead
+    if env::var_os("CARGO_FEATURE_DEV_SHADERS").is_some() {
+        return;
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut table = String::from("&[\n");
+
+    for path in shader_compiler::ENTRY_SHADERS {
+        let words = match shader_compiler::compile(Path::new(SHADER_DIR), path) {
+            Ok(w) => w,
+            Err(e) => panic!("{}", e),
+        };
+
+        let words = words.iter().map(|w| format!("{:#010x}", w)).collect::<Vec<_>>();
+        table += &format!("    ({:?}, &[{}]),\n", path, words.join(", "));
+    }
+
+    table += "]\n";
+    fs::write(Path::new(&out_dir).join("embedded_shaders.rs"), table).unwrap();
+}
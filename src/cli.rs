@@ -0,0 +1,78 @@
+use std::{path::Path, time::Instant};
+
+use crate::{
+    render::texture::TextureAtlas,
+    server::commands::parse_chunk_pos,
+    world::{
+        block_data::StaticBlockData,
+        generation::{
+            config::{TerrainConfig, TERRAIN_CONFIG_PATH},
+            preview::{generate_region, write_images, RegionStats},
+            terrain::TerrainGenerator,
+        },
+        map::{generate_chunks, write_map, BlockColors, MAP_DIR},
+    },
+};
+
+/// Textures and blocks for the tools that run without a window
+fn load_blocks() -> (TextureAtlas, StaticBlockData) {
+    let atlas = TextureAtlas::from_folder("./resources");
+    let mut block_data = StaticBlockData::empty();
+    block_data.init(&atlas);
+    (atlas, block_data)
+}
+
+/// `--map <seed> <x1> <z1> <x2> <z2> [dir]` generates the chunks between two chunk corners with the
+/// game's terrain config and writes map tiles of them, to `MAP_DIR` without a directory
+pub fn map(args: &[String]) -> Result<String, String> {
+    if args.len() != 5 && args.len() != 6 {
+        return Err("usage: --map <seed> <x1> <z1> <x2> <z2> [dir]".to_string());
+    }
+    let seed = args[0].parse::<u32>().map_err(|_| format!("\"{}\" is not a seed", args[0]))?;
+    let corners = (parse_chunk_pos(&args[1..3])?, parse_chunk_pos(&args[3..5])?);
+    let dir = args.get(5).map_or(MAP_DIR, |dir| dir.as_str());
+
+    let (atlas, block_data) = load_blocks();
+    let config = TerrainConfig::load_or_default(TERRAIN_CONFIG_PATH);
+    let mut generator = TerrainGenerator::with_config(seed, config, &block_data);
+    let chunks = generate_chunks(&mut generator, corners, &block_data, &BlockColors::new(&block_data, &atlas));
+
+    let tiles = write_map(&chunks, dir).map_err(|e| format!("Could not write the map to {}: {}", dir, e))?;
+    Ok(format!("Wrote {} tiles of {} chunks to {}", tiles, chunks.len(), dir))
+}
+
+/// `--worldgen <seed> <x1> <z1> <x2> <z2> [--config <path>] [--images <dir>]` generates the chunks between
+/// two chunk corners in parallel and reports what came out, optionally with a heightmap and a cross section.
+/// Without `--config` it uses the game's terrain config.
+pub fn worldgen(args: &[String]) -> Result<String, String> {
+    const USAGE: &str = "usage: --worldgen <seed> <x1> <z1> <x2> <z2> [--config <path>] [--images <dir>]";
+    if args.len() < 5 {
+        return Err(USAGE.to_string());
+    }
+    let seed = args[0].parse::<u32>().map_err(|_| format!("\"{}\" is not a seed", args[0]))?;
+    let corners = (parse_chunk_pos(&args[1..3])?, parse_chunk_pos(&args[3..5])?);
+
+    let mut config = TerrainConfig::load_or_default(TERRAIN_CONFIG_PATH);
+    let mut images = None;
+    for option in args[5..].chunks(2) {
+        match option {
+            [flag, path] if flag == "--config" => {
+                config = TerrainConfig::load(path).map_err(|e| format!("Could not read the config {}: {}", path, e))?
+            },
+            [flag, dir] if flag == "--images" => images = Some(dir.as_str()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let (atlas, block_data) = load_blocks();
+    let start = Instant::now();
+    let chunks = generate_region(seed, config, corners, &block_data);
+    let mut report = RegionStats::new(&chunks, &block_data).report(start.elapsed());
+
+    if let Some(dir) = images {
+        write_images(&chunks, &BlockColors::new(&block_data, &atlas), Path::new(dir))
+            .map_err(|e| format!("Could not write the images to {}: {}", dir, e))?;
+        report += &format!("Wrote heightmap.png and cross_section.png to {}\n", dir);
+    }
+    Ok(report)
+}
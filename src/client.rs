@@ -0,0 +1,130 @@
+use std::{path::Path, sync::Mutex};
+
+use crate::{
+    render::{renderer::Renderer, util::DebugMode},
+    server::{commands::ClientCommand, server::Server},
+    settings::{Settings, SETTINGS_PATH},
+    world::{
+        block_data::StaticBlockData,
+        export::{export_region, EXPORT_DIR},
+        map::{loaded_chunks, write_map, BlockColors, MAP_DIR},
+        region::RegionBlocks,
+        schematic::{schematic_path, Schematic},
+        vox,
+        world_blocks::WorldBlocks,
+    },
+};
+
+const EMPTY_CLIPBOARD: &str = "The clipboard is empty, copy or load a schematic first";
+
+/// Runs a console command that needs the renderer, the loaded blocks or the clipboard, returns what to print.
+///
+/// Changed settings are applied to the renderer, the server and the world and saved to `SETTINGS_PATH`.
+pub fn execute_command(
+    command: ClientCommand,
+    settings: &mut Settings,
+    renderer: &mut Renderer,
+    server: &mut Server,
+    world_blocks: &Mutex<WorldBlocks>,
+    block_data: &StaticBlockData,
+    clipboard: &mut Option<Schematic>,
+) -> String {
+    match command {
+        ClientCommand::Settings => settings.describe(),
+        ClientCommand::Set(name, value) => match settings.set(&name, &value) {
+            Ok(()) => {
+                renderer.apply_settings(settings);
+                server.apply_settings(settings);
+                world_blocks.lock().unwrap().render_distance = settings.render_distance;
+
+                if let Err(e) = settings.save(SETTINGS_PATH) {
+                    println!("WARNING: Could not save the settings: {}", e);
+                }
+                if Settings::STARTUP_ONLY.contains(&name.as_str()) {
+                    format!("{} takes effect after a restart", name)
+                } else {
+                    format!("Set {} to {}", name, value)
+                }
+            },
+            Err(e) => e,
+        },
+        ClientCommand::Debug(None) => {
+            let names = DebugMode::ALL.map(DebugMode::name).join(", ");
+            let current = DebugMode::from_id(renderer.program_info.debug_mode);
+            format!("Debug view: {} (one of {})", current.name(), names)
+        },
+        ClientCommand::Debug(Some(name)) => match DebugMode::parse(&name) {
+            Some(mode) => {
+                renderer.program_info.debug_mode = mode.id();
+                format!("Debug view: {}", mode.name())
+            },
+            None => format!("Unknown debug view \"{}\"", name),
+        },
+        ClientCommand::Buffers => renderer.vertex_buffer.stats(),
+        ClientCommand::Memory => renderer.memory_report().to_string(),
+        ClientCommand::Export(format, corners, path) => {
+            let path = path.unwrap_or_else(|| format!("{}/export.{}", EXPORT_DIR, format.extension()));
+            let world = world_blocks.lock().unwrap();
+            match export_region(&world, block_data, &renderer.texture_atlas, corners, format, Path::new(&path)) {
+                Ok(triangles) => format!("Wrote {} triangles to {}", triangles, path),
+                Err(e) => format!("Could not export to {}: {}", path, e),
+            }
+        },
+        ClientCommand::VoxImport(path, origin, turns) => {
+            let mut world = world_blocks.lock().unwrap();
+            vox::convert::import_file(&path, origin, turns, &mut world, block_data)
+        },
+        ClientCommand::VoxExport(path, corners) => {
+            let world = world_blocks.lock().unwrap();
+            vox::convert::export_file(&path, corners, &world, block_data, &renderer.texture_atlas)
+        },
+        ClientCommand::Map(corners, dir) => {
+            let dir = dir.unwrap_or_else(|| MAP_DIR.to_string());
+            let colors = BlockColors::new(block_data, &renderer.texture_atlas);
+            let chunks = loaded_chunks(&world_blocks.lock().unwrap(), corners, block_data, &colors);
+            match write_map(&chunks, &dir) {
+                Ok(tiles) => format!("Wrote {} tiles of {} chunks to {}", tiles, chunks.len(), dir),
+                Err(e) => format!("Could not write the map to {}: {}", dir, e),
+            }
+        },
+        ClientCommand::SchematicCopy(corners) => {
+            let world = world_blocks.lock().unwrap();
+            match RegionBlocks::copy(&world, corners.0, corners.1) {
+                Ok(region) => {
+                    let schematic = Schematic::from_region(&region, block_data);
+                    let message = format!("Copied {:?} blocks with {} kinds", schematic.size, schematic.palette.len());
+                    *clipboard = Some(schematic);
+                    message
+                },
+                Err(e) => format!("Could not copy: {}", e),
+            }
+        },
+        ClientCommand::SchematicPaste(origin, options) => match clipboard {
+            Some(schematic) => {
+                let mut world = world_blocks.lock().unwrap();
+                let placed = schematic.paste(&mut world, origin, options, block_data);
+                format!("Placed {} blocks", placed)
+            },
+            None => EMPTY_CLIPBOARD.to_string(),
+        },
+        ClientCommand::SchematicSave(name) => {
+            let path = schematic_path(&name);
+            match clipboard.as_ref().map(|schematic| schematic.save(&path)) {
+                Some(Ok(())) => format!("Wrote the clipboard to {:?}", path),
+                Some(Err(e)) => format!("Could not write {:?}: {}", path, e),
+                None => EMPTY_CLIPBOARD.to_string(),
+            }
+        },
+        ClientCommand::SchematicLoad(name) => {
+            let path = schematic_path(&name);
+            match Schematic::load(&path) {
+                Ok(schematic) => {
+                    let message = format!("Loaded {:?} blocks from {:?}", schematic.size, path);
+                    *clipboard = Some(schematic);
+                    message
+                },
+                Err(e) => format!("Could not read {:?}: {}", path, e),
+            }
+        },
+    }
+}
@@ -11,6 +11,8 @@ use winit::{
 pub enum UserEvent {
     InputHandler(InputHandlerEvent),
     RedrawAt(Instant),
+    /// A line typed into the console
+    Command(String),
 }
 
 #[derive(Clone, Debug)]
@@ -21,6 +23,8 @@ pub enum InputHandlerEvent {
 
 pub struct InputHandler {
     pub key_press_map: HashMap<VirtualKeyCode, bool>,
+    /// Keys that went down since the last frame, cleared like `mouse_delta`
+    pub just_pressed: Vec<VirtualKeyCode>,
     pub mouse_delta: Vec2,
 }
 
@@ -35,6 +39,7 @@ impl InputHandler {
     pub fn new() -> Self {
         Self {
             key_press_map: HashMap::default(),
+            just_pressed: Vec::new(),
             mouse_delta: Vec2::zero(),
         }
     }
@@ -55,6 +60,11 @@ impl InputHandler {
                 state,
                 ..
             }) => {
+                // Held keys repeat their press events
+                if state == ElementState::Pressed && !self.is_pressed(key) {
+                    self.just_pressed.push(key);
+                }
+
                 self.key_press_map.insert(
                     key,
                     match state {
@@ -74,6 +84,10 @@ impl InputHandler {
         // }
     }
 
+    pub fn was_just_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.just_pressed.contains(&key)
+    }
+
     pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
         match self.key_press_map.get(&key) {
             Some(state) => *state,
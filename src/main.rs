@@ -1,10 +1,9 @@
-#![feature(slice_as_chunks)]
-#![feature(slice_flatten)]
 #![feature(portable_simd)]
-#![feature(associated_const_equality)]
 #![feature(fn_traits)]
+#![allow(clippy::module_inception, clippy::new_without_default, clippy::too_many_arguments, clippy::type_complexity)]
 
 use std::{
+    io::{self, BufRead},
     sync::{Arc, Mutex},
     thread,
     time::{Duration, Instant},
@@ -13,34 +12,70 @@ use std::{
 use event_handler::{InputHandler, UserEvent};
 use mimalloc::MiMalloc;
 use render::{
+    camera::camera::Camera,
+    device,
     fps_log::FpsLog,
     renderer::Renderer,
-    util::{GetWindow, RenderState},
+    sky::Sky,
+    util::{DebugMode, GetWindow, RenderState},
 };
 
 use crate::util::util::AdditionalSwizzles;
-use server::server::Server;
-use ultraviolet::Vec2;
+use server::{commands::ConsoleCommand, server::Server};
+use settings::{Settings, SETTINGS_PATH};
+use ultraviolet::{Vec2, Vec3};
 use winit::{
-    event::{Event, WindowEvent},
+    event::{Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopBuilder},
 };
-use world::{block_data::StaticBlockData, world_blocks::WorldBlocks};
+use world::{
+    block_data::StaticBlockData,
+    generation::config::{TerrainConfig, TERRAIN_CONFIG_PATH},
+    schematic::Schematic,
+    world_blocks::WorldBlocks,
+};
 
+pub mod cli;
+pub mod client;
 pub mod event_handler;
 pub mod physics;
+pub mod profiler;
 pub mod render;
 pub mod server;
+pub mod settings;
 pub mod util;
 pub mod world;
 
-pub const FRAME_TIME: f64 = 0.0;
+/// Face ids as the shaders number them
+const FACE_NAMES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
 
 #[global_allocator]
 pub static GLOBAL: MiMalloc = MiMalloc;
 
 fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
+
+    if std::env::args().any(|arg| arg == "--list-devices") {
+        match device::device_report() {
+            Ok(report) => print!("{}", report),
+            Err(e) => println!("ERROR: {}", e),
+        }
+        return;
+    }
+
+    let args = std::env::args().collect::<Vec<_>>();
+    // Tools that run without a window or GPU
+    let tools: [(&str, fn(&[String]) -> Result<String, String>); 2] = [("--map", cli::map), ("--worldgen", cli::worldgen)];
+    for (flag, tool) in tools {
+        if let Some(i) = args.iter().position(|arg| arg == flag) {
+            match tool(&args[i + 1..]) {
+                Ok(report) => println!("{}", report.trim_end()),
+                Err(e) => println!("ERROR: {}", e),
+            }
+            return;
+        }
+    }
+
     let event_loop: EventLoop<UserEvent> = EventLoopBuilder::with_user_event().build();
     let mut proxy = event_loop.create_proxy();
 
@@ -50,8 +85,13 @@ fn main() {
 
     let static_block_data = Arc::new(static_block_data);
 
-    let mut renderer = Renderer::new(&event_loop, texture_atlas, &static_block_data);
-    let world_blocks = Arc::new(Mutex::new(WorldBlocks::new(&static_block_data)));
+    let mut settings = Settings::load(SETTINGS_PATH);
+
+    let mut renderer = Renderer::new(&event_loop, texture_atlas, &static_block_data, &settings);
+    let terrain_config = TerrainConfig::load_or_default(TERRAIN_CONFIG_PATH);
+    let mut world_blocks = WorldBlocks::new(terrain_config, &static_block_data);
+    world_blocks.render_distance = settings.render_distance;
+    let world_blocks = Arc::new(Mutex::new(world_blocks));
 
     thread::spawn({
         let world_blocks = world_blocks.clone();
@@ -67,6 +107,20 @@ fn main() {
 
     let mut server = Server::new();
     server.init_single_player();
+    server.apply_settings(&settings);
+
+    // Console for admin commands
+    thread::spawn({
+        let proxy = event_loop.create_proxy();
+        move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if proxy.send_event(UserEvent::Command(line)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
 
     let mut input_handler = InputHandler::new();
     let mut fps_log = FpsLog::new();
@@ -74,12 +128,15 @@ fn main() {
     let mut window_resized = false;
     let mut recreate_swapchain = false;
     let mut last_frame_start = Instant::now();
+    // Filled by the schematic copy and load commands
+    let mut clipboard: Option<Schematic> = None;
 
     event_loop.run(move |event, _, control_flow| {
         let world_blocks = world_blocks.clone();
 
         match event {
             Event::RedrawRequested(_) => {
+                profiler::begin_frame();
                 let delta_time = fps_log.update();
                 if window_resized || recreate_swapchain {
                     recreate_swapchain = false;
@@ -91,6 +148,12 @@ fn main() {
                     }
                 }
 
+                match renderer.reload_changed_shaders() {
+                    Some(Ok(())) => println!("Reloaded shaders"),
+                    Some(Err(e)) => println!("ERROR: Keeping the previous shaders\n{}", e),
+                    None => (),
+                }
+
                 let camera = server.camera();
 
                 let mut world_blocks_lock = world_blocks.lock().unwrap();
@@ -105,8 +168,33 @@ fn main() {
 
                 let camera = server.camera();
                 renderer.cam_uniform = Some(camera.calculate_matrix());
+                renderer.sky = Some(Sky::from_time_of_day(server.time.time_of_day()));
+
+                if input_handler.was_just_pressed(VirtualKeyCode::F4) {
+                    let mode = DebugMode::from_id(renderer.program_info.debug_mode).next();
+                    renderer.program_info.debug_mode = mode.id();
+                    println!("Debug view: {}", mode.name());
+                }
+
+                renderer.hud.push_line(format!("{:.0} FPS ({:.2} ms)", fps_log.fps, fps_log.frame_time * 1000.0));
+                renderer.hud.push_line(format!("XYZ: {:.1} {:.1} {:.1}", camera.pos.x, camera.pos.y, camera.pos.z));
+                renderer.hud.push_line(facing_line(&camera));
+
+                let debug_mode = DebugMode::from_id(renderer.program_info.debug_mode);
+                if debug_mode != DebugMode::None {
+                    renderer.hud.push_line(format!("Debug view: {}", debug_mode.name()));
+                    if let Some((pos, face)) = renderer.debug_target {
+                        let block = world_blocks.lock().unwrap().get_block(pos);
+                        let name = block.map_or("unloaded", |b| static_block_data.get(&b).id.as_str());
+                        renderer.hud.push_line(format!(
+                            "Target: {} at {} {} {}, {} face",
+                            name, pos.x, pos.y, pos.z, FACE_NAMES[face],
+                        ));
+                    }
+                }
 
                 input_handler.mouse_delta = Vec2::zero();
+                input_handler.just_pressed.clear();
 
                 match renderer.render(world_blocks.clone(), &static_block_data) {
                     RenderState::OutOfDate | RenderState::Suboptimal => recreate_swapchain = true,
@@ -115,7 +203,7 @@ fn main() {
             }
 
             Event::RedrawEventsCleared => {
-                let next_render = last_frame_start + Duration::from_secs_f64(FRAME_TIME);
+                let next_render = last_frame_start + Duration::from_secs_f64(settings.frame_time);
                 proxy.send_event(UserEvent::RedrawAt(next_render)).unwrap();
             }
 
@@ -133,6 +221,24 @@ fn main() {
                             proxy.send_event(UserEvent::RedrawAt(instant)).unwrap();
                         }
                     },
+                    UserEvent::Command(line) => {
+                        match ConsoleCommand::parse(&line) {
+                            Ok(ConsoleCommand::Client(command)) => println!(
+                                "{}",
+                                client::execute_command(
+                                    command,
+                                    &mut settings,
+                                    &mut renderer,
+                                    &mut server,
+                                    &world_blocks,
+                                    &static_block_data,
+                                    &mut clipboard,
+                                ),
+                            ),
+                            Ok(ConsoleCommand::Server(command)) => println!("{}", server.execute_command(command)),
+                            Err(e) => println!("{}", e),
+                        }
+                    },
                     _ => ()
                 }
             }
@@ -144,12 +250,37 @@ fn main() {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => {
+                server.save();
+                if let Err(e) = renderer.pipeline_cache.save() {
+                    println!("WARNING: Could not save the pipeline cache: {}", e);
+                }
+                *control_flow = ControlFlow::Exit;
+            },
             _ => (),
         }
     });
 }
 
+/// The horizontal axis the camera looks along the most, with its yaw and pitch
+fn facing_line(camera: &Camera) -> String {
+    let forward = camera.calculate_matrix().transform_vec3(Vec3::unit_z());
+    let axis = if forward.x.abs() > forward.z.abs() {
+        if forward.x > 0.0 { "+X" } else { "-X" }
+    } else if forward.z > 0.0 {
+        "+Z"
+    } else {
+        "-Z"
+    };
+
+    format!(
+        "Facing {} (yaw {:.1}, pitch {:.1})",
+        axis,
+        camera.rotation.yaw.to_degrees().rem_euclid(360.0),
+        camera.rotation.pitch.to_degrees(),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use std::{hint::black_box, println, time::Instant};
@@ -0,0 +1,336 @@
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::Path,
+    sync::Mutex,
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+/// Frames kept for the summary and the trace
+const HISTORY_FRAMES: usize = 600;
+
+pub const TRACE_PATH: &str = "./profile.json";
+
+static PROFILER: Mutex<Profiler> = Mutex::new(Profiler::new());
+
+#[derive(Clone, Debug)]
+struct CpuEvent {
+    name: &'static str,
+    thread: ThreadId,
+    /// Since the profiler's epoch
+    start: Duration,
+    duration: Duration,
+}
+
+/// GPU clocks can't be compared to the CPU's, so these are placed relative to the frame start.
+#[derive(Clone, Debug)]
+struct GpuEvent {
+    name: &'static str,
+    /// Since the first timestamp of the frame
+    start: Duration,
+    duration: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+struct FrameTimeline {
+    index: u64,
+    start: Duration,
+    duration: Duration,
+    cpu: Vec<CpuEvent>,
+    gpu: Vec<GpuEvent>,
+}
+
+/// Time spent in one scope per frame, over the kept history
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeStats {
+    pub name: String,
+    /// Frames the scope ran in
+    pub frames: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Collects named CPU scopes from any thread and GPU timings into per-frame timelines.
+pub struct Profiler {
+    epoch: Option<Instant>,
+    current: Option<FrameTimeline>,
+    frames: VecDeque<FrameTimeline>,
+}
+
+impl Profiler {
+    pub const fn new() -> Self {
+        Self {
+            epoch: None,
+            current: None,
+            frames: VecDeque::new(),
+        }
+    }
+
+    fn now(&mut self) -> Duration {
+        self.epoch.get_or_insert_with(Instant::now).elapsed()
+    }
+
+    /// Closes the current frame and starts the next one
+    pub fn begin_frame(&mut self) -> u64 {
+        let now = self.now();
+        let index = match self.current.take() {
+            Some(mut frame) => {
+                frame.duration = now - frame.start;
+                let index = frame.index + 1;
+                self.frames.push_back(frame);
+                if self.frames.len() > HISTORY_FRAMES {
+                    self.frames.pop_front();
+                }
+                index
+            }
+            None => 0,
+        };
+
+        self.current = Some(FrameTimeline {
+            index,
+            start: now,
+            ..Default::default()
+        });
+        index
+    }
+
+    fn record_cpu(&mut self, name: &'static str, start: Instant, duration: Duration) {
+        let epoch = *self.epoch.get_or_insert(start);
+        let event = CpuEvent {
+            name,
+            thread: thread::current().id(),
+            start: start.saturating_duration_since(epoch),
+            duration,
+        };
+
+        if let Some(frame) = &mut self.current {
+            frame.cpu.push(event);
+        }
+    }
+
+    /// GPU results arrive a few frames late, they are added to the frame that recorded them
+    pub fn record_gpu(&mut self, frame_index: u64, name: &'static str, start: Duration, duration: Duration) {
+        let event = GpuEvent { name, start, duration };
+        let frame = self
+            .current
+            .iter_mut()
+            .chain(self.frames.iter_mut().rev())
+            .find(|f| f.index == frame_index);
+
+        if let Some(frame) = frame {
+            frame.gpu.push(event);
+        }
+    }
+
+    pub fn current_frame(&self) -> u64 {
+        self.current.as_ref().map_or(0, |f| f.index)
+    }
+
+    /// Per-frame totals of every scope, the whole frame is called `frame`
+    pub fn summary(&self) -> Vec<ScopeStats> {
+        let mut totals: Vec<(String, Vec<Duration>)> = Vec::new();
+
+        for frame in self.frames.iter() {
+            let mut frame_totals: Vec<(String, Duration)> = vec![("frame".to_string(), frame.duration)];
+            let events = frame
+                .cpu
+                .iter()
+                .map(|e| (e.name.to_string(), e.duration))
+                .chain(frame.gpu.iter().map(|e| (format!("gpu: {}", e.name), e.duration)));
+
+            for (name, duration) in events {
+                match frame_totals.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, total)) => *total += duration,
+                    None => frame_totals.push((name, duration)),
+                }
+            }
+
+            for (name, total) in frame_totals {
+                match totals.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, values)) => values.push(total),
+                    None => totals.push((name, vec![total])),
+                }
+            }
+        }
+
+        totals
+            .into_iter()
+            .map(|(name, mut values)| {
+                values.sort();
+                ScopeStats {
+                    name,
+                    frames: values.len(),
+                    p50: percentile(&values, 50.0),
+                    p95: percentile(&values, 95.0),
+                    p99: percentile(&values, 99.0),
+                    max: *values.last().unwrap(),
+                }
+            })
+            .collect()
+    }
+
+    /// Chrome's trace event format, viewable in `chrome://tracing` or Perfetto.
+    ///
+    /// GPU events go on their own track, starting at the CPU start of their frame.
+    pub fn chrome_trace(&self) -> Value {
+        const GPU_TRACK: usize = 0;
+        let mut threads: Vec<ThreadId> = Vec::new();
+        let mut events = Vec::new();
+        let micros = |d: Duration| d.as_secs_f64() * 1_000_000.0;
+
+        for frame in self.frames.iter() {
+            events.push(json!({
+                "name": format!("frame {}", frame.index),
+                "cat": "frame",
+                "ph": "X",
+                "ts": micros(frame.start),
+                "dur": micros(frame.duration),
+                "pid": 0,
+                "tid": GPU_TRACK,
+            }));
+
+            for e in frame.cpu.iter() {
+                let tid = match threads.iter().position(|t| *t == e.thread) {
+                    Some(i) => i + 1,
+                    None => {
+                        threads.push(e.thread);
+                        threads.len()
+                    }
+                };
+
+                events.push(json!({
+                    "name": e.name,
+                    "cat": "cpu",
+                    "ph": "X",
+                    "ts": micros(e.start),
+                    "dur": micros(e.duration),
+                    "pid": 0,
+                    "tid": tid,
+                }));
+            }
+
+            for e in frame.gpu.iter() {
+                events.push(json!({
+                    "name": e.name,
+                    "cat": "gpu",
+                    "ph": "X",
+                    "ts": micros(frame.start + e.start),
+                    "dur": micros(e.duration),
+                    "pid": 0,
+                    "tid": GPU_TRACK,
+                }));
+            }
+        }
+
+        let mut thread_names = vec![json!({
+            "name": "thread_name", "ph": "M", "pid": 0, "tid": GPU_TRACK, "args": { "name": "frames and GPU" },
+        })];
+        for (i, thread) in threads.iter().enumerate() {
+            thread_names.push(json!({
+                "name": "thread_name", "ph": "M", "pid": 0, "tid": i + 1, "args": { "name": format!("{:?}", thread) },
+            }));
+        }
+        thread_names.extend(events);
+
+        json!({ "traceEvents": thread_names })
+    }
+}
+
+/// Nearest rank of sorted values
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Records the time until it is dropped
+pub struct ScopeGuard {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        let duration = self.start.elapsed();
+        PROFILER.lock().unwrap().record_cpu(self.name, self.start, duration);
+    }
+}
+
+/// Times the rest of the enclosing block, `let _scope = profiler::scope("name");`
+pub fn scope(name: &'static str) -> ScopeGuard {
+    ScopeGuard {
+        name,
+        start: Instant::now(),
+    }
+}
+
+/// Returns the index of the new frame
+pub fn begin_frame() -> u64 {
+    PROFILER.lock().unwrap().begin_frame()
+}
+
+pub fn record_gpu(frame_index: u64, name: &'static str, start: Duration, duration: Duration) {
+    PROFILER.lock().unwrap().record_gpu(frame_index, name, start, duration);
+}
+
+pub fn current_frame() -> u64 {
+    PROFILER.lock().unwrap().current_frame()
+}
+
+pub fn summary() -> String {
+    let stats = PROFILER.lock().unwrap().summary();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+    let mut ret = format!("{:<24} {:>7} {:>9} {:>9} {:>9} {:>9}", "scope", "frames", "p50 ms", "p95 ms", "p99 ms", "max ms");
+    for s in stats {
+        ret += &format!(
+            "\n{:<24} {:>7} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+            s.name, s.frames, ms(s.p50), ms(s.p95), ms(s.p99), ms(s.max),
+        );
+    }
+    ret
+}
+
+pub fn export_chrome_trace(path: impl AsRef<Path>) -> io::Result<()> {
+    let trace = PROFILER.lock().unwrap().chrome_trace();
+    fs::write(path, serde_json::to_string(&trace)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let values = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&values, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&values, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&values[..1], 95.0), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn scopes_are_summed_per_frame() {
+        let mut profiler = Profiler::new();
+        for _ in 0..3 {
+            profiler.begin_frame();
+            let start = Instant::now();
+            profiler.record_cpu("tick", start, Duration::from_millis(1));
+            profiler.record_cpu("tick", start, Duration::from_millis(2));
+        }
+        profiler.record_gpu(1, "pass", Duration::ZERO, Duration::from_millis(4));
+        profiler.begin_frame();
+
+        let summary = profiler.summary();
+        let tick = summary.iter().find(|s| s.name == "tick").unwrap();
+        assert_eq!(tick.frames, 3);
+        assert_eq!(tick.max, Duration::from_millis(3));
+
+        let pass = summary.iter().find(|s| s.name == "gpu: pass").unwrap();
+        assert_eq!(pass.frames, 1);
+        assert_eq!(profiler.chrome_trace()["traceEvents"].as_array().unwrap().len(), 2 + 3 * 3 + 1);
+    }
+}
@@ -1,51 +1,278 @@
-use std::collections::{VecDeque, hash_map::Entry};
+use std::collections::VecDeque;
 
 use ahash::{HashMap, HashMapExt};
-use ultraviolet::{UVec3, IVec3};
-use vulkano::{buffer::{subbuffer::BufferWriteGuard, Subbuffer, Buffer, BufferCreateInfo, BufferContents, BufferUsage}, memory::allocator::{StandardMemoryAllocator, AllocationCreateInfo, MemoryUsage}};
+use ultraviolet::{IVec2, IVec3, Vec3};
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator},
+};
 
-use crate::{render::{brick::brickgrid::morton_encode, util::CreateInfoConvenience, buffer::allocator::{ChunkBufferAllocator, ChunkBufferAllocation}}, util::util::{VecRounding, UVecTrunc}};
+use crate::{
+    render::{
+        brick::brickgrid::{morton_encode, BRICKGRID_SIZE},
+        buffer::{
+            allocator::{ChunkBufferAllocation, ChunkBufferAllocator},
+            queued_writes::QueuedWrites,
+            staging_ring::StagingRing,
+        },
+        memory::{MemoryReport, Subsystem},
+        util::CreateInfoConvenience,
+    },
+    util::util::{IVecTrunc, VecModPos, VecRounding},
+    world::{chunk::CHUNK_HEIGHT, section::F_SECTION_SIZE},
+};
 
-use super::{grid::SurfelGrid, surfel::{SurfelRaw, Surfel}};
+use super::{
+    grid::{SurfelMapSlice, MAX_SLICE_START, SG_ARRAY_SIZE},
+    surfel::{Surfel, SurfelRaw},
+};
 
-const MAX_SURFELS: usize = 0b1111_1111_1111_1111_11 + 1;
+pub const MAX_SURFELS: usize = 1 << 18;
+/// Once this many surfels are alive the oldest ones are recycled for new ones
+pub const SURFEL_BUDGET: usize = 65_536;
+const SURFEL_MAP_SIZE: usize = MAX_SLICE_START as usize + 1;
+/// Keeps the amount of surfels the shader has to check per cell bounded
+const MAX_SURFELS_PER_SECTION: usize = 64;
+/// Surfels further away from the camera than this are recycled
+const SURFEL_DISTANCE: f32 = 160.0;
+/// A new surfel is not spawned if one with a similar normal is closer than `radius * SURFEL_COVERAGE`
+const SURFEL_COVERAGE: f32 = 0.5;
+const MAX_SPAWNS_PER_FRAME: usize = 64;
 
+/// All buffers needed to find and shade surfels on the GPU.
+///
+/// Everything is device local, changes are queued and uploaded with `record_uploads`.
 pub struct SurfelBuffers {
     pub surfel_buffer: SurfelBuffer,
-    pub surfel_grid_buffer: Subbuffer<SurfelGrid>,
-    pub surfel_map_buffer: Subbuffer<SurfelMap>,
+    pub surfel_mapper: SurfelMapper,
+    pub surfel_grid_buffer: Subbuffer<[u32]>,
+    grid_writes: QueuedWrites<u32>,
+
+    /// Live surfels in the order they were spawned, paired with their generation
+    spawn_order: VecDeque<(u32, u32)>,
+    next_generation: u32,
 }
 
 impl SurfelBuffers {
-    pub fn new(allocator: &StandardMemoryAllocator) -> Self {
-        fn create_buffer<T: BufferContents>(allocator: &StandardMemoryAllocator) -> Subbuffer<T> {
-            Buffer::new_sized(
-                allocator, 
-                BufferCreateInfo::usage(BufferUsage::STORAGE_BUFFER), 
-                AllocationCreateInfo::usage(MemoryUsage::Upload),
-            ).unwrap()
-        }
+    pub fn new(
+        allocator: &StandardMemoryAllocator,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let surfel_grid_buffer = create_device_slice(allocator, SG_ARRAY_SIZE);
+        // Every cell starts out without surfels
+        cbb.fill_buffer(surfel_grid_buffer.clone(), 0).unwrap();
 
         Self {
             surfel_buffer: SurfelBuffer::new(allocator),
-            surfel_grid_buffer: create_buffer(allocator),
-            surfel_map_buffer: create_buffer(allocator)
+            surfel_mapper: SurfelMapper::new(allocator),
+            surfel_grid_buffer,
+            grid_writes: QueuedWrites::new(),
+
+            spawn_order: VecDeque::new(),
+            next_generation: 0,
+        }
+    }
+
+    /// Inserts a surfel and registers it in every section its radius reaches.
+    ///
+    /// Recycles the oldest surfel when over budget.
+    pub fn insert_surfel(&mut self, surfel: Surfel) -> Option<u32> {
+        if self.surfel_buffer.live_count() >= SURFEL_BUDGET {
+            self.recycle_oldest();
+        }
+
+        let generation = self.next_generation;
+        self.next_generation = self.next_generation.wrapping_add(1);
+
+        let sections = overlapped_sections(&surfel);
+        let surfel_index = self.surfel_buffer.insert(SurfelEntry {
+            surfel,
+            sections: Vec::new(),
+            generation,
+        })?;
+
+        let mut registered = Vec::with_capacity(sections.len());
+        for section_pos in sections {
+            if let Some(slice) = self.surfel_mapper.add_surfel_to_section(surfel_index, section_pos) {
+                self.write_grid(section_pos, slice);
+                registered.push(section_pos);
+            }
+        }
+
+        self.surfel_buffer.entries[surfel_index as usize]
+            .as_mut()
+            .unwrap()
+            .sections = registered;
+        self.spawn_order.push_back((surfel_index, generation));
+
+        Some(surfel_index)
+    }
+
+    pub fn remove_surfel(&mut self, surfel_index: u32) {
+        let Some(entry) = self.surfel_buffer.remove(surfel_index) else {
+            return;
+        };
+
+        for section_pos in entry.sections {
+            let slice = self.surfel_mapper.remove_surfel_from_section(surfel_index, section_pos);
+            self.write_grid(section_pos, slice);
+        }
+    }
+
+    /// Spawns surfels on surfaces the shader found uncovered.
+    ///
+    /// Requests next to an existing surfel are dropped, many pixels report the same spot.
+    pub fn spawn_requested(&mut self, requests: &[(Vec3, usize)]) {
+        for (world_pos, face_id) in requests.iter().take(MAX_SPAWNS_PER_FRAME) {
+            let surfel = Surfel::on_face(*world_pos, *face_id);
+            if !self.is_covered(&surfel) {
+                self.insert_surfel(surfel);
+            }
+        }
+    }
+
+    pub fn report_memory(&self, report: &mut MemoryReport) {
+        report.add_buffer(Subsystem::Surfels, &self.surfel_buffer.inner);
+        report.add_buffer(Subsystem::Surfels, &self.surfel_mapper.buffer);
+        report.add_buffer(Subsystem::Surfels, &self.surfel_grid_buffer);
+    }
+
+    /// Removes the surfels of every section in a chunk, the geometry they were sitting on has changed.
+    pub fn clear_chunk(&mut self, chunk_pos: IVec2) {
+        let mut indices = Vec::new();
+        for y in 0..CHUNK_HEIGHT as i32 {
+            let section_pos = IVec3::new(chunk_pos.x, y, chunk_pos.y);
+            if let Some(list) = self.surfel_mapper.sections.get(&section_pos) {
+                indices.extend_from_slice(list);
+            }
+        }
+
+        for surfel_index in indices {
+            self.remove_surfel(surfel_index);
+        }
+    }
+
+    /// Removes every surfel too far away from `camera_pos` to matter.
+    pub fn recycle_far(&mut self, camera_pos: Vec3) {
+        let far = self
+            .surfel_buffer
+            .iter()
+            .filter(|(_, entry)| (entry.surfel.world_pos - camera_pos).mag() > SURFEL_DISTANCE)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        for surfel_index in far {
+            self.remove_surfel(surfel_index);
+        }
+
+        // Drop stale entries, only recycling pops them otherwise
+        let surfel_buffer = &self.surfel_buffer;
+        self.spawn_order.retain(|(surfel_index, generation)| {
+            surfel_buffer
+                .get(*surfel_index)
+                .is_some_and(|entry| entry.generation == *generation)
+        });
+    }
+
+    /// Records the copies of every change since the last call.
+    pub fn record_uploads(
+        &mut self,
+        allocator: &StandardMemoryAllocator,
+        ring: &mut StagingRing,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        self.surfel_buffer
+            .writes
+            .record(allocator, ring, cbb, self.surfel_buffer.inner.clone());
+        self.surfel_mapper
+            .writes
+            .record(allocator, ring, cbb, self.surfel_mapper.buffer.clone());
+        self.grid_writes
+            .record(allocator, ring, cbb, self.surfel_grid_buffer.clone());
+    }
+
+    fn is_covered(&self, surfel: &Surfel) -> bool {
+        let section_pos = surfel.section_pos().into_i();
+        let Some(list) = self.surfel_mapper.sections.get(&section_pos) else {
+            return false;
+        };
+
+        list.iter()
+            .filter_map(|i| self.surfel_buffer.get(*i))
+            .any(|entry| {
+                let other = &entry.surfel;
+                other.normal.dot(surfel.normal) > 0.9
+                    && (other.world_pos - surfel.world_pos).mag() < other.radius * SURFEL_COVERAGE
+            })
+    }
+
+    fn recycle_oldest(&mut self) {
+        while let Some((surfel_index, generation)) = self.spawn_order.pop_front() {
+            // Entries of surfels that were already removed are stale
+            let live = self
+                .surfel_buffer
+                .get(surfel_index)
+                .is_some_and(|entry| entry.generation == generation);
+
+            if live {
+                self.remove_surfel(surfel_index);
+                return;
+            }
         }
     }
 
-    pub fn insert_surfel(&mut self, surfel: Surfel) {
-        let surfel_index = self.surfel_buffer.insert(surfel.to_raw());
+    fn write_grid(&mut self, section_pos: IVec3, slice: SurfelMapSlice) {
+        let pos = section_pos.mod_pos(BRICKGRID_SIZE.into());
+        self.grid_writes
+            .write(morton_encode(pos.x, pos.y, pos.z) as u32, slice.to_raw());
+    }
+}
+
+/// Sections a surfel's radius reaches into
+fn overlapped_sections(surfel: &Surfel) -> Vec<IVec3> {
+    let radius = Vec3::one() * surfel.radius;
+    let min = ((surfel.world_pos - radius) / F_SECTION_SIZE).floor().into_i();
+    let max = ((surfel.world_pos + radius) / F_SECTION_SIZE).floor().into_i();
 
-        const RECIP_16: f32 = 1.0 / 16.0;
-        let section_pos = (surfel.world_pos * RECIP_16).floor().into_u();
-        
+    let mut ret = Vec::new();
+    for x in min.x..=max.x {
+        for y in min.y.max(0)..=max.y.min(CHUNK_HEIGHT as i32 - 1) {
+            for z in min.z..=max.z {
+                ret.push(IVec3::new(x, y, z));
+            }
+        }
     }
+    ret
+}
+
+fn create_device_slice<T>(allocator: &StandardMemoryAllocator, len: usize) -> Subbuffer<[T]>
+where
+    T: BufferContents,
+{
+    Buffer::new_slice(
+        allocator,
+        BufferCreateInfo::usage(BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST),
+        AllocationCreateInfo::usage(MemoryUsage::DeviceOnly),
+        len as u64,
+    )
+    .unwrap()
+}
+
+/// CPU copy of a surfel
+pub struct SurfelEntry {
+    pub surfel: Surfel,
+    /// Sections this surfel is registered in
+    pub sections: Vec<IVec3>,
+    generation: u32,
 }
 
 pub struct SurfelBuffer {
     free: VecDeque<usize>,
     top: usize,
-    inner: Subbuffer<SurfelContainer>
+    entries: Vec<Option<SurfelEntry>>,
+    writes: QueuedWrites<SurfelRaw>,
+    pub inner: Subbuffer<[SurfelRaw]>,
 }
 
 impl SurfelBuffer {
@@ -53,119 +280,151 @@ impl SurfelBuffer {
         Self {
             free: VecDeque::new(),
             top: 0,
-            inner: Buffer::new_sized(
-                allocator, 
-                BufferCreateInfo::usage(BufferUsage::STORAGE_BUFFER), 
-                AllocationCreateInfo::usage(MemoryUsage::Upload),
-            ).unwrap()
+            entries: Vec::new(),
+            writes: QueuedWrites::new(),
+            inner: create_device_slice(allocator, MAX_SURFELS),
         }
     }
 
-    pub fn insert(&mut self, surfel: SurfelRaw) -> u32 {
+    /// Returns `None` if the buffer is full.
+    pub fn insert(&mut self, entry: SurfelEntry) -> Option<u32> {
         let idx = match self.free.pop_front() {
             Some(idx) => idx,
-            None => {
+            None if self.top < MAX_SURFELS => {
                 self.top += 1;
+                self.entries.push(None);
                 self.top - 1
             },
+            None => return None,
         };
 
-        let mut write = self
-            .inner
-            .write()
-            .expect("Insert should only be called when free.");
-        write.surfels[idx] = surfel;
+        self.writes.write(idx as u32, entry.surfel.to_raw());
+        self.entries[idx] = Some(entry);
 
-        idx as u32
+        Some(idx as u32)
     }
 
-    pub fn remove(&mut self, index: u32) {
-        let mut write = self
-            .inner
-            .write()
-            .expect("Remove should only be called when free.");
-        write.surfels[index as usize] = SurfelRaw::default();
+    pub fn remove(&mut self, index: u32) -> Option<SurfelEntry> {
+        let entry = self.entries.get_mut(index as usize)?.take()?;
+        // A NaN radius marks the slot as dead for the shaders
+        self.writes.write(index, SurfelRaw::default());
+        self.free.push_back(index as usize);
+
+        Some(entry)
+    }
+
+    pub fn get(&self, index: u32) -> Option<&SurfelEntry> {
+        self.entries.get(index as usize)?.as_ref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &SurfelEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| Some((i as u32, entry.as_ref()?)))
+    }
+
+    /// Every slot below this has been used at some point
+    pub fn top(&self) -> usize {
+        self.top
     }
-}
 
-#[repr(C)]
-#[derive(BufferContents)]
-pub struct SurfelContainer {
-    pub surfel_count: u32,
-    pub surfels: [SurfelRaw; MAX_SURFELS]
+    pub fn live_count(&self) -> usize {
+        self.top - self.free.len()
+    }
 }
 
+/// Keeps the indices of the surfels in each section contiguous in the surfel map.
 pub struct SurfelMapper {
-    buffer: Subbuffer<SurfelMap>,
+    pub buffer: Subbuffer<[u32]>,
     allocator: ChunkBufferAllocator,
     pub allocations: HashMap<IVec3, ChunkBufferAllocation>,
-    pub highest: usize,
+    pub sections: HashMap<IVec3, Vec<u32>>,
+    writes: QueuedWrites<u32>,
 }
 
 impl SurfelMapper {
     pub fn new(allocator: &StandardMemoryAllocator) -> Self {
         Self {
-            buffer: Buffer::new_sized(
-                allocator, 
-                BufferCreateInfo::usage(BufferUsage::STORAGE_BUFFER), 
-                AllocationCreateInfo::usage(MemoryUsage::Upload),
-            ).unwrap(),
+            buffer: create_device_slice(allocator, SURFEL_MAP_SIZE),
             allocator: ChunkBufferAllocator::new(),
             allocations: HashMap::new(),
-            highest: 0,
+            sections: HashMap::new(),
+            writes: QueuedWrites::new(),
         }
     }
 
-    pub fn add_surfel_to_section(&mut self, surfel_index: u32, section_pos: IVec3) {
-        let mut write = self.buffer.write().expect("Add should only be called when free.");
-        match self.allocations.entry(section_pos) {
-            Entry::Occupied(mut entry) => {
-                let alloc = *entry.get();
-                self.allocator.deallocate(&alloc);
-                let new_alloc = self.allocator.allocate((alloc.back - alloc.front) + 1);
-                entry.insert(new_alloc);
+    /// Returns the new slice of the section, `None` if the section or the surfel map is full.
+    pub fn add_surfel_to_section(&mut self, surfel_index: u32, section_pos: IVec3) -> Option<SurfelMapSlice> {
+        let list = self.sections.entry(section_pos).or_default();
+        if list.len() >= MAX_SURFELS_PER_SECTION {
+            return None;
+        }
+        list.push(surfel_index);
 
-                let (old_front, old_back) = (alloc.front as usize, alloc.back as usize);
-                let (new_front, new_back) = (new_alloc.front as usize, new_alloc.back as usize);
+        let slice = self.reallocate(section_pos);
+        if slice.is_none() {
+            println!("WARNING: Surfel map is full, section {:?} gets no more surfels", section_pos);
+            let list = self.sections.get_mut(&section_pos).unwrap();
+            list.pop();
+            if list.is_empty() {
+                self.sections.remove(&section_pos);
+            }
+        }
+        slice
+    }
 
-                write.pointers.copy_within(old_front..old_back, new_front);
-                write.pointers[new_back - 1] = surfel_index;
-            },
-            Entry::Vacant(entry) => {
-                let new_alloc = self.allocator.allocate(1);
-                entry.insert(new_alloc);
-                write.pointers[new_alloc.front as usize] = surfel_index;
-            },
+    /// Returns the new slice of the section.
+    pub fn remove_surfel_from_section(&mut self, surfel_index: u32, section_pos: IVec3) -> SurfelMapSlice {
+        if let Some(list) = self.sections.get_mut(&section_pos) {
+            list.retain(|i| *i != surfel_index);
         }
+
+        self.reallocate(section_pos)
+            .expect("a shorter list fits where the old one was")
     }
-}
 
-#[repr(C)]
-#[derive(BufferContents)]
-pub struct SurfelMap {
-    // indices for the surfel container, contiguous for each section
-    pub pointers: [u32; MAX_SURFELS]
-}
+    /// Moves the indices of a section to a new allocation of the right size.
+    ///
+    /// Returns `None` and keeps the old allocation if the surfel map has no room for a longer list.
+    fn reallocate(&mut self, section_pos: IVec3) -> Option<SurfelMapSlice> {
+        let len = self.sections.get(&section_pos).map_or(0, |list| list.len());
+        let old = self.allocations.remove(&section_pos);
+        if len == 0 {
+            if let Some(old) = old {
+                self.allocator.deallocate(&old);
+            }
+            self.sections.remove(&section_pos);
+            return Some(SurfelMapSlice::Empty);
+        }
 
-impl SurfelMap {
-    pub fn new() -> Self {
-        Self {
-            pointers: [0; MAX_SURFELS]
+        // A shorter list always fits where the old one was, a longer one has to fit before the old one is let go
+        let shrinks = old.is_some_and(|old| old.size() >= len as u32);
+        if let Some(old) = old.filter(|_| shrinks) {
+            self.allocator.deallocate(&old);
         }
-    }
-}
 
-#[derive(Clone, Debug)]
-pub struct SurfelGridBufferTask {
-    pub pos: UVec3,
-    pub ptr: u32,
-}
+        let alloc = self.allocator.allocate(len as u32);
+        if alloc.back as usize > SURFEL_MAP_SIZE {
+            self.allocator.deallocate(&alloc);
+            if let Some(old) = old.filter(|_| !shrinks) {
+                self.allocations.insert(section_pos, old);
+            }
+            return None;
+        }
+        if let Some(old) = old.filter(|_| !shrinks) {
+            self.allocator.deallocate(&old);
+        }
+
+        let list = &self.sections[&section_pos];
+        for (i, surfel_index) in list.iter().enumerate() {
+            self.writes.write(alloc.front + i as u32, *surfel_index);
+        }
+        self.allocations.insert(section_pos, alloc);
 
-/// Writes a queue to a write lock and clears the queue.
-pub fn write_queue_buffer(queue: &mut VecDeque<SurfelGridBufferTask>, write_lock: &mut BufferWriteGuard<SurfelGrid>) {
-    let ptrs = &mut write_lock.pointers;
-    for task in queue.drain(..) {
-        let pos = task.pos;
-        ptrs[morton_encode(pos.x, pos.y, pos.z)] = task.ptr;
+        Some(SurfelMapSlice::SurfelMap {
+            start: alloc.front,
+            length: len as u32,
+        })
     }
-}
\ No newline at end of file
+}
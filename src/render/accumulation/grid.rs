@@ -1,28 +1,26 @@
-use vulkano::buffer::BufferContents;
-
 use crate::render::brick::brickgrid::BRICKGRID_SIZE;
 
 const SGS_X: usize = BRICKGRID_SIZE[0] as usize;
 const SGS_Y: usize = BRICKGRID_SIZE[1] as usize;
 const SGS_Z: usize = BRICKGRID_SIZE[2] as usize;
 
-const SG_ARRAY_SIZE: usize = SGS_X * SGS_Y * SGS_Z;
+/// The surfel grid has one surfel map slice per brickgrid cell and is indexed the same way.
+pub const SG_ARRAY_SIZE: usize = SGS_X * SGS_Y * SGS_Z;
 
-#[repr(C)]
-#[derive(Clone, BufferContents)]
-pub struct SurfelGrid {
-    // surfel map slices
-    pub pointers: [u32; SG_ARRAY_SIZE],
-}
+const START_BITS: u32 = 20;
+const LENGTH_BITS: u32 = 11;
+
+pub const MAX_SLICE_START: u32 = (1 << START_BITS) - 1;
+pub const MAX_SLICE_LENGTH: u32 = (1 << LENGTH_BITS) - 1;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SurfelMapSlice {
     // one flag bit
     Empty,
     SurfelMap {
-        // must be < 18 bits
+        // must be < 20 bits
         start: u32,
-        // must be < 13 bits
+        // must be < 11 bits
         length: u32,
     }
 }
@@ -32,9 +30,9 @@ impl SurfelMapSlice {
         match pointer & 1 {
             0 => Self::Empty,
             1 => {
-                Self::SurfelMap { 
-                    start: (pointer >> 1) & 0b1111_1111_1111_1111_11, 
-                    length: pointer >> 19,
+                Self::SurfelMap {
+                    start: (pointer >> 1) & MAX_SLICE_START,
+                    length: pointer >> (START_BITS + 1),
                 }
             },
             _ => unreachable!(),
@@ -45,12 +43,30 @@ impl SurfelMapSlice {
         match *self {
             Self::Empty => 0,
             Self::SurfelMap { start, length } => {
-                if start > 0b1111_1111_1111_1111_11 || length > 0b1111_1111_1111_1 {
+                if start > MAX_SLICE_START || length > MAX_SLICE_LENGTH {
                     panic!("Too many surfel!!");
                 }
 
-                1 | (start << 1) | (length << 19)
+                1 | (start << 1) | (length << (START_BITS + 1))
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slice_round_trip() {
+        let slices = [
+            SurfelMapSlice::Empty,
+            SurfelMapSlice::SurfelMap { start: 0, length: 1 },
+            SurfelMapSlice::SurfelMap { start: MAX_SLICE_START, length: MAX_SLICE_LENGTH },
+        ];
+
+        for slice in slices {
+            assert_eq!(SurfelMapSlice::from_raw(slice.to_raw()), slice);
+        }
+    }
+}
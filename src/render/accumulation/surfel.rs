@@ -1,9 +1,19 @@
-use bytemuck::Zeroable;
+use bytemuck::{Pod, Zeroable};
 use ultraviolet::Vec3;
-use vulkano::buffer::BufferContents;
 
-use crate::{util::util::{VecRounding, UVecTrunc, IVecTrunc, VecModPos}, render::brick::brickgrid::BRICKGRID_SIZE};
+use crate::{
+    render::{
+        brick::brickgrid::BRICKGRID_SIZE,
+        gpu_layout::{gpu_fields, GlslType, GpuField, GpuStruct, GpuStructKind},
+    },
+    util::util::{Facing, IVecTrunc, Sign, UVecTrunc, VecModPos, VecRounding},
+    world::section::F_SECTION_SIZE,
+};
 
+/// Radius of newly spawned surfels in blocks
+pub const SURFEL_RADIUS: f32 = 1.5;
+
+#[derive(Clone, Debug)]
 pub struct Surfel {
     pub accumulated: Vec3,
     pub radius: f32,
@@ -16,47 +26,87 @@ impl Surfel {
         Self { accumulated, radius, normal, world_pos }
     }
 
+    /// A surfel without any light sitting on the face `face_id` of a block.
+    pub fn on_face(world_pos: Vec3, face_id: usize) -> Self {
+        let facing = Facing::from_num(face_id);
+        let normal = match facing.sign {
+            Sign::Positive => facing.axis.unit_vec(),
+            Sign::Negative => -facing.axis.unit_vec(),
+        };
+
+        Self::new(Vec3::zero(), SURFEL_RADIUS, normal, world_pos)
+    }
+
+    /// Position of the section the surfel is in
+    pub fn section_pos(&self) -> Vec3 {
+        (self.world_pos / F_SECTION_SIZE).floor()
+    }
+
     pub fn to_raw(&self) -> SurfelRaw {
-        if self.normal.mag_sq() > 1.0 {
+        if self.normal.mag_sq() > 1.0 + f32::EPSILON {
             panic!("Normal big!");
         }
 
-        const RECIP_16: f32 = 1.0 / 16.0;
-        let chunk_pos = (self.world_pos * RECIP_16).floor();
-        let chunk_offset = (self.world_pos - chunk_pos).floor();
-        let offset = (self.world_pos - chunk_pos) - chunk_offset;
+        let section_pos = self.section_pos();
+        let local_pos = self.world_pos - (section_pos * F_SECTION_SIZE);
+        let chunk_offset = local_pos.floor();
+        let offset = local_pos - chunk_offset;
 
-        let chunk_pos = chunk_pos.into_i().mod_pos(BRICKGRID_SIZE.into());
+        // The shader recovers the real section position using the brickgrid window
+        let section_pos = section_pos.into_i().mod_pos(BRICKGRID_SIZE.into());
         let chunk_offset = chunk_offset.into_u();
-        let offset = (offset * 65536.0).floor().into_u();
-        let normal = (self.normal * 65536.0).floor().into_u();
+        let offset = (offset * 65535.0).round().into_u();
+        // Stored unsigned, so [-1, 1] is mapped to [0, 1]
+        let normal = ((self.normal * 0.5 + Vec3::one() * 0.5) * 65535.0).round().into_u();
 
-        let packed_chunk_pos = (chunk_pos.x << 15) | (chunk_pos.y << 10) | (chunk_pos.z);
+        let packed_chunk_pos = (section_pos.x << 15) | (section_pos.y << 10) | (section_pos.z);
         let packed_chunk_offset = (chunk_offset.x << 6) | (chunk_offset.y << 3) | (chunk_offset.z);
 
         SurfelRaw {
             accumulated: self.accumulated.into(),
             radius: self.radius,
-            normal: normal.as_array().map(|n| n as u16),
-            offset: offset.as_array().map(|n| n as u16),
+            n_xy: (normal.x << 16) | normal.y,
+            nz_ox: (normal.z << 16) | offset.x,
+            o_yz: (offset.y << 16) | offset.z,
             packed_chunk_offset,
             packed_chunk_pos,
+            _pad: [0; 3],
         }
     }
 }
 
+/// Matches the std430 layout of `Surfel` in the shaders, the `vec3` makes it 16 byte aligned.
 #[repr(C)]
-#[derive(Clone, Debug, BufferContents, Zeroable)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct SurfelRaw {
     pub accumulated: [f32; 3],
     pub radius: f32,
-    pub normal: [u16; 3],
-    pub offset: [u16; 3],
+    pub n_xy: u32,
+    pub nz_ox: u32,
+    pub o_yz: u32,
 
     // empty (23) chunk offset (3 3 3)
     pub packed_chunk_offset: u32,
     // empty (7) chunk pos (10 5 10)
     pub packed_chunk_pos: u32,
+    pub _pad: [u32; 3],
+}
+
+impl GpuStruct for SurfelRaw {
+    const GLSL_NAME: &'static str = "Surfel";
+    const KIND: GpuStructKind = GpuStructKind::Struct;
+
+    fn fields() -> Vec<GpuField> {
+        gpu_fields!(SurfelRaw {
+            accumulated: GlslType::Vec3,
+            radius: GlslType::Float,
+            n_xy: GlslType::Uint,
+            nz_ox: GlslType::Uint,
+            o_yz: GlslType::Uint,
+            packed_chunk_offset: GlslType::Uint,
+            packed_chunk_pos: GlslType::Uint,
+        })
+    }
 }
 
 impl Default for SurfelRaw {
@@ -66,4 +116,4 @@ impl Default for SurfelRaw {
             ..Self::zeroed()
         }
     }
-}
\ No newline at end of file
+}
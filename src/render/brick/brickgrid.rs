@@ -1,8 +1,6 @@
-use std::{mem::size_of, collections::VecDeque};
+use std::mem::size_of;
 
-use bytemuck::{Zeroable, Pod};
-use ultraviolet::UVec3;
-use vulkano::buffer::subbuffer::BufferWriteGuard;
+use ultraviolet::IVec2;
 
 use super::brickmap::BrickmapPointerRaw;
 
@@ -14,44 +12,56 @@ const BGS_Z: usize = BRICKGRID_SIZE[2] as usize;
 
 pub const BG_ARRAY_SIZE: usize = BGS_X * BGS_Y * BGS_Z;
 
+// The shader wraps positions with a bit mask
+const _: () = assert!(BGS_X.is_power_of_two() && BGS_Y.is_power_of_two() && BGS_Z.is_power_of_two());
+
 const _DATA_SIZE: usize = size_of::<BrickmapPointerRaw>() * BGS_X * BGS_Y * BGS_Z;
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
-pub struct Brickgrid {
-    pub pointers: [BrickmapPointerRaw; BG_ARRAY_SIZE],
+/// The part of the world that the brickgrid covers, measured in chunks on the XZ plane.
+///
+/// Sections are stored at their position modulo `BRICKGRID_SIZE`, so the window can slide along
+/// with the camera. Anything outside of it is treated as not loaded, by the shader as well.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BrickgridWindow {
+    pub origin: IVec2,
 }
 
-impl Brickgrid {
-    pub fn new_empty() -> Self {
-        Self {
-            pointers: [BrickmapPointerRaw::zeroed(); BG_ARRAY_SIZE],
-        }
+impl BrickgridWindow {
+    const HALF_SIZE: IVec2 = IVec2::new(BGS_X as i32 / 2, BGS_Z as i32 / 2);
+
+    /// The window is only moved once the camera is this far away from its center
+    const RECENTER_DISTANCE: i32 = BGS_X as i32 / 4;
+
+    pub fn centered_on(chunk_pos: IVec2) -> Self {
+        Self { origin: chunk_pos - Self::HALF_SIZE }
     }
-}
 
-#[derive(Clone, Debug)]
-pub enum BrickgridBufferTask {
-    One {
-        pos: UVec3,
-        section: BrickmapPointerRaw,
+    pub fn origin_array(&self) -> [i32; 2] {
+        [self.origin.x, self.origin.y]
     }
-}
 
-/// Writes a queue to a write lock and clears the queue.
-pub fn write_queue_buffer(queue: &mut VecDeque<BrickgridBufferTask>, write_lock: &mut BufferWriteGuard<Brickgrid>) {
-    let ptrs = &mut write_lock.pointers;
-    for task in queue.drain(..) {
-        match task {
-            BrickgridBufferTask::One { pos, section } => {
-                ptrs[morton_encode(pos.x, pos.y, pos.z)] = section;
-            }
-        }
+    pub fn center(&self) -> IVec2 {
+        self.origin + Self::HALF_SIZE
+    }
+
+    pub fn should_recenter(&self, chunk_pos: IVec2) -> bool {
+        (chunk_pos - self.center()).abs().component_max() > Self::RECENTER_DISTANCE
+    }
+
+    pub fn contains(&self, chunk_pos: IVec2) -> bool {
+        let rel = chunk_pos - self.origin;
+        rel.x >= 0 && rel.y >= 0 && rel.x < BGS_X as i32 && rel.y < BGS_Z as i32
     }
 }
 
 // XZ XYZ XZ XYZ XZ XYZ XZ XYZ XZ XYZ
 pub fn morton_encode(x: u32, y: u32, z: u32) -> usize {
+    // Only the low bits are encoded, larger positions would alias onto each other
+    debug_assert!(
+        x < BRICKGRID_SIZE[0] && y < BRICKGRID_SIZE[1] && z < BRICKGRID_SIZE[2],
+        "Position outside of the brickgrid: ({x}, {y}, {z})"
+    );
+
     let mut i = 0;
 
     for b in 0..5u32 {
@@ -74,4 +84,32 @@ pub fn morton_encode(x: u32, y: u32, z: u32) -> usize {
     }
     
     i as usize
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use ultraviolet::{IVec3, UVec3};
+
+    use crate::util::util::VecModPos;
+
+    use super::*;
+
+    #[test]
+    fn window_wraps() {
+        let window = BrickgridWindow::centered_on(IVec2::new(-2000, 3000));
+
+        assert!(window.contains(IVec2::new(-2000, 3000)));
+        assert!(!window.contains(window.origin - IVec2::one()));
+        assert!(!window.contains(window.origin + IVec2::new(BGS_X as i32, 0)));
+        assert!(!window.should_recenter(IVec2::new(-2000, 3000)));
+
+        // Both ends of the window map to distinct cells
+        let size = UVec3::from(BRICKGRID_SIZE);
+        let first = IVec3::new(window.origin.x, 0, window.origin.y).mod_pos(size);
+        let last = IVec3::new(window.origin.x + BGS_X as i32 - 1, 0, window.origin.y).mod_pos(size);
+        assert_ne!(
+            morton_encode(first.x, first.y, first.z),
+            morton_encode(last.x, last.y, last.z)
+        );
+    }
+}
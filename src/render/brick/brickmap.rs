@@ -1,13 +1,14 @@
-use std::mem;
 
 use bytemuck::{Zeroable, Pod};
 
-use crate::render::texture::TextureHandle;
+use crate::render::{texture::TextureHandle, gpu_layout::{gpu_fields, GlslType, GpuField, GpuStruct, GpuStructKind}};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Zeroable, Pod)]
 pub struct Brickmap {
     pub solid_mask: [[u8; 8]; 8],
+    /// Subset of `solid_mask` that light can get through
+    pub transparent_mask: [[u8; 8]; 8],
     pub textures_offset: u32,
     pub lod_color: [u8; 3],
     pub _pad: u8,
@@ -17,6 +18,7 @@ impl Brickmap {
     pub fn empty() -> Self {
         Self {
             solid_mask: [[0; 8]; 8],
+            transparent_mask: [[0; 8]; 8],
             textures_offset: 0,
             lod_color: [0; 3],
             _pad: 0,
@@ -33,6 +35,21 @@ impl Brickmap {
     }
 }
 
+impl GpuStruct for Brickmap {
+    const GLSL_NAME: &'static str = "Brickmap";
+    const KIND: GpuStructKind = GpuStructKind::Struct;
+
+    fn fields() -> Vec<GpuField> {
+        // The bit masks are read as whole words
+        gpu_fields!(Brickmap {
+            solid_mask: GlslType::Array(&GlslType::Uint, 16),
+            transparent_mask: GlslType::Array(&GlslType::Uint, 16),
+            textures_offset: GlslType::Uint,
+            lod_color: GlslType::Uint,
+        })
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Zeroable, Pod)]
 pub struct BrickmapPointerRaw {
@@ -42,6 +59,7 @@ pub struct BrickmapPointerRaw {
 #[derive(Clone, Copy, Debug)]
 pub enum BrickmapPointer {
     NotLoaded,
+    Requested,
     Empty,
     Lod([u8; 3]),
     Brickmap(u32),
@@ -58,10 +76,11 @@ impl BrickmapPointer {
         //  11 => Loaded, 30 most significant bits are index into brickmap buffer
         let pointer = match self {
             BrickmapPointer::NotLoaded => 0,
+            BrickmapPointer::Requested => 0b100,
             BrickmapPointer::Empty => 1,
             BrickmapPointer::Lod([r, g, b]) => {
                 let arr = [0, r, g, b];
-                let trans = unsafe { mem::transmute::<_, u32>(arr) };
+                let trans = u32::from_ne_bytes(arr);
                 trans << 2 | 0b10
             },
             BrickmapPointer::Brickmap(idx) => {
@@ -1,14 +1,136 @@
 use bytemuck::{Pod, Zeroable};
+use ultraviolet::{IVec2, IVec3, Vec3};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator},
+};
 
+use crate::render::{util::CreateInfoConvenience, gpu_layout::{gpu_fields, GlslType, GpuField, GpuStruct, GpuStructKind}};
+
+pub const FEEDBACK_SIZE: usize = 256;
+pub const SURFEL_FEEDBACK_SIZE: usize = 256;
+
+/// Requests written by the shaders for the CPU.
+///
+/// Chunk positions of brickgrid cells that rays hit while they were not loaded,
+/// and surfaces no surfel covers yet.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Feedback {
     pub top: u32,
-    pub map_positions: [[i32; 2]; 32],
+    pub surfel_top: u32,
+    pub _pad: [u32; 2],
+    /// Block under the crosshair, `w` is the face id plus one or `0` if nothing was hit
+    pub target: [i32; 4],
+    /// World position and face id
+    pub surfel_spawns: [[f32; 4]; SURFEL_FEEDBACK_SIZE],
+    pub map_positions: [[i32; 2]; FEEDBACK_SIZE],
+}
+
+impl GpuStruct for Feedback {
+    const GLSL_NAME: &'static str = "Feedback";
+    const KIND: GpuStructKind = GpuStructKind::StorageBlock;
+
+    fn fields() -> Vec<GpuField> {
+        gpu_fields!(Feedback {
+            top: GlslType::Uint,
+            surfel_top: GlslType::Uint,
+            target: GlslType::IVec4,
+            surfel_spawns: GlslType::Array(&GlslType::Vec4, SURFEL_FEEDBACK_SIZE),
+            map_positions: GlslType::Array(&GlslType::IVec2, FEEDBACK_SIZE),
+        })
+    }
 }
 
 impl Feedback {
     pub fn empty() -> Self {
         Self::zeroed()
     }
-}
\ No newline at end of file
+
+    /// `top` keeps counting after the buffer is full, so it has to be clamped.
+    pub fn requests(&self) -> impl Iterator<Item = IVec2> + '_ {
+        let len = (self.top as usize).min(FEEDBACK_SIZE);
+        self.map_positions[..len].iter().map(|p| IVec2::new(p[0], p[1]))
+    }
+
+    pub fn surfel_requests(&self) -> impl Iterator<Item = (Vec3, usize)> + '_ {
+        let len = (self.surfel_top as usize).min(SURFEL_FEEDBACK_SIZE);
+        self.surfel_spawns[..len]
+            .iter()
+            .map(|s| (Vec3::new(s[0], s[1], s[2]), s[3] as usize))
+    }
+
+    pub fn target(&self) -> Option<(IVec3, usize)> {
+        let [x, y, z, face] = self.target;
+        (face > 0).then(|| (IVec3::new(x, y, z), face as usize - 1))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FeedbackRequests {
+    pub chunks: Vec<IVec2>,
+    pub surfels: Vec<(Vec3, usize)>,
+    /// Block position and face id under the crosshair
+    pub target: Option<(IVec3, usize)>,
+}
+
+/// One feedback buffer per swapchain image, so the CPU can read one back while the others are in flight.
+pub struct FeedbackBuffers {
+    pub buffers: Vec<Subbuffer<Feedback>>,
+    /// Frame number each buffer was last recorded for
+    frames: Vec<u32>,
+}
+
+impl FeedbackBuffers {
+    pub fn new(allocator: &StandardMemoryAllocator, count: usize) -> Self {
+        let buffers = (0..count)
+            .map(|_| {
+                Buffer::from_data(
+                    allocator,
+                    BufferCreateInfo::usage(BufferUsage::STORAGE_BUFFER),
+                    AllocationCreateInfo::usage(MemoryUsage::Download),
+                    Feedback::empty(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        Self { buffers, frames: vec![0; count] }
+    }
+
+    /// Call when recording the frame that writes to the buffer of the image
+    pub fn recorded(&mut self, image_index: usize, frame_number: u32) {
+        let len = self.frames.len();
+        self.frames[image_index % len] = frame_number;
+    }
+
+    /// Reads and clears every buffer that is not in use by the GPU.
+    ///
+    /// Chunk requests come without duplicates, the target is the one of the newest frame that was read.
+    pub fn collect(&self) -> FeedbackRequests {
+        let mut ret = FeedbackRequests::default();
+        let mut newest = None;
+        for (buffer, frame) in self.buffers.iter().zip(self.frames.iter()) {
+            // May not be instant due to frames in flight, the requests will be picked up next frame
+            if let Ok(mut write) = buffer.write() {
+                for pos in write.requests() {
+                    if !ret.chunks.contains(&pos) {
+                        ret.chunks.push(pos);
+                    }
+                }
+                ret.surfels.extend(write.surfel_requests());
+                if newest.is_none_or(|newest| *frame > newest) {
+                    newest = Some(*frame);
+                    ret.target = write.target();
+                }
+                write.top = 0;
+                write.surfel_top = 0;
+            }
+        }
+        ret
+    }
+
+    pub fn get_buffer(&self, image_index: usize) -> Subbuffer<Feedback> {
+        self.buffers[image_index % self.buffers.len()].clone()
+    }
+}
@@ -1,24 +1,44 @@
-use std::collections::hash_map::Iter;
+use std::{
+    collections::{btree_map::Iter, BTreeMap},
+    fmt,
+    mem::size_of,
+};
 
 use ahash::HashMap;
 use bytemuck::Pod;
 use ultraviolet::IVec3;
 use vulkano::{
     buffer::{BufferContents, BufferUsage, Subbuffer},
-    command_buffer::DrawIndirectCommand,
+    command_buffer::{AutoCommandBufferBuilder, DrawIndirectCommand, PrimaryAutoCommandBuffer},
+    device::DeviceOwned,
     memory::allocator::StandardMemoryAllocator,
 };
 
-use super::swap_buffer::SwapBufferSlice;
+use crate::render::memory::{MemoryReport, Subsystem};
+
+use super::{device_buffer::DeviceBuffer, staging_ring::StagingRing};
+
+/// Share of the space below `top` that has to be free before allocations are moved
+const COMPACTION_THRESHOLD: f32 = 0.25;
+/// Share of the buffer below which `top` has to be before the buffer shrinks to twice `top`
+const SHRINK_THRESHOLD: f32 = 0.25;
 
 pub struct HeapBuffer<T>
 where
     [T]: BufferContents,
 {
-    buffer: SwapBufferSlice<T>,
+    /// Used in messages about the buffer
+    name: &'static str,
+    buffer: DeviceBuffer<T>,
+    /// In elements, the device's limit for storage buffers
+    max_len: usize,
+    /// In elements, the buffer never shrinks below its initial length
+    min_len: usize,
     chunk_allocator: ChunkBufferAllocator,
     pub allocations: HashMap<IVec3, ChunkBufferAllocation>,
-    pub uploaded: Vec<(IVec3, ChunkBufferAllocation)>,
+    /// Sections by the front of their allocation, to find the highest ones when compacting.
+    /// Empty allocations aren't worth moving and are left out.
+    by_front: BTreeMap<u32, IVec3>,
     pub indirect_buffer: Option<Subbuffer<[DrawIndirectCommand]>>,
     pub vertex_count_multiplier: u32,
     pub highest: usize,
@@ -29,50 +49,91 @@ where
     U: Copy + Clone + Send + Sync + Pod,
     [U]: BufferContents,
 {
+    /// `size` is the initial length, the buffer grows when it runs out of space
     pub fn new(
+        name: &'static str,
         allocator: &StandardMemoryAllocator,
         usage: BufferUsage,
         size: usize,
         vertex_count_multiplier: u32,
     ) -> Self {
+        let max_range = allocator.device().physical_device().properties().max_storage_buffer_range;
+        let max_len = max_range as usize / size_of::<U>();
+
         HeapBuffer {
-            buffer: SwapBufferSlice::new(size, usage, allocator),
+            name,
+            buffer: DeviceBuffer::new(allocator, usage, size.min(max_len)),
+            max_len,
+            min_len: size.min(max_len),
             chunk_allocator: ChunkBufferAllocator::new(),
             allocations: HashMap::default(),
-            uploaded: Vec::new(),
+            by_front: BTreeMap::new(),
             indirect_buffer: None,
             vertex_count_multiplier,
             highest: 0,
         }
     }
 
-    pub fn update(&mut self) -> bool {
-        let swapped = self.buffer.update();
-        if swapped {
-            self.uploaded = self
-                .allocations
-                .iter()
-                .map(|(v, alloc)| (*v, *alloc))
-                .collect();
-        }
-        swapped
+    /// Records the changes since the last call, returns true if the buffer was replaced
+    pub fn record_uploads(
+        &mut self,
+        allocator: &StandardMemoryAllocator,
+        ring: &mut StagingRing,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> bool {
+        self.buffer.record(allocator, ring, cbb)
     }
 
-    pub fn insert(&mut self, section_pos: IVec3, data: &[U]) -> ChunkBufferAllocation {
+    /// Returns `None` if the buffer can't grow large enough to fit `data`
+    pub fn insert(&mut self, section_pos: IVec3, data: &[U]) -> Option<ChunkBufferAllocation> {
         let size = data.len() as u32;
         let allocation = self.chunk_allocator.allocate(size);
+        if !self.reserve(allocation.back as usize) {
+            self.chunk_allocator.deallocate(&allocation);
+            return None;
+        }
+
         if allocation.back as usize > self.highest {
             self.highest = allocation.back as usize;
         }
 
         self.allocations.insert(section_pos, allocation);
+        if size > 0 {
+            self.by_front.insert(allocation.front, section_pos);
+        }
         self.buffer
             .write(allocation.front.try_into().unwrap(), data);
-        allocation
+        Some(allocation)
+    }
+
+    /// Replaces the data of an existing allocation with data of the same length
+    pub fn overwrite(&mut self, section_pos: IVec3, data: &[U]) {
+        let allocation = self.allocations[&section_pos];
+        assert_eq!(allocation.size() as usize, data.len(), "Overwrite changes the size of {:?}", section_pos);
+        self.buffer.write(allocation.front as usize, data);
+    }
+
+    /// Grows the buffer so it can hold `len` elements, false if that's more than the device allows
+    fn reserve(&mut self, len: usize) -> bool {
+        let capacity = self.buffer.capacity();
+        if len <= capacity {
+            return true;
+        }
+        if len > self.max_len {
+            return false;
+        }
+
+        let new_len = (capacity * 2).clamp(len, self.max_len);
+        println!("Growing the {} buffer to {} elements", self.name, new_len);
+        self.buffer.grow(new_len);
+        true
     }
 
     pub fn remove(&mut self, section_pos: IVec3) {
         if let Some(allocation) = self.allocations.remove(&section_pos) {
+            if allocation.size() > 0 {
+                self.by_front.remove(&allocation.front);
+            }
             self.chunk_allocator.deallocate(&allocation);
             // No need to push to queue, corresponding memory will not be read
         } else {
@@ -83,13 +144,65 @@ where
         }
     }
 
-    pub fn reinsert(&mut self, section_pos: IVec3, data: &[U]) {
+    pub fn reinsert(&mut self, section_pos: IVec3, data: &[U]) -> Option<ChunkBufferAllocation> {
         self.remove(section_pos);
-        self.insert(section_pos, data);
+        self.insert(section_pos, data)
+    }
+
+    /// Moves up to `max_moves` of the highest allocations into free space further down, as long
+    /// as the buffer is fragmented enough. Shrinks the buffer once most of it is free above `top`.
+    ///
+    /// Returns the sections that moved and their new allocations, whatever points into this buffer
+    /// has to be rewritten for them.
+    pub fn compact(&mut self, max_moves: usize) -> Vec<(IVec3, ChunkBufferAllocation)> {
+        let mut moved = Vec::new();
+
+        while moved.len() < max_moves && self.chunk_allocator.fragmentation() > COMPACTION_THRESHOLD {
+            let Some((&front, &section_pos)) = self.by_front.last_key_value() else { break };
+            let old = self.allocations[&section_pos];
+
+            // Only the highest allocation lowers `top`, moving any other just shuffles the gaps
+            let Some(new) = self.chunk_allocator.relocate(&old) else { break };
+
+            self.buffer.copy(old.front as usize, new.front as usize, old.size() as usize);
+            self.by_front.remove(&front);
+            self.by_front.insert(new.front, section_pos);
+            self.allocations.insert(section_pos, new);
+            moved.push((section_pos, new));
+        }
+
+        self.shrink_to_fit();
+        moved
+    }
+
+    fn shrink_to_fit(&mut self) {
+        let capacity = self.buffer.capacity();
+        let top = self.chunk_allocator.top() as usize;
+        if capacity <= self.min_len || top as f32 >= capacity as f32 * SHRINK_THRESHOLD {
+            return;
+        }
+
+        let new_len = (top * 2).max(self.min_len);
+        println!("Shrinking the {} buffer to {} elements", self.name, new_len);
+        self.buffer.shrink(new_len);
+    }
+
+    pub fn report_memory(&self, report: &mut MemoryReport, subsystem: Subsystem) {
+        self.buffer.report_memory(report, subsystem);
+    }
+
+    pub fn stats(&self) -> HeapBufferStats {
+        HeapBufferStats {
+            name: self.name,
+            capacity: self.buffer.capacity(),
+            max_len: self.max_len,
+            allocations: self.allocations.len(),
+            allocator: self.chunk_allocator.stats(),
+        }
     }
 
     pub fn get_buffer(&self) -> Subbuffer<[U]> {
-        self.buffer.buffer.current_buffer()
+        self.buffer.get_buffer()
     }
 
     pub fn get_ind_commands(&self) -> Vec<DrawIndirectCommand> {
@@ -124,29 +237,82 @@ impl ChunkBufferAllocation {
     fn new_size(front: u32, size: u32) -> Self {
         Self::new(front, front + size)
     }
+
+    pub fn size(&self) -> u32 {
+        self.back - self.front
+    }
+}
+
+/// Usage of a `ChunkBufferAllocator`, all in elements
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AllocatorStats {
+    pub used: u32,
+    /// End of the highest allocation
+    pub top: u32,
+    pub free_ranges: usize,
+    pub largest_free: u32,
+}
+
+impl AllocatorStats {
+    /// Share of the space below `top` that is free
+    pub fn fragmentation(&self) -> f32 {
+        if self.top == 0 {
+            0.0
+        } else {
+            1.0 - self.used as f32 / self.top as f32
+        }
+    }
+}
+
+pub struct HeapBufferStats {
+    pub name: &'static str,
+    pub capacity: usize,
+    pub max_len: usize,
+    pub allocations: usize,
+    pub allocator: AllocatorStats,
+}
+
+impl fmt::Display for HeapBufferStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let a = &self.allocator;
+        write!(
+            f,
+            "{}: {} allocations, {} of {} elements used (max {}), top {}, {} free ranges, largest free {}, {:.1}% fragmented",
+            self.name,
+            self.allocations,
+            a.used,
+            self.capacity,
+            self.max_len,
+            a.top,
+            a.free_ranges,
+            a.largest_free,
+            a.fragmentation() * 100.0,
+        )
+    }
 }
 
 pub struct ChunkBufferAllocator {
-    /// Represents all free sections
-    dual_map: DualHashMap<u32>,
+    /// Represents all free sections, none of them reach `top`
+    dual_map: DualMap<u32>,
     top: u32,
+    /// Sum of the sizes of all live allocations
+    used: u32,
 }
 
 impl ChunkBufferAllocator {
     pub fn new() -> Self {
         Self {
-            dual_map: DualHashMap::new(),
+            dual_map: DualMap::new(),
             top: 0,
+            used: 0,
         }
     }
 
+    /// Takes the lowest free section that fits
     pub fn allocate(&mut self, size: u32) -> ChunkBufferAllocation {
+        self.used += size;
         if let Some((front, back, free_size)) = Self::find_free(&self.dual_map, size) {
-            self.dual_map.remove_front(&front);
-            if free_size != size {
-                self.dual_map.insert(front + size, back);
-            }
-            ChunkBufferAllocation::new_size(front, size)
+            self.take_free(front, back, free_size, size)
         } else {
             let old_top = self.top;
             self.top += size;
@@ -154,9 +320,22 @@ impl ChunkBufferAllocator {
         }
     }
 
+    fn take_free(&mut self, front: u32, back: u32, free_size: u32, size: u32) -> ChunkBufferAllocation {
+        self.dual_map.remove_front(&front);
+        if free_size != size {
+            self.dual_map.insert(front + size, back);
+        }
+        ChunkBufferAllocation::new_size(front, size)
+    }
+
     /// Deallocates a section and marks it as free.<br>
     /// <b>DOES NOT CHECK IF THIS ALLOCATION IS VALID</b>. *Use an allocation generated by* `.allocate()`
     pub fn deallocate(&mut self, alloc: &ChunkBufferAllocation) {
+        self.used -= alloc.size();
+        if alloc.size() == 0 {
+            return;
+        }
+
         let mut joined_alloc = *alloc; // Calling `clone()` on `alloc` which implements `Copy`. Dereference instead.
 
         if let Some(back) = self.dual_map.remove_front(&alloc.back) {
@@ -167,10 +346,55 @@ impl ChunkBufferAllocator {
             joined_alloc.front = front.to_owned();
         }
 
-        self.dual_map.insert(joined_alloc.front, joined_alloc.back);
+        if joined_alloc.back == self.top {
+            self.top = joined_alloc.front;
+        } else {
+            self.dual_map.insert(joined_alloc.front, joined_alloc.back);
+        }
+    }
+
+    /// Moves an allocation to the lowest free section below it that fits, the old one is freed.
+    ///
+    /// Returns `None` if there is no such section.
+    pub fn relocate(&mut self, alloc: &ChunkBufferAllocation) -> Option<ChunkBufferAllocation> {
+        let size = alloc.size();
+        let (front, back, free_size) =
+            Self::find_free(&self.dual_map, size).filter(|(front, ..)| *front < alloc.front)?;
+
+        let new = self.take_free(front, back, free_size, size);
+        self.used += size;
+        self.deallocate(alloc);
+        Some(new)
+    }
+
+    pub fn fragmentation(&self) -> f32 {
+        self.stats_without_ranges().fragmentation()
+    }
+
+    /// End of the highest allocation
+    pub fn top(&self) -> u32 {
+        self.top
     }
 
-    fn find_free(map: &DualHashMap<u32>, needed_size: u32) -> Option<(u32, u32, u32)> {
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            free_ranges: self.dual_map.len(),
+            largest_free: self.dual_map.iter().map(|(front, back)| back - front).max().unwrap_or(0),
+            ..self.stats_without_ranges()
+        }
+    }
+
+    /// Skips going through the free sections
+    fn stats_without_ranges(&self) -> AllocatorStats {
+        AllocatorStats {
+            used: self.used,
+            top: self.top,
+            free_ranges: 0,
+            largest_free: 0,
+        }
+    }
+
+    fn find_free(map: &DualMap<u32>, needed_size: u32) -> Option<(u32, u32, u32)> {
         for (front, back) in map.iter() {
             let free_size = back - front;
             if needed_size <= free_size {
@@ -181,18 +405,19 @@ impl ChunkBufferAllocator {
     }
 }
 
-struct DualHashMap<T> {
-    front_back: HashMap<T, T>,
+/// Ranges looked up by either end, iterated from the lowest front
+struct DualMap<T> {
+    front_back: BTreeMap<T, T>,
     back_front: HashMap<T, T>,
 }
 
-impl<T> DualHashMap<T>
+impl<T> DualMap<T>
 where
-    T: std::hash::Hash + std::cmp::Eq + Clone,
+    T: std::hash::Hash + std::cmp::Ord + Clone,
 {
     fn new() -> Self {
         Self {
-            front_back: HashMap::default(),
+            front_back: BTreeMap::new(),
             back_front: HashMap::default(),
         }
     }
@@ -235,4 +460,133 @@ where
     fn iter(&self) -> Iter<'_, T, T> {
         self.front_back.iter()
     }
+
+    fn len(&self) -> usize {
+        self.front_back.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Allocate(u32),
+        /// Index into the live allocations
+        Deallocate(usize),
+        /// Moves the highest allocation down
+        Relocate,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..64u32).prop_map(Op::Allocate),
+            any::<usize>().prop_map(Op::Deallocate),
+            Just(Op::Relocate),
+        ]
+    }
+
+    /// Live allocations and free sections cover `0..top` exactly once and free sections are merged
+    fn check_invariants(allocator: &ChunkBufferAllocator, live: &[ChunkBufferAllocation]) {
+        let mut ranges = live
+            .iter()
+            .map(|a| (a.front, a.back, false))
+            .chain(allocator.dual_map.iter().map(|(front, back)| (*front, *back, true)))
+            .filter(|(front, back, _)| front != back)
+            .collect::<Vec<_>>();
+        ranges.sort();
+
+        let mut end = 0;
+        let mut previous_free = false;
+        for (front, back, free) in ranges {
+            assert_eq!(front, end, "Gap or overlap at {}", front);
+            assert!(!(free && previous_free), "Free sections at {} were not merged", front);
+            end = back;
+            previous_free = free;
+        }
+
+        assert_eq!(end, allocator.top);
+        assert!(!previous_free, "A free section reaches the top");
+        assert_eq!(allocator.used, live.iter().map(|a| a.size()).sum::<u32>());
+    }
+
+    fn highest(live: &[ChunkBufferAllocation]) -> Option<usize> {
+        (0..live.len())
+            .filter(|i| live[*i].size() > 0)
+            .max_by_key(|i| live[*i].front)
+    }
+
+    proptest! {
+        #[test]
+        fn allocations_never_overlap(ops in prop::collection::vec(op(), 1..200)) {
+            let mut allocator = ChunkBufferAllocator::new();
+            let mut live = Vec::new();
+
+            for op in ops {
+                match op {
+                    Op::Allocate(size) => live.push(allocator.allocate(size)),
+                    Op::Deallocate(i) if !live.is_empty() => {
+                        let alloc = live.swap_remove(i % live.len());
+                        allocator.deallocate(&alloc);
+                    },
+                    Op::Relocate => {
+                        if let Some(i) = highest(&live) {
+                            if let Some(new) = allocator.relocate(&live[i]) {
+                                prop_assert!(new.front < live[i].front);
+                                prop_assert_eq!(new.size(), live[i].size());
+                                live[i] = new;
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+                check_invariants(&allocator, &live);
+            }
+        }
+
+        #[test]
+        fn compaction_removes_gaps(count in 1..200usize, removed in prop::collection::vec(any::<usize>(), 0..150)) {
+            let mut allocator = ChunkBufferAllocator::new();
+            let mut live = (0..count).map(|_| allocator.allocate(1)).collect::<Vec<_>>();
+            for i in removed {
+                let alloc = live.swap_remove(i % live.len());
+                allocator.deallocate(&alloc);
+                if live.is_empty() {
+                    break;
+                }
+            }
+
+            while let Some(i) = highest(&live) {
+                match allocator.relocate(&live[i]) {
+                    Some(new) => live[i] = new,
+                    None => break,
+                }
+            }
+
+            check_invariants(&allocator, &live);
+            prop_assert_eq!(allocator.stats().top, live.len() as u32);
+            prop_assert_eq!(allocator.stats().free_ranges, 0);
+            prop_assert_eq!(allocator.fragmentation(), 0.0);
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let mut allocator = ChunkBufferAllocator::new();
+        let allocs = [4, 2, 8, 1].map(|size| allocator.allocate(size));
+        allocator.deallocate(&allocs[0]);
+        allocator.deallocate(&allocs[2]);
+
+        let stats = allocator.stats();
+        assert_eq!(stats, AllocatorStats { used: 3, top: 15, free_ranges: 2, largest_free: 8 });
+
+        // The free section below the top one is merged into the top
+        allocator.deallocate(&allocs[3]);
+        assert_eq!(allocator.stats().top, 6);
+        assert_eq!(allocator.stats().largest_free, 4);
+        assert!((allocator.fragmentation() - 4.0 / 6.0).abs() < 1e-6);
+    }
 }
@@ -0,0 +1,302 @@
+use std::{collections::BTreeMap, mem, ops::Range};
+
+use bytemuck::Pod;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, BufferCopy, CopyBufferInfoTyped, PrimaryAutoCommandBuffer},
+    memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator},
+};
+
+use crate::render::{
+    memory::{MemoryReport, Subsystem},
+    util::CreateInfoConvenience,
+};
+
+use super::staging_ring::StagingRing;
+
+#[derive(Clone, Debug)]
+pub enum DeviceBufferTask<T> {
+    Write {
+        start_idx: usize,
+        data: Vec<T>,
+    },
+    /// Copies data within the buffer, the source is left as it is
+    Copy {
+        src_idx: usize,
+        dst_idx: usize,
+        len: usize,
+    },
+}
+
+impl<T> DeviceBufferTask<T> {
+    /// Element ranges the task reads or writes
+    #[allow(clippy::single_range_in_vec_init)]
+    fn ranges(&self) -> Vec<Range<usize>> {
+        match self {
+            DeviceBufferTask::Write { start_idx, data } => vec![*start_idx..start_idx + data.len()],
+            DeviceBufferTask::Copy { src_idx, dst_idx, len } => {
+                vec![*src_idx..src_idx + len, *dst_idx..dst_idx + len]
+            }
+        }
+    }
+}
+
+/// Device local buffer that is changed through copies recorded into the frame's command buffer.
+///
+/// Changes are queued and recorded in order with `record`, the auto command buffer inserts the
+/// barriers between them and the shaders reading the buffer.
+pub struct DeviceBuffer<T>
+where
+    [T]: BufferContents,
+{
+    buffer: Subbuffer<[T]>,
+    usage: BufferUsage,
+    tasks: Vec<DeviceBufferTask<T>>,
+    /// Length of the buffer that replaces the current one on the next record
+    resize_to: Option<usize>,
+    /// Zeroed with the first record
+    cleared: bool,
+    /// The buffer changed since the last record, descriptor sets have to use the new one
+    replaced: bool,
+}
+
+impl<T> DeviceBuffer<T>
+where
+    T: BufferContents + Pod,
+    [T]: BufferContents,
+{
+    pub fn new(allocator: &StandardMemoryAllocator, usage: BufferUsage, len: usize) -> Self {
+        Self {
+            buffer: create_buffer(allocator, usage, len),
+            usage,
+            tasks: Vec::new(),
+            resize_to: None,
+            cleared: false,
+            replaced: true,
+        }
+    }
+
+    /// Length including resizing that hasn't happened yet, writes may go up to it
+    pub fn capacity(&self) -> usize {
+        self.resize_to.unwrap_or(self.buffer.len() as usize)
+    }
+
+    /// Replaces the buffer with a larger one on the next record, the contents are kept
+    pub fn grow(&mut self, new_len: usize) {
+        if new_len > self.capacity() {
+            self.resize_to = Some(new_len);
+        }
+    }
+
+    /// Replaces the buffer with a smaller one once the queued changes are recorded, the contents up to
+    /// `new_len` are kept
+    pub fn shrink(&mut self, new_len: usize) {
+        if new_len < self.capacity() {
+            self.resize_to = Some(new_len);
+        }
+    }
+
+    pub fn write(&mut self, start_idx: usize, data: &[T]) {
+        if !data.is_empty() {
+            self.tasks.push(DeviceBufferTask::Write { start_idx, data: data.to_vec() });
+        }
+    }
+
+    /// Copies `len` elements from `src_idx` to `dst_idx`, in order with the writes. The ranges can't overlap.
+    pub fn copy(&mut self, src_idx: usize, dst_idx: usize, len: usize) {
+        if len > 0 {
+            self.tasks.push(DeviceBufferTask::Copy { src_idx, dst_idx, len });
+        }
+    }
+
+    pub fn get_buffer(&self) -> Subbuffer<[T]> {
+        self.buffer.clone()
+    }
+
+    pub fn report_memory(&self, report: &mut MemoryReport, subsystem: Subsystem) {
+        report.add_buffer(subsystem, &self.buffer);
+    }
+
+    /// Records the resizing and every queued change, returns true if the buffer was replaced
+    pub fn record(
+        &mut self,
+        allocator: &StandardMemoryAllocator,
+        ring: &mut StagingRing,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> bool {
+        if !self.cleared {
+            self.cleared = true;
+            debug_assert!(mem::size_of::<T>().is_multiple_of(4));
+            // SAFETY: the buffer starts at its own allocation and its elements are made of whole words
+            cbb.fill_buffer(unsafe { self.buffer.clone().reinterpret_unchecked() }, 0).unwrap();
+        }
+
+        // Growing comes before the changes that need the space, shrinking after the copies out of the end
+        let resize_to = self.resize_to.take().filter(|len| *len != self.buffer.len() as usize);
+        let shrinking = resize_to.is_some_and(|len| len < self.buffer.len() as usize);
+        if let Some(new_len) = resize_to.filter(|_| !shrinking) {
+            self.resize(allocator, cbb, new_len);
+        }
+
+        for batch in batches(&self.tasks) {
+            let tasks = &self.tasks[batch];
+            match tasks[0] {
+                DeviceBufferTask::Write { .. } => self.record_writes(allocator, ring, cbb, tasks),
+                DeviceBufferTask::Copy { .. } => self.record_copies(cbb, tasks),
+            }
+        }
+        self.tasks.clear();
+
+        if let Some(new_len) = resize_to.filter(|_| shrinking) {
+            self.resize(allocator, cbb, new_len);
+        }
+
+        mem::take(&mut self.replaced)
+    }
+
+    fn resize(
+        &mut self,
+        allocator: &StandardMemoryAllocator,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        new_len: usize,
+    ) {
+        let old = mem::replace(&mut self.buffer, create_buffer(allocator, self.usage, new_len));
+        let kept = old.len().min(new_len as u64);
+        cbb.copy_buffer(CopyBufferInfoTyped::buffers(old.slice(0..kept), self.buffer.clone().slice(0..kept)))
+            .unwrap();
+        self.replaced = true;
+    }
+
+    /// One upload and one copy for all the writes
+    fn record_writes(
+        &self,
+        allocator: &StandardMemoryAllocator,
+        ring: &mut StagingRing,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        tasks: &[DeviceBufferTask<T>],
+    ) {
+        let mut staged = Vec::new();
+        let mut regions = Vec::new();
+        for task in tasks {
+            if let DeviceBufferTask::Write { start_idx, data } = task {
+                // Offsets are in elements for typed copies
+                regions.push(BufferCopy {
+                    src_offset: staged.len() as u64,
+                    dst_offset: *start_idx as u64,
+                    size: data.len() as u64,
+                    ..Default::default()
+                });
+                staged.extend_from_slice(data);
+            }
+        }
+
+        let staging = ring.upload(allocator, &staged);
+        cbb.copy_buffer(CopyBufferInfoTyped {
+            regions: regions.into(),
+            ..CopyBufferInfoTyped::buffers(staging, self.buffer.clone())
+        })
+        .unwrap();
+    }
+
+    fn record_copies(
+        &self,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        tasks: &[DeviceBufferTask<T>],
+    ) {
+        let regions = tasks
+            .iter()
+            .filter_map(|task| match task {
+                DeviceBufferTask::Copy { src_idx, dst_idx, len } => Some(BufferCopy {
+                    src_offset: *src_idx as u64,
+                    dst_offset: *dst_idx as u64,
+                    size: *len as u64,
+                    ..Default::default()
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        cbb.copy_buffer(CopyBufferInfoTyped {
+            regions: regions.into(),
+            ..CopyBufferInfoTyped::buffers(self.buffer.clone(), self.buffer.clone())
+        })
+        .unwrap();
+    }
+}
+
+fn create_buffer<T>(allocator: &StandardMemoryAllocator, usage: BufferUsage, len: usize) -> Subbuffer<[T]>
+where
+    T: BufferContents,
+{
+    Buffer::new_slice(
+        allocator,
+        BufferCreateInfo::usage(usage | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST),
+        AllocationCreateInfo::usage(MemoryUsage::DeviceOnly),
+        len as u64,
+    )
+    .unwrap()
+}
+
+/// Splits the tasks into runs that can each be recorded as one copy.
+///
+/// A run has tasks of one kind, and none of them touch a range another one in the run touches,
+/// since the regions of a copy happen in no particular order.
+fn batches<T>(tasks: &[DeviceBufferTask<T>]) -> Vec<Range<usize>> {
+    let mut ret = Vec::new();
+    let mut start = 0;
+    // Start to end of the ranges in the current run, they never overlap each other
+    let mut touched = BTreeMap::<usize, usize>::new();
+
+    for (i, task) in tasks.iter().enumerate() {
+        let ranges = task.ranges();
+        let overlaps = ranges.iter().any(|range| {
+            touched
+                .range(..range.end)
+                .next_back()
+                .is_some_and(|(_, end)| *end > range.start)
+        });
+
+        if i > start && (overlaps || mem::discriminant(task) != mem::discriminant(&tasks[start])) {
+            ret.push(start..i);
+            start = i;
+            touched.clear();
+        }
+
+        for range in ranges {
+            touched.insert(range.start, range.end);
+        }
+    }
+
+    if start < tasks.len() {
+        ret.push(start..tasks.len());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(start_idx: usize, len: usize) -> DeviceBufferTask<u32> {
+        DeviceBufferTask::Write { start_idx, data: vec![0; len] }
+    }
+
+    fn copy(src_idx: usize, dst_idx: usize, len: usize) -> DeviceBufferTask<u32> {
+        DeviceBufferTask::Copy { src_idx, dst_idx, len }
+    }
+
+    #[test]
+    fn batches_keep_order() {
+        assert_eq!(batches::<u32>(&[]), vec![]);
+        assert_eq!(batches(&[write(0, 4), write(4, 4), write(10, 1)]), vec![0..3]);
+
+        // Writing the same element twice needs two copies
+        assert_eq!(batches(&[write(0, 4), write(3, 1), write(8, 1)]), vec![0..1, 1..3]);
+
+        // A move of freshly written data has to wait for the write
+        assert_eq!(batches(&[write(8, 2), copy(8, 0, 2), write(8, 1)]), vec![0..1, 1..2, 2..3]);
+
+        // Moving into space another move just freed
+        assert_eq!(batches(&[copy(10, 0, 2), copy(4, 2, 2), copy(20, 10, 2)]), vec![0..2, 2..3]);
+    }
+}
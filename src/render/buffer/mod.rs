@@ -1,6 +1,7 @@
 pub mod allocator;
 pub mod buffer_queue;
-pub mod swap_buffer;
 pub mod vertex_buffer;
 pub mod upload;
-pub mod task_buffer;
\ No newline at end of file
+pub mod queued_writes;
+pub mod staging_ring;
+pub mod device_buffer;
\ No newline at end of file
@@ -0,0 +1,80 @@
+use ahash::{HashMap, HashMapExt};
+use bytemuck::Pod;
+use vulkano::{
+    buffer::{BufferContents, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferCopy, CopyBufferInfoTyped, PrimaryAutoCommandBuffer,
+    },
+    memory::allocator::StandardMemoryAllocator,
+};
+
+use super::staging_ring::StagingRing;
+
+/// Single element writes to a device local buffer.
+///
+/// Writes are collected on the CPU and recorded as one copy from the staging ring,
+/// so the GPU never has to wait for the CPU to get access to the buffer.
+pub struct QueuedWrites<T> {
+    writes: HashMap<u32, T>,
+}
+
+impl<T> Default for QueuedWrites<T> {
+    fn default() -> Self {
+        Self {
+            writes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> QueuedWrites<T>
+where
+    T: BufferContents + Pod,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Later writes to the same index replace earlier ones.
+    pub fn write(&mut self, index: u32, value: T) {
+        self.writes.insert(index, value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Records the copy of every queued write into `dst` and clears the queue.
+    pub fn record(
+        &mut self,
+        allocator: &StandardMemoryAllocator,
+        ring: &mut StagingRing,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dst: Subbuffer<[T]>,
+    ) {
+        if self.writes.is_empty() {
+            return;
+        }
+
+        let (indices, data): (Vec<u32>, Vec<T>) = self.writes.drain().unzip();
+
+        let staging = ring.upload(allocator, &data);
+
+        // Offsets are in elements for typed copies
+        let regions = indices
+            .into_iter()
+            .enumerate()
+            .map(|(i, dst_index)| BufferCopy {
+                src_offset: i as u64,
+                dst_offset: dst_index as u64,
+                size: 1,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        cbb.copy_buffer(CopyBufferInfoTyped {
+            regions: regions.into(),
+            ..CopyBufferInfoTyped::buffers(staging, dst)
+        })
+        .unwrap();
+    }
+}
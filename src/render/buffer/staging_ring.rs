@@ -0,0 +1,92 @@
+use bytemuck::Pod;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator},
+};
+
+use crate::render::{
+    memory::{MemoryReport, Subsystem},
+    util::CreateInfoConvenience,
+};
+
+/// Offsets of staged data, enough for every type that is uploaded
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Host visible memory that uploads go through on their way to device local buffers.
+///
+/// Uploads are placed one after another and wrap around at the end. An upload gets a buffer of its own
+/// instead when it would overwrite a range that is still copied from: vulkano only knows about the ranges
+/// of submitted command buffers, so the ring also refuses to lap what was staged since the last submit.
+pub struct StagingRing {
+    buffer: Subbuffer<[u8]>,
+    head: u64,
+    /// Bytes used since the last submit, including the end of the ring skipped when wrapping
+    pending: u64,
+}
+
+impl StagingRing {
+    pub fn new(allocator: &StandardMemoryAllocator, size: u64) -> Self {
+        Self {
+            buffer: Buffer::new_slice(
+                allocator,
+                BufferCreateInfo::usage(BufferUsage::TRANSFER_SRC),
+                AllocationCreateInfo::usage(MemoryUsage::Upload),
+                size,
+            )
+            .unwrap(),
+            head: 0,
+            pending: 0,
+        }
+    }
+
+    /// Call once the command buffers that copy from the staged data are submitted
+    pub fn submitted(&mut self) {
+        self.pending = 0;
+    }
+
+    /// `data` can't be empty
+    pub fn upload<T>(&mut self, allocator: &StandardMemoryAllocator, data: &[T]) -> Subbuffer<[T]>
+    where
+        T: BufferContents + Pod,
+    {
+        match self.try_write(bytemuck::cast_slice(data)) {
+            // SAFETY: staged data starts at a multiple of `STAGING_ALIGNMENT` and is a whole number of `T`s
+            Some(staged) => unsafe { staged.reinterpret_unchecked() },
+            None => Buffer::from_iter(
+                allocator,
+                BufferCreateInfo::usage(BufferUsage::TRANSFER_SRC),
+                AllocationCreateInfo::usage(MemoryUsage::Upload),
+                data.iter().copied(),
+            )
+            .unwrap(),
+        }
+    }
+
+    fn try_write(&mut self, bytes: &[u8]) -> Option<Subbuffer<[u8]>> {
+        let len = bytes.len() as u64;
+        if len == 0 || len > self.buffer.len() {
+            return None;
+        }
+
+        let wraps = self.head + len > self.buffer.len();
+        let start = if wraps { 0 } else { self.head };
+        let end = (start + len).next_multiple_of(STAGING_ALIGNMENT);
+        let used = if wraps { self.buffer.len().saturating_sub(self.head) + end } else { end - self.head };
+        // The command buffer being recorded still copies from everything staged since the last submit
+        if self.pending + used > self.buffer.len() {
+            return None;
+        }
+
+        let region = self.buffer.clone().slice(start..start + len);
+        // Fails while a submitted frame in flight still copies from the range
+        region.write().ok()?.copy_from_slice(bytes);
+
+        self.head = end;
+        self.pending += used;
+        Some(region)
+    }
+
+    pub fn report_memory(&self, report: &mut MemoryReport) {
+        report.add_buffer(Subsystem::Staging, &self.buffer);
+    }
+}
@@ -1,46 +1,127 @@
+use ahash::{HashMap, HashSet};
 use ultraviolet::{IVec2, IVec3};
-use vulkano::{buffer::BufferUsage, memory::allocator::StandardMemoryAllocator};
+use vulkano::{buffer::BufferUsage, command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer}, memory::allocator::StandardMemoryAllocator};
 
-use crate::{render::brick::{brickmap::{Brickmap, BrickmapPointer}, brickgrid::{BrickgridBufferTask, BRICKGRID_SIZE, Brickgrid, self}}, world::{chunk::{Chunk, CHUNK_HEIGHT}, section::Section, block_data::{StaticBlockData, ModelType}}, util::util::{InsertVec2, VecModPos}};
+use crate::{render::brick::{brickmap::{Brickmap, BrickmapPointer, BrickmapPointerRaw}, brickgrid::{BRICKGRID_SIZE, BG_ARRAY_SIZE, BrickgridWindow, morton_encode}}, world::{chunk::{Chunk, CHUNK_HEIGHT}, section::Section, block_data::{StaticBlockData, texture_pointer}}, util::util::{InsertVec2, VecModPos}};
 
-use super::{allocator::HeapBuffer, task_buffer::TaskBuffer};
+use crate::render::memory::{MemoryReport, Subsystem};
+
+use super::{allocator::HeapBuffer, device_buffer::DeviceBuffer, staging_ring::StagingRing};
 
 pub struct ChunkVertexBuffer {
     pub brickmap_buffer: HeapBuffer<Brickmap>,
     pub texture_pointer_buffer: HeapBuffer<u32>,
-    pub brickgrid_buffer: TaskBuffer<BrickgridBufferTask, Brickgrid>,
+    /// Starts out zeroed, which is `NotLoaded` everywhere
+    pub brickgrid_buffer: DeviceBuffer<BrickmapPointerRaw>,
+    pub window: BrickgridWindow,
+    /// Chunks that have anything other than `NotLoaded` written to the brickgrid
+    columns: HashSet<IVec2>,
+    /// Copies of the uploaded brickmaps, rewritten when their textures move
+    brickmaps: HashMap<IVec3, Brickmap>,
 }
 
 const BM_BUFFER_USAGE: BufferUsage = BufferUsage::STORAGE_BUFFER;
 
+/// Allocations each buffer may move per frame while compacting
+const COMPACTION_MOVES_PER_FRAME: usize = 64;
+
 impl ChunkVertexBuffer {
     pub fn new(allocator: &StandardMemoryAllocator) -> Self {
         Self {
             brickmap_buffer: HeapBuffer::new(
+                "brickmap",
                 allocator, BM_BUFFER_USAGE, 
-                1_000_000, 
+                65_536, 
                 0
             ),
 
             texture_pointer_buffer: HeapBuffer::new(
+                "texture pointer",
                 allocator, BM_BUFFER_USAGE, 
-                128_000_000, 
+                4_000_000, 
                 0
             ),
 
-            brickgrid_buffer: TaskBuffer::new(allocator, brickgrid::write_queue_buffer),
+            brickgrid_buffer: DeviceBuffer::new(allocator, BM_BUFFER_USAGE, BG_ARRAY_SIZE),
+            window: BrickgridWindow::centered_on(IVec2::zero()),
+            columns: HashSet::default(),
+            brickmaps: HashMap::default(),
         }
     }
 
-    pub fn update(&mut self) -> (bool, bool, bool) {
-        let bg = self.brickgrid_buffer.update();
-        let tp = self.texture_pointer_buffer.update();
-        let bm = self.brickmap_buffer.update();
+    /// Moves the brickgrid window if the camera got too close to its edge.
+    ///
+    /// Chunks that end up outside of the window are evicted, since their cells will be reused
+    /// by chunks on the opposite side. Returns true if the window moved.
+    pub fn recenter(&mut self, chunk_pos: IVec2) -> bool {
+        if !self.window.should_recenter(chunk_pos) {
+            return false;
+        }
+
+        self.window = BrickgridWindow::centered_on(chunk_pos);
+
+        let evicted = self
+            .columns
+            .iter()
+            .filter(|pos| !self.window.contains(**pos))
+            .copied()
+            .collect::<Vec<_>>();
+
+        for pos in evicted {
+            self.evict_chunk(pos);
+        }
+
+        true
+    }
+
+    pub fn has_chunk(&self, chunk_pos: IVec2) -> bool {
+        self.columns.contains(&chunk_pos)
+    }
+
+    /// Records the changes to every buffer, returns which of them were replaced
+    pub fn record_uploads(
+        &mut self,
+        allocator: &StandardMemoryAllocator,
+        ring: &mut StagingRing,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> (bool, bool, bool) {
+        let bg = self.brickgrid_buffer.record(allocator, ring, cbb);
+        let tp = self.texture_pointer_buffer.record_uploads(allocator, ring, cbb);
+        let bm = self.brickmap_buffer.record_uploads(allocator, ring, cbb);
 
         (bg, tp, bm)
     }
 
+    /// Moves a few allocations of each buffer down if they got fragmented
+    pub fn compact(&mut self) {
+        for (section_pos, allocation) in self.brickmap_buffer.compact(COMPACTION_MOVES_PER_FRAME) {
+            self.write_pointer(section_pos, BrickmapPointer::Brickmap(allocation.front));
+        }
+
+        for (section_pos, allocation) in self.texture_pointer_buffer.compact(COMPACTION_MOVES_PER_FRAME) {
+            let brickmap = self.brickmaps.get_mut(&section_pos).unwrap();
+            brickmap.textures_offset = allocation.front;
+            let brickmap = *brickmap;
+            self.brickmap_buffer.overwrite(section_pos, &[brickmap]);
+        }
+    }
+
+    pub fn report_memory(&self, report: &mut MemoryReport) {
+        self.brickmap_buffer.report_memory(report, Subsystem::Brickmaps);
+        self.texture_pointer_buffer.report_memory(report, Subsystem::TexturePointers);
+        self.brickgrid_buffer.report_memory(report, Subsystem::Brickgrid);
+    }
+
+    pub fn stats(&self) -> String {
+        format!("{}\n{}", self.brickmap_buffer.stats(), self.texture_pointer_buffer.stats())
+    }
+
     pub fn insert_chunk(&mut self, chunk: &Chunk, block_data: &StaticBlockData) {
+        if !self.window.contains(chunk.pos) {
+            return;
+        }
+
+        self.columns.insert(chunk.pos);
         for (i, section) in chunk.sections.iter().enumerate() {
             let section_pos = chunk.pos.insert_y(i as i32);
             self.insert_section(section_pos, section, block_data);
@@ -48,10 +129,35 @@ impl ChunkVertexBuffer {
     }
 
     pub fn remove_chunk(&mut self, chunk_pos: IVec2) {
+        if self.window.contains(chunk_pos) {
+            self.evict_chunk(chunk_pos);
+        }
+    }
+
+    fn evict_chunk(&mut self, chunk_pos: IVec2) {
+        self.columns.remove(&chunk_pos);
         for i in 0..CHUNK_HEIGHT as i32 {
             let section_pos = chunk_pos.insert_y(i);
             if self.brickmap_buffer.allocations.contains_key(&section_pos) {
                 self.remove_section(section_pos);
+            } else {
+                // Empty sections have no allocation but still need to be marked as unloaded
+                self.write_pointer(section_pos, BrickmapPointer::NotLoaded);
+            }
+        }
+    }
+
+    /// Marks the sections of a chunk so the shader stops requesting them until they are inserted.
+    pub fn mark_requested(&mut self, chunk_pos: IVec2) {
+        if !self.window.contains(chunk_pos) {
+            return;
+        }
+
+        self.columns.insert(chunk_pos);
+        for i in 0..CHUNK_HEIGHT as i32 {
+            let section_pos = chunk_pos.insert_y(i);
+            if !self.has_section(section_pos) {
+                self.write_pointer(section_pos, BrickmapPointer::Requested);
             }
         }
     }
@@ -66,38 +172,52 @@ impl ChunkVertexBuffer {
             self.remove_section(section_pos);
         }
 
-        let mut brickmap = section.brickmap;
+        let brickmap = section.brickmap;
         let ptr = if brickmap.is_empty() {
             BrickmapPointer::Empty
         } else {
-            let block_textures = section.blocks.iter().filter_map(|b| {
-                match &block_data.get(b).model {
-                    ModelType::FullBlock(_) => Some(b.inner()),
-                    _ => None,
-                }
-            }).collect::<Vec<_>>();
-            brickmap.textures_offset = self.texture_pointer_buffer.insert(section_pos, &block_textures).front;
-
-            let allocation = self.brickmap_buffer.insert(section_pos, &[brickmap]);
-            BrickmapPointer::Brickmap(allocation.front)
+            let block_textures = section.blocks.iter()
+                .filter_map(|b| texture_pointer(*b, block_data.get(b)))
+                .collect::<Vec<_>>();
+            self.insert_brickmap(section_pos, brickmap, &block_textures)
         };
 
-        let raw_ptr = ptr.to_raw();
-        let m_pos = section_pos.mod_pos(BRICKGRID_SIZE.into());
+        self.write_pointer(section_pos, ptr);
+    }
+
+    /// Falls back to the LOD color if the buffers can't grow any further
+    fn insert_brickmap(&mut self, section_pos: IVec3, mut brickmap: Brickmap, block_textures: &[u32]) -> BrickmapPointer {
+        let Some(textures) = self.texture_pointer_buffer.insert(section_pos, block_textures) else {
+            println!("WARNING: The texture pointer buffer is full, {:?} is drawn as LOD", section_pos);
+            return BrickmapPointer::Lod(brickmap.lod_color);
+        };
+        brickmap.textures_offset = textures.front;
+
+        let Some(allocation) = self.brickmap_buffer.insert(section_pos, &[brickmap]) else {
+            println!("WARNING: The brickmap buffer is full, {:?} is drawn as LOD", section_pos);
+            self.texture_pointer_buffer.remove(section_pos);
+            return BrickmapPointer::Lod(brickmap.lod_color);
+        };
 
-        self.brickgrid_buffer.write(BrickgridBufferTask::One { pos: m_pos, section: raw_ptr });
+        self.brickmaps.insert(section_pos, brickmap);
+        BrickmapPointer::Brickmap(allocation.front)
     }
 
     pub fn remove_section(&mut self, section_pos: IVec3) {
         self.brickmap_buffer.remove(section_pos);
         self.texture_pointer_buffer.remove(section_pos);
+        self.brickmaps.remove(&section_pos);
+
+        self.write_pointer(section_pos, BrickmapPointer::NotLoaded);
+    }
 
-        let raw_ptr = BrickmapPointer::Empty.to_raw();
+    fn write_pointer(&mut self, section_pos: IVec3, ptr: BrickmapPointer) {
         let m_pos = section_pos.mod_pos(BRICKGRID_SIZE.into());
-        self.brickgrid_buffer.write(BrickgridBufferTask::One { pos: m_pos, section: raw_ptr });
+        let index = morton_encode(m_pos.x, m_pos.y, m_pos.z);
+        self.brickgrid_buffer.write(index, &[ptr.to_raw()]);
     }
 
     pub fn has_section(&self, section_pos: IVec3) -> bool {
-        self.brickmap_buffer.allocations.get(&section_pos).is_some()
+        self.brickmap_buffer.allocations.contains_key(&section_pos)
     }
 }
\ No newline at end of file
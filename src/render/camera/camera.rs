@@ -1,6 +1,6 @@
 use std::{
     f32::consts::PI,
-    simd::{f32x4, SimdPartialOrd},
+    simd::{f32x4, prelude::SimdPartialOrd},
 };
 
 use ultraviolet::{Isometry3, Mat4, Rotor3, Vec3};
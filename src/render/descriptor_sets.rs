@@ -1,6 +1,5 @@
 use std::sync::Arc;
 
-use bytemuck::Zeroable;
 use vulkano::{
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
@@ -11,10 +10,14 @@ use vulkano::{
     sampler::Sampler,
 };
 
-use crate::world::block_data::{BlockTexture, ModelType, StaticBlockData};
+use crate::world::block_data::{BlockTexture, StaticBlockData};
 
 use super::{
-    brick::{brickgrid::Brickgrid, brickmap::Brickmap},
+    accumulation::{buffers::SurfelBuffers, surfel::SurfelRaw},
+    brick::{
+        brickmap::{Brickmap, BrickmapPointerRaw},
+        feedback::{Feedback, FeedbackBuffers},
+    },
     buffer::upload::UploadDescriptorSet,
     mesh::quad::TexelTexture,
     renderer::{Pipelines, View},
@@ -33,8 +36,15 @@ pub struct DescriptorSets {
     pub program_info: UploadDescriptorSet<Subbuffer<ProgramInfo>>,
 
     pub brickmap: UploadDescriptorSet<Subbuffer<[Brickmap]>>,
-    pub brickgrid: UploadDescriptorSet<Subbuffer<Brickgrid>>,
+    pub brickgrid: UploadDescriptorSet<Subbuffer<[BrickmapPointerRaw]>>,
     pub texture_buffer: UploadDescriptorSet<Subbuffer<[u32]>>,
+
+    pub surfel_buffer: UploadDescriptorSet<Subbuffer<[SurfelRaw]>>,
+    pub surfel_grid: UploadDescriptorSet<Subbuffer<[u32]>>,
+    pub surfel_map: UploadDescriptorSet<Subbuffer<[u32]>>,
+
+    /// One per swapchain image
+    pub feedback: Vec<UploadDescriptorSet<Subbuffer<Feedback>>>,
 }
 
 impl DescriptorSets {
@@ -47,6 +57,8 @@ impl DescriptorSets {
         texture_atlas: &TextureAtlas,
         block_data: &StaticBlockData,
         sampler: Arc<Sampler>,
+        surfel_buffers: &SurfelBuffers,
+        feedback_buffers: &FeedbackBuffers,
     ) -> Self {
         let raytracing_layouts = pipelines.layout.set_layouts();
 
@@ -76,10 +88,7 @@ impl DescriptorSets {
             cbb,
             BufferUsage::STORAGE_BUFFER,
             // Needless `into_iter()`
-            block_data.block_data().iter().map(|b| match &b.model {
-                ModelType::FullBlock(m) => BlockTexture::from(m.clone()),
-                _ => BlockTexture::zeroed(),
-            }),
+            block_data.block_data().iter().map(|b| b.model.textures()),
         );
 
         let block_texture_map = UploadDescriptorSet::new(
@@ -133,10 +142,11 @@ impl DescriptorSets {
             descriptor_set_allocator,
             raytracing_layouts[5].clone(),
             0,
-            Buffer::new_sized(
+            Buffer::new_slice(
                 memory_allocator,
                 BufferCreateInfo::usage(BufferUsage::STORAGE_BUFFER),
                 AllocationCreateInfo::usage(MemoryUsage::Upload),
+                1,
             )
             .unwrap(),
         );
@@ -154,6 +164,40 @@ impl DescriptorSets {
             .unwrap(),
         );
 
+        let surfel_buffer = UploadDescriptorSet::new(
+            descriptor_set_allocator,
+            raytracing_layouts[8].clone(),
+            0,
+            surfel_buffers.surfel_buffer.inner.clone(),
+        );
+
+        let surfel_grid = UploadDescriptorSet::new(
+            descriptor_set_allocator,
+            raytracing_layouts[9].clone(),
+            0,
+            surfel_buffers.surfel_grid_buffer.clone(),
+        );
+
+        let surfel_map = UploadDescriptorSet::new(
+            descriptor_set_allocator,
+            raytracing_layouts[10].clone(),
+            0,
+            surfel_buffers.surfel_mapper.buffer.clone(),
+        );
+
+        let feedback = feedback_buffers
+            .buffers
+            .iter()
+            .map(|buffer| {
+                UploadDescriptorSet::new(
+                    descriptor_set_allocator,
+                    raytracing_layouts[11].clone(),
+                    0,
+                    buffer.clone(),
+                )
+            })
+            .collect();
+
         Self {
             atlas,
             atlas_map,
@@ -163,16 +207,23 @@ impl DescriptorSets {
             brickmap,
             brickgrid,
             texture_buffer,
+            surfel_buffer,
+            surfel_grid,
+            surfel_map,
+            feedback,
         }
     }
 
+    /// Binds every set, shared by the raytracing and the surfel pipeline.
     pub fn bind_raytracing(
         &self,
         cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        bind_point: PipelineBindPoint,
         pipeline_layout: Arc<PipelineLayout>,
+        image_index: usize,
     ) {
         cbb.bind_descriptor_sets(
-            PipelineBindPoint::Graphics,
+            bind_point,
             // Redundant clone.
             pipeline_layout,
             0,
@@ -185,6 +236,10 @@ impl DescriptorSets {
                 self.brickgrid.set.clone(),
                 self.texture_buffer.set.clone(),
                 self.block_texture_map.set.clone(),
+                self.surfel_buffer.set.clone(),
+                self.surfel_grid.set.clone(),
+                self.surfel_map.set.clone(),
+                self.feedback[image_index % self.feedback.len()].set.clone(),
             ],
         );
     }
@@ -0,0 +1,280 @@
+use std::{env, fmt, sync::Arc};
+
+use vulkano::{
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        DeviceExtensions, Features, QueueFlags,
+    },
+    instance::{Instance, InstanceCreateInfo},
+    memory::MemoryHeapFlags,
+    swapchain::Surface,
+    VulkanLibrary,
+};
+
+use crate::settings::Settings;
+
+/// Overrides the `device` setting
+pub const DEVICE_ENV_VAR: &str = "VK_VOXEL_DEVICE";
+
+/// Picks a device instead of the automatic choice
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Position in `--list-devices`
+    Index(usize),
+    Type(PhysicalDeviceType),
+    /// Part of the device name, ignoring case
+    Name(String),
+}
+
+impl DeviceSelector {
+    pub fn parse(input: &str) -> Self {
+        let input = input.trim();
+        if let Ok(index) = input.parse() {
+            return Self::Index(index);
+        }
+
+        match input.to_lowercase().as_str() {
+            "discrete" => Self::Type(PhysicalDeviceType::DiscreteGpu),
+            "integrated" => Self::Type(PhysicalDeviceType::IntegratedGpu),
+            "virtual" => Self::Type(PhysicalDeviceType::VirtualGpu),
+            "cpu" => Self::Type(PhysicalDeviceType::Cpu),
+            name => Self::Name(name.to_string()),
+        }
+    }
+
+    /// From `DEVICE_ENV_VAR` or the settings, `None` chooses automatically
+    pub fn from_env_or_settings(settings: &Settings) -> Option<Self> {
+        env::var(DEVICE_ENV_VAR)
+            .ok()
+            .or_else(|| settings.device.clone())
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| Self::parse(&s))
+    }
+
+    fn matches(&self, index: usize, physical: &PhysicalDevice) -> bool {
+        let properties = physical.properties();
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Type(ty) => properties.device_type == *ty,
+            Self::Name(name) => properties.device_name.to_lowercase().contains(name),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(i) => write!(f, "device {}", i),
+            Self::Type(ty) => write!(f, "a {:?} device", ty),
+            Self::Name(name) => write!(f, "a device named \"{}\"", name),
+        }
+    }
+}
+
+pub struct QueueFamilyIndices {
+    pub graphics: u32,
+}
+
+struct FeatureRequirement {
+    name: &'static str,
+    features: Features,
+    required: bool,
+    /// What the feature is used for
+    usage: &'static str,
+}
+
+fn feature_requirements() -> [FeatureRequirement; 1] {
+    [
+        FeatureRequirement {
+            name: "fragment_stores_and_atomics",
+            features: Features {
+                fragment_stores_and_atomics: true,
+                ..Features::empty()
+            },
+            required: true,
+            usage: "the fragment shader writes chunk and surfel requests to the feedback buffer",
+        },
+    ]
+}
+
+pub fn required_extensions() -> DeviceExtensions {
+    DeviceExtensions {
+        khr_swapchain: true,
+        ..DeviceExtensions::empty()
+    }
+}
+
+/// The required features plus every optional one the device supports.
+///
+/// Fails with the missing required features.
+pub fn negotiate_features(physical: &PhysicalDevice) -> Result<Features, String> {
+    let supported = physical.supported_features();
+    let mut enabled = Features::empty();
+    let mut missing = Vec::new();
+
+    for requirement in feature_requirements() {
+        if supported.contains(&requirement.features) {
+            enabled = enabled.union(&requirement.features);
+        } else if requirement.required {
+            missing.push(format!("{} ({})", requirement.name, requirement.usage));
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(enabled)
+    } else {
+        Err(format!("missing required features: {}", missing.join(", ")))
+    }
+}
+
+/// Checks everything the renderer needs from a device
+fn check_device(physical: &PhysicalDevice, surface: &Surface) -> Result<QueueFamilyIndices, String> {
+    let extensions = required_extensions();
+    if !physical.supported_extensions().contains(&extensions) {
+        let missing = extensions.difference(physical.supported_extensions());
+        return Err(format!("missing required extensions: {:?}", missing));
+    }
+
+    negotiate_features(physical)?;
+
+    let graphics = physical
+        .queue_family_properties()
+        .iter()
+        .enumerate()
+        .find(|(i, q)| {
+            q.queue_flags.contains(QueueFlags::GRAPHICS)
+                && physical.surface_support(*i as u32, surface).unwrap_or(false)
+        })
+        .map(|(i, _)| i)
+        .ok_or("no graphics queue that can present to the window")?;
+
+    Ok(QueueFamilyIndices {
+        graphics: graphics as u32,
+    })
+}
+
+/// Uses the device matching `selector`, or the best suitable device if there is none.
+pub fn select_physical_device(
+    instance: &Arc<Instance>,
+    surface: &Surface,
+    selector: Option<&DeviceSelector>,
+) -> Result<(Arc<PhysicalDevice>, QueueFamilyIndices), String> {
+    let devices = instance
+        .enumerate_physical_devices()
+        .map_err(|e| format!("Could not enumerate devices: {}", e))?
+        .collect::<Vec<_>>();
+
+    if let Some(selector) = selector {
+        let (_, physical) = devices
+            .iter()
+            .enumerate()
+            .find(|(i, p)| selector.matches(*i, p))
+            .ok_or_else(|| format!("No device is {}, run with --list-devices to see them", selector))?;
+
+        return match check_device(physical, surface) {
+            Ok(queues) => Ok((physical.clone(), queues)),
+            Err(e) => Err(format!("{} can't be used: {}", physical.properties().device_name, e)),
+        };
+    }
+
+    let mut rejected = Vec::new();
+    devices
+        .iter()
+        .filter_map(|p| match check_device(p, surface) {
+            Ok(queues) => Some((p.clone(), queues)),
+            Err(e) => {
+                rejected.push(format!("    {}: {}", p.properties().device_name, e));
+                None
+            }
+        })
+        .min_by_key(|(p, _)| device_type_rank(p.properties().device_type))
+        .ok_or_else(|| format!("No usable device found\n{}", rejected.join("\n")))
+}
+
+fn device_type_rank(device_type: PhysicalDeviceType) -> u32 {
+    match device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        _ => 4,
+    }
+}
+
+/// Capabilities of every device, for `--list-devices`.
+///
+/// Presenting to a window can only be checked once there is one.
+pub fn device_report() -> Result<String, String> {
+    let library = VulkanLibrary::new().map_err(|e| format!("Could not load Vulkan: {}", e))?;
+    let instance = Instance::new(library, InstanceCreateInfo::default())
+        .map_err(|e| format!("Could not create a Vulkan instance: {}", e))?;
+
+    let devices = instance
+        .enumerate_physical_devices()
+        .map_err(|e| format!("Could not enumerate devices: {}", e))?;
+
+    let mut out = String::new();
+    for (i, physical) in devices.enumerate() {
+        let properties = physical.properties();
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        out += &format!("[{}] {}\n", i, properties.device_name);
+        out += &format!("    type: {:?}\n", properties.device_type);
+        out += &format!("    api version: {}\n", physical.api_version());
+        out += &format!("    driver version: {}\n", properties.driver_version);
+
+        let device_local = physical
+            .memory_properties()
+            .memory_heaps
+            .iter()
+            .filter(|h| h.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|h| h.size)
+            .sum::<u64>();
+        out += &format!("    device local memory: {} MiB\n", device_local / (1024 * 1024));
+        out += &format!("    max storage buffer range: {} MiB\n", properties.max_storage_buffer_range / (1024 * 1024));
+
+        let has_graphics = physical
+            .queue_family_properties()
+            .iter()
+            .any(|q| q.queue_flags.contains(QueueFlags::GRAPHICS));
+        out += &format!("    graphics queue: {}\n", yes_no(has_graphics));
+        out += &format!(
+            "    swapchain extension: {}\n",
+            yes_no(physical.supported_extensions().contains(&required_extensions())),
+        );
+
+        for requirement in feature_requirements() {
+            out += &format!(
+                "    {} ({}): {}\n",
+                requirement.name,
+                if requirement.required { "required" } else { "optional" },
+                yes_no(physical.supported_features().contains(&requirement.features)),
+            );
+        }
+
+        let usable = has_graphics
+            && physical.supported_extensions().contains(&required_extensions())
+            && negotiate_features(&physical).is_ok();
+        out += &format!("    usable: {}\n", yes_no(usable));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_selectors() {
+        assert_eq!(DeviceSelector::parse("1"), DeviceSelector::Index(1));
+        assert_eq!(
+            DeviceSelector::parse("Integrated"),
+            DeviceSelector::Type(PhysicalDeviceType::IntegratedGpu),
+        );
+        assert_eq!(
+            DeviceSelector::parse(" GeForce RTX "),
+            DeviceSelector::Name("geforce rtx".to_string()),
+        );
+    }
+}
@@ -3,6 +3,10 @@ use std::time::Instant;
 const TIME_AVG: f64 = 0.5;
 
 pub struct FpsLog {
+    /// Averaged over the last `TIME_AVG` seconds
+    pub fps: f64,
+    /// Average frame time in seconds
+    pub frame_time: f64,
     frame_times: Vec<f64>,
     prev_frame: Instant,
     last_log: Instant,
@@ -17,6 +21,8 @@ impl Default for FpsLog {
 impl FpsLog {
     pub fn new() -> Self {
         Self {
+            fps: 0.0,
+            frame_time: 0.0,
             frame_times: Vec::with_capacity(30),
             prev_frame: Instant::now(),
             last_log: Instant::now(),
@@ -31,7 +37,8 @@ impl FpsLog {
         if (Instant::now() - self.last_log).as_secs_f64() >= TIME_AVG {
             let mut sum = 0.0;
             self.frame_times.iter().for_each(|t| sum += t);
-            println!("Avg. FPS: {:.2}", self.frame_times.len() as f64 / sum);
+            self.fps = self.frame_times.len() as f64 / sum;
+            self.frame_time = sum / self.frame_times.len() as f64;
             self.last_log = Instant::now();
             self.frame_times.clear();
         }
@@ -0,0 +1,320 @@
+use std::fmt::Write;
+
+use bytemuck::Pod;
+
+use crate::world::{
+    block_data::{BlockType, Shape, MATERIAL_SHIFT, SHAPE_SHIFT},
+    section::SECTION_SIZE,
+};
+
+use super::{
+    accumulation::surfel::SurfelRaw,
+    brick::{
+        brickgrid::BRICKGRID_SIZE,
+        brickmap::{Brickmap, BrickmapPointer},
+        feedback::{Feedback, FEEDBACK_SIZE, SURFEL_FEEDBACK_SIZE},
+    },
+    mesh::quad::TexelTexture,
+    renderer::View,
+    util::{DebugMode, ProgramInfo},
+};
+
+/// Included by the shaders, generated by `glsl_header`.
+/// Run `UPDATE_GPU_TYPES=1 cargo test` after changing any type or constant shared with them.
+pub const GPU_TYPES_PATH: &str = "./src/shaders/gpu_types.comp";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GlslType {
+    Uint,
+    Float,
+    UVec2,
+    IVec2,
+    Vec3,
+    IVec4,
+    Vec4,
+    Mat4,
+    Array(&'static GlslType, usize),
+}
+
+impl GlslType {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Uint => "uint",
+            Self::Float => "float",
+            Self::UVec2 => "uvec2",
+            Self::IVec2 => "ivec2",
+            Self::Vec3 => "vec3",
+            Self::IVec4 => "ivec4",
+            Self::Vec4 => "vec4",
+            Self::Mat4 => "mat4",
+            Self::Array(element, _) => element.name(),
+        }
+    }
+
+    fn align(&self, layout: Layout) -> usize {
+        match self {
+            Self::Uint | Self::Float => 4,
+            Self::UVec2 | Self::IVec2 => 8,
+            Self::Vec3 | Self::Vec4 | Self::IVec4 | Self::Mat4 => 16,
+            Self::Array(element, _) => layout.array_align(element.align(layout)),
+        }
+    }
+
+    fn size(&self, layout: Layout) -> usize {
+        match self {
+            Self::Uint | Self::Float => 4,
+            Self::UVec2 | Self::IVec2 => 8,
+            Self::Vec3 => 12,
+            Self::Vec4 | Self::IVec4 => 16,
+            Self::Mat4 => 64,
+            Self::Array(element, len) => {
+                let stride = round_up(element.size(layout), self.align(layout));
+                stride * len
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Uniform buffers
+    Std140,
+    /// Storage buffers
+    Std430,
+}
+
+impl Layout {
+    fn array_align(&self, element_align: usize) -> usize {
+        match self {
+            Self::Std140 => round_up(element_align, 16),
+            Self::Std430 => element_align,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpuStructKind {
+    /// A `struct`, used in arrays
+    Struct,
+    /// The members of an interface block, written as a `<NAME>_FIELDS` macro
+    /// since the block declarations carry their own set and binding
+    UniformBlock,
+    StorageBlock,
+}
+
+impl GpuStructKind {
+    pub fn layout(&self) -> Layout {
+        match self {
+            Self::UniformBlock => Layout::Std140,
+            Self::Struct | Self::StorageBlock => Layout::Std430,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GpuField {
+    pub name: &'static str,
+    pub ty: GlslType,
+    /// Where the field is on the Rust side, the generated GLSL has to agree
+    pub rust_offset: usize,
+}
+
+/// A `#[repr(C)]` type the shaders read or write.
+///
+/// Padding fields that only exist to satisfy the GLSL layout rules are left out of `fields`.
+pub trait GpuStruct: Pod {
+    const GLSL_NAME: &'static str;
+    const KIND: GpuStructKind;
+
+    fn fields() -> Vec<GpuField>;
+
+    /// Offsets of `fields` under the GLSL layout rules
+    fn glsl_offsets() -> Vec<usize> {
+        let layout = Self::KIND.layout();
+        let mut offset = 0;
+
+        Self::fields()
+            .iter()
+            .map(|f| {
+                let aligned = round_up(offset, f.ty.align(layout));
+                offset = aligned + f.ty.size(layout);
+                aligned
+            })
+            .collect()
+    }
+
+    /// Size of the type in GLSL, padded to its alignment like the Rust type
+    fn glsl_size() -> usize {
+        let layout = Self::KIND.layout();
+        let fields = Self::fields();
+        let end = match (fields.last(), Self::glsl_offsets().last()) {
+            (Some(f), Some(offset)) => offset + f.ty.size(layout),
+            _ => 0,
+        };
+
+        let align = fields.iter().map(|f| f.ty.align(layout)).max().unwrap_or(1);
+        round_up(end, align)
+    }
+}
+
+/// Lists the fields of a type for `GpuStruct::fields`, with their Rust offsets.
+///
+/// `field as "name": GlslType::...` renames a field on the GLSL side.
+macro_rules! gpu_fields {
+    ($ty:ty { $($field:ident $(as $glsl_name:literal)?: $glsl_ty:expr),* $(,)? }) => {{
+        let value = <$ty as bytemuck::Zeroable>::zeroed();
+        let base = std::ptr::addr_of!(value) as usize;
+        vec![$(
+            $crate::render::gpu_layout::GpuField {
+                name: $crate::render::gpu_layout::gpu_fields!(@name $field $($glsl_name)?),
+                ty: $glsl_ty,
+                rust_offset: std::ptr::addr_of!(value.$field) as usize - base,
+            }
+        ),*]
+    }};
+    (@name $field:ident $glsl_name:literal) => { $glsl_name };
+    (@name $field:ident) => { stringify!($field) };
+}
+
+pub(crate) use gpu_fields;
+
+/// Constants the shaders share with the CPU
+fn defines() -> Vec<(String, String)> {
+    let [bx, by, bz] = BRICKGRID_SIZE;
+    let pointer_flags = |ptr: BrickmapPointer| ptr.to_raw().pointer.to_string();
+
+    let mut defines = [
+        ("SECTION_SIZE", format!("uvec3({}, {}, {})", SECTION_SIZE.x, SECTION_SIZE.y, SECTION_SIZE.z)),
+        ("BRICKGRID_SIZE", format!("uvec3({}, {}, {})", bx, by, bz)),
+        ("POINTER_NOT_LOADED", pointer_flags(BrickmapPointer::NotLoaded)),
+        ("POINTER_REQUESTED", pointer_flags(BrickmapPointer::Requested)),
+        ("POINTER_EMPTY", pointer_flags(BrickmapPointer::Empty)),
+        ("POINTER_LOD", pointer_flags(BrickmapPointer::Lod([0; 3]))),
+        ("POINTER_BRICKMAP", pointer_flags(BrickmapPointer::Brickmap(0))),
+        ("FEEDBACK_SIZE", FEEDBACK_SIZE.to_string()),
+        ("SURFEL_FEEDBACK_SIZE", SURFEL_FEEDBACK_SIZE.to_string()),
+        ("MATERIAL_SHIFT", MATERIAL_SHIFT.to_string()),
+        ("MATERIAL_OPAQUE", BlockType::Full.material().to_string()),
+        ("MATERIAL_ALPHA_TEST", BlockType::Transparent.material().to_string()),
+        ("MATERIAL_TRANSLUCENT", BlockType::Translucent.material().to_string()),
+        ("SHAPE_SHIFT", SHAPE_SHIFT.to_string()),
+        ("SHAPE_CUBE", Shape::Cube.id().to_string()),
+        ("SHAPE_CROSS", Shape::Cross.id().to_string()),
+        ("SHAPE_SLAB_BOTTOM", Shape::SlabBottom.id().to_string()),
+        ("SHAPE_SLAB_TOP", Shape::SlabTop.id().to_string()),
+    ]
+    .map(|(name, value)| (name.to_string(), value))
+    .to_vec();
+
+    for mode in DebugMode::ALL {
+        let name = format!("DEBUG_{}", mode.name().to_uppercase());
+        defines.push((name, mode.id().to_string()));
+    }
+    defines
+}
+
+/// Contents of `GPU_TYPES_PATH`
+pub fn glsl_header() -> String {
+    let mut out = String::new();
+    writeln!(out, "// Generated from the Rust types by `render::gpu_layout`, do not edit.").unwrap();
+    writeln!(out, "// Run `UPDATE_GPU_TYPES=1 cargo test` to regenerate.").unwrap();
+
+    out.push('\n');
+    for (name, value) in defines() {
+        writeln!(out, "#define {} {}", name, value).unwrap();
+    }
+
+    write_struct::<TexelTexture>(&mut out);
+    write_struct::<Brickmap>(&mut out);
+    write_struct::<SurfelRaw>(&mut out);
+    write_struct::<View>(&mut out);
+    write_struct::<ProgramInfo>(&mut out);
+    write_struct::<Feedback>(&mut out);
+
+    out
+}
+
+fn write_struct<T: GpuStruct>(out: &mut String) {
+    let declarations = T::fields()
+        .iter()
+        .map(|f| match f.ty {
+            GlslType::Array(_, len) => format!("{} {}[{}];", f.ty.name(), f.name, len),
+            _ => format!("{} {};", f.ty.name(), f.name),
+        })
+        .collect::<Vec<_>>();
+
+    out.push('\n');
+    match T::KIND {
+        GpuStructKind::Struct => {
+            writeln!(out, "struct {} {{", T::GLSL_NAME).unwrap();
+            for d in declarations {
+                writeln!(out, "    {}", d).unwrap();
+            }
+            writeln!(out, "}};").unwrap();
+        }
+        GpuStructKind::UniformBlock | GpuStructKind::StorageBlock => {
+            writeln!(out, "#define {}_FIELDS \\", upper_snake_case(T::GLSL_NAME)).unwrap();
+            let last = declarations.len() - 1;
+            for (i, d) in declarations.iter().enumerate() {
+                let continuation = if i == last { "" } else { " \\" };
+                writeln!(out, "    {}{}", d, continuation).unwrap();
+            }
+        }
+    }
+}
+
+fn upper_snake_case(name: &str) -> String {
+    let mut ret = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            ret.push('_');
+        }
+        ret.push(c.to_ascii_uppercase());
+    }
+    ret
+}
+
+fn round_up(n: usize, align: usize) -> usize {
+    n.next_multiple_of(align)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, mem::size_of};
+
+    use super::*;
+
+    fn check_layout<T: GpuStruct>() {
+        let rust_offsets = T::fields().iter().map(|f| f.rust_offset).collect::<Vec<_>>();
+        assert_eq!(rust_offsets, T::glsl_offsets(), "Field offsets of {}", T::GLSL_NAME);
+        assert_eq!(size_of::<T>(), T::glsl_size(), "Size of {}", T::GLSL_NAME);
+    }
+
+    #[test]
+    fn layouts_match_rust() {
+        check_layout::<TexelTexture>();
+        check_layout::<Brickmap>();
+        check_layout::<SurfelRaw>();
+        check_layout::<View>();
+        check_layout::<ProgramInfo>();
+        check_layout::<Feedback>();
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let header = glsl_header();
+        if env::var_os("UPDATE_GPU_TYPES").is_some() {
+            fs::write(GPU_TYPES_PATH, &header).unwrap();
+        }
+
+        let current = fs::read_to_string(GPU_TYPES_PATH).unwrap_or_default();
+        assert!(current == header, "{} is out of date, run `UPDATE_GPU_TYPES=1 cargo test`", GPU_TYPES_PATH);
+    }
+
+    #[test]
+    fn std140_pads_arrays() {
+        const ARRAY: GlslType = GlslType::Array(&GlslType::Uint, 4);
+        assert_eq!(ARRAY.size(Layout::Std140), 64);
+        assert_eq!(ARRAY.size(Layout::Std430), 16);
+    }
+}
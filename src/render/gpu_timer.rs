@@ -0,0 +1,122 @@
+use std::{sync::Arc, time::Duration};
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    device::{physical::PhysicalDevice, Device},
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+
+use crate::profiler;
+
+/// Points in the render command buffer a timestamp is written at
+#[derive(Clone, Copy, Debug)]
+pub enum GpuMark {
+    FrameStart = 0,
+    UploadsDone = 1,
+    SurfelsDone = 2,
+    PassDone = 3,
+}
+
+const MARK_COUNT: u32 = 4;
+
+/// Spans reported to the profiler, between two marks
+const SPANS: [(&str, GpuMark, GpuMark); 3] = [
+    ("uploads", GpuMark::FrameStart, GpuMark::UploadsDone),
+    ("surfels", GpuMark::UploadsDone, GpuMark::SurfelsDone),
+    ("raytracing pass", GpuMark::SurfelsDone, GpuMark::PassDone),
+];
+
+/// Timestamp queries around the parts of a frame.
+///
+/// Every swapchain image has its own pool, its results are read the next time the image is
+/// recorded, when the GPU is usually done with it.
+pub struct GpuTimer {
+    pools: Vec<Arc<QueryPool>>,
+    /// Profiler frame each pool was last recorded in
+    pending: Vec<Option<u64>>,
+    /// Nanoseconds per tick
+    timestamp_period: f64,
+    valid_mask: u64,
+}
+
+impl GpuTimer {
+    /// `None` if the queue family can't write timestamps
+    pub fn new(
+        device: Arc<Device>,
+        physical: &PhysicalDevice,
+        queue_family_index: u32,
+        image_count: usize,
+    ) -> Option<Self> {
+        let valid_bits = physical.queue_family_properties()[queue_family_index as usize].timestamp_valid_bits?;
+
+        let pools = (0..image_count)
+            .map(|_| {
+                QueryPool::new(
+                    device.clone(),
+                    QueryPoolCreateInfo {
+                        query_count: MARK_COUNT,
+                        ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| println!("WARNING: GPU timings are disabled: {}", e))
+            .ok()?;
+
+        Some(Self {
+            pending: vec![None; pools.len()],
+            pools,
+            timestamp_period: physical.properties().timestamp_period as f64,
+            valid_mask: if valid_bits >= 64 { u64::MAX } else { (1 << valid_bits) - 1 },
+        })
+    }
+
+    /// Reports the previous results of the image's pool and resets it, must be outside of a render pass
+    pub fn begin(&mut self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, image_index: usize) {
+        let pool = self.pools[image_index].clone();
+
+        if let Some(frame) = self.pending[image_index].take() {
+            let mut ticks = [0u64; MARK_COUNT as usize];
+            let ready = pool
+                .queries_range(0..MARK_COUNT)
+                .unwrap()
+                .get_results(&mut ticks, QueryResultFlags::empty());
+
+            // Not ready means the GPU is far behind, that frame is left without GPU timings
+            if let Ok(true) = ready {
+                let to_duration = |from: GpuMark, to: GpuMark| {
+                    let delta = ticks[to as usize].wrapping_sub(ticks[from as usize]) & self.valid_mask;
+                    Duration::from_nanos((delta as f64 * self.timestamp_period) as u64)
+                };
+
+                for (name, from, to) in SPANS {
+                    profiler::record_gpu(frame, name, to_duration(GpuMark::FrameStart, from), to_duration(from, to));
+                }
+            }
+        }
+
+        unsafe {
+            builder.reset_query_pool(pool, 0..MARK_COUNT).unwrap();
+        }
+        self.pending[image_index] = Some(profiler::current_frame());
+    }
+
+    pub fn mark(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image_index: usize,
+        mark: GpuMark,
+    ) {
+        let stage = match mark {
+            GpuMark::FrameStart => PipelineStage::TopOfPipe,
+            _ => PipelineStage::BottomOfPipe,
+        };
+
+        unsafe {
+            builder
+                .write_timestamp(self.pools[image_index].clone(), mark as u32, stage)
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{mesh::quad::TexelTexture, texture::TextureAtlas, vertex::HudVertex};
+
+/// `resources/font.png`, printable ASCII in rows of 16 starting at the space
+const FONT_TEXTURE: &str = "font";
+const FONT_COLUMNS: u32 = 16;
+const FIRST_CHAR: u32 = b' ' as u32;
+/// Glyphs sit in the top left of their cell, the rest is spacing
+const GLYPH_SIZE: [u32; 2] = [3, 5];
+const CELL_SIZE: [u32; 2] = [4, 6];
+
+/// Screen pixels per font pixel
+const TEXT_SCALE: f32 = 3.0;
+/// Screen pixels between the text and the window border
+const MARGIN: f32 = 8.0;
+const LINE_SPACING: f32 = 1.0 * TEXT_SCALE;
+
+const CROSSHAIR_SIZE: f32 = 16.0;
+const CROSSHAIR_THICKNESS: f32 = 2.0;
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.4];
+const CROSSHAIR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+
+/// 2D overlay drawn on top of the raytraced image.
+///
+/// Lines are pushed every frame and cleared once they are drawn.
+pub struct Hud {
+    lines: Vec<(String, [f32; 4])>,
+    font: TexelTexture,
+    /// For solid rectangles
+    white: TexelTexture,
+}
+
+impl Hud {
+    pub fn new(atlas: &TextureAtlas) -> Self {
+        let texture = |name| atlas.get_uv(atlas.get_handle(name).unwrap_or_else(|| panic!("Missing texture {}", name)));
+        Self {
+            lines: Vec::new(),
+            font: texture(FONT_TEXTURE),
+            white: texture("white"),
+        }
+    }
+
+    pub fn push_line(&mut self, text: impl Into<String>) {
+        self.push_colored_line(text, TEXT_COLOR);
+    }
+
+    pub fn push_colored_line(&mut self, text: impl Into<String>, color: [f32; 4]) {
+        self.lines.push((text.into(), color));
+    }
+
+    /// Triangles for this frame's lines and the crosshair, clears the lines
+    pub fn take_vertices(&mut self, resolution: [u32; 2]) -> Vec<HudVertex> {
+        let mut quads = QuadBuilder {
+            vertices: Vec::new(),
+            resolution: [resolution[0] as f32, resolution[1] as f32],
+        };
+
+        let advance = CELL_SIZE[0] as f32 * TEXT_SCALE;
+        let line_height = GLYPH_SIZE[1] as f32 * TEXT_SCALE + LINE_SPACING;
+
+        if let Some(longest) = self.lines.iter().map(|(l, _)| l.chars().count()).max() {
+            let size = [
+                longest as f32 * advance + MARGIN,
+                self.lines.len() as f32 * line_height + MARGIN,
+            ];
+            quads.push([MARGIN / 2.0; 2], size, self.white.center(), [0.0; 2], BACKGROUND_COLOR);
+        }
+
+        for (i, (line, color)) in self.lines.drain(..).enumerate() {
+            let y = MARGIN + i as f32 * line_height;
+            for (j, c) in line.chars().enumerate() {
+                let Some(uv) = glyph_uv(&self.font, c) else { continue };
+                let pos = [MARGIN + j as f32 * advance, y];
+                let size = GLYPH_SIZE.map(|s| s as f32 * TEXT_SCALE);
+                quads.push(pos, size, uv, GLYPH_SIZE.map(|s| s as f32), color);
+            }
+        }
+
+        let center = quads.resolution.map(|r| (r / 2.0).floor());
+        let white = self.white.center();
+        quads.push(
+            [center[0] - CROSSHAIR_SIZE / 2.0, center[1] - CROSSHAIR_THICKNESS / 2.0],
+            [CROSSHAIR_SIZE, CROSSHAIR_THICKNESS],
+            white,
+            [0.0; 2],
+            CROSSHAIR_COLOR,
+        );
+        // Split around the center so it isn't drawn twice
+        let vertical_half = (CROSSHAIR_SIZE - CROSSHAIR_THICKNESS) / 2.0;
+        for y in [center[1] - CROSSHAIR_SIZE / 2.0, center[1] + CROSSHAIR_THICKNESS / 2.0] {
+            quads.push(
+                [center[0] - CROSSHAIR_THICKNESS / 2.0, y],
+                [CROSSHAIR_THICKNESS, vertical_half],
+                white,
+                [0.0; 2],
+                CROSSHAIR_COLOR,
+            );
+        }
+
+        quads.vertices
+    }
+}
+
+/// Top left texel of a glyph in the atlas, `None` for characters the font doesn't have
+fn glyph_uv(font: &TexelTexture, c: char) -> Option<[f32; 2]> {
+    let index = (c as u32).checked_sub(FIRST_CHAR)?;
+    let rows = font.size[1] as u32 / CELL_SIZE[1];
+    if c == ' ' || index >= FONT_COLUMNS * rows {
+        return None;
+    }
+
+    Some([
+        (font.offset[0] as u32 + (index % FONT_COLUMNS) * CELL_SIZE[0]) as f32,
+        (font.offset[1] as u32 + (index / FONT_COLUMNS) * CELL_SIZE[1]) as f32,
+    ])
+}
+
+trait TexelCenter {
+    fn center(&self) -> [f32; 2];
+}
+
+impl TexelCenter for TexelTexture {
+    fn center(&self) -> [f32; 2] {
+        [
+            self.offset[0] as f32 + self.size[0] as f32 / 2.0,
+            self.offset[1] as f32 + self.size[1] as f32 / 2.0,
+        ]
+    }
+}
+
+struct QuadBuilder {
+    vertices: Vec<HudVertex>,
+    resolution: [f32; 2],
+}
+
+impl QuadBuilder {
+    /// `pos` and `size` are in pixels from the top left of the window
+    fn push(&mut self, pos: [f32; 2], size: [f32; 2], uv: [f32; 2], uv_size: [f32; 2], color: [f32; 4]) {
+        let to_ndc = |x: f32, y: f32| [x / self.resolution[0] * 2.0 - 1.0, y / self.resolution[1] * 2.0 - 1.0];
+        let corner = |cx: f32, cy: f32| HudVertex {
+            position: to_ndc(pos[0] + cx * size[0], pos[1] + cy * size[1]),
+            uv: [uv[0] + cx * uv_size[0], uv[1] + cy * uv_size[1]],
+            color,
+        };
+
+        let [a, b, c, d] = [corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)];
+        self.vertices.extend([a, b, c, a, c, d]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glyph_positions() {
+        let font = TexelTexture::new([100, 200], [64, 36]);
+        assert_eq!(glyph_uv(&font, '!'), Some([104.0, 200.0]));
+        assert_eq!(glyph_uv(&font, '0'), Some([100.0, 206.0]));
+        assert_eq!(glyph_uv(&font, ' '), None);
+        assert_eq!(glyph_uv(&font, 'é'), None);
+    }
+}
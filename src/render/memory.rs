@@ -0,0 +1,286 @@
+use std::{ffi::c_void, fmt};
+
+use ash::vk;
+use vulkano::{
+    buffer::{BufferMemory, Subbuffer},
+    device::physical::PhysicalDevice,
+    memory::MemoryHeapFlags,
+    Version, VulkanObject,
+};
+
+/// Share of a heap's budget above which a warning is printed
+const WARN_FRACTION: f32 = 0.85;
+/// Share of a heap's budget above which the render distance is lowered
+const DEGRADE_FRACTION: f32 = 0.95;
+/// The render distance is never lowered below this
+const MIN_RENDER_DISTANCE: u32 = 8;
+/// Checks to wait after changing the render distance, so chunks are unloaded or loaded and memory is measured
+/// again before it changes any further
+const SETTLE_CHECKS: u32 = 3;
+
+/// Parts of the renderer that are reported separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    Brickmaps,
+    TexturePointers,
+    Brickgrid,
+    Surfels,
+    Atlas,
+    Staging,
+    Other,
+}
+
+impl Subsystem {
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Brickmaps => "brickmaps",
+            Subsystem::TexturePointers => "texture pointers",
+            Subsystem::Brickgrid => "brickgrid",
+            Subsystem::Surfels => "surfels",
+            Subsystem::Atlas => "atlas",
+            Subsystem::Staging => "staging",
+            Subsystem::Other => "other",
+        }
+    }
+}
+
+struct HeapReport {
+    size: u64,
+    device_local: bool,
+    /// From `VK_EXT_memory_budget`, how much this process may use
+    budget: Option<u64>,
+    /// From `VK_EXT_memory_budget`, how much this process uses, including memory that isn't tracked
+    usage: Option<u64>,
+    tracked: u64,
+}
+
+impl HeapReport {
+    fn budget(&self) -> u64 {
+        self.budget.unwrap_or(self.size)
+    }
+
+    fn used(&self) -> u64 {
+        self.usage.unwrap_or(self.tracked)
+    }
+}
+
+/// GPU memory in use by each subsystem and how full each heap is.
+///
+/// Subsystems add their buffers with `add_buffer`, the heap is looked up from the memory the buffer was
+/// allocated in.
+pub struct MemoryReport {
+    heaps: Vec<HeapReport>,
+    /// Heap index of each memory type
+    memory_type_heaps: Vec<u32>,
+    subsystems: Vec<(Subsystem, u64)>,
+}
+
+impl MemoryReport {
+    pub fn new(physical: &PhysicalDevice) -> Self {
+        let properties = physical.memory_properties();
+        let budgets = query_budgets(physical);
+
+        let heaps = properties
+            .memory_heaps
+            .iter()
+            .enumerate()
+            .map(|(i, heap)| HeapReport {
+                size: heap.size,
+                device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+                budget: budgets.as_ref().map(|b| b[i].0),
+                usage: budgets.as_ref().map(|b| b[i].1),
+                tracked: 0,
+            })
+            .collect();
+
+        Self {
+            heaps,
+            memory_type_heaps: properties.memory_types.iter().map(|t| t.heap_index).collect(),
+            subsystems: Vec::new(),
+        }
+    }
+
+    pub fn add_buffer<T: ?Sized>(&mut self, subsystem: Subsystem, buffer: &Subbuffer<T>) {
+        let heap = match buffer.buffer().memory() {
+            BufferMemory::Normal(alloc) => {
+                Some(self.memory_type_heaps[alloc.device_memory().memory_type_index() as usize])
+            }
+            _ => None,
+        };
+        self.add(subsystem, heap, buffer.size());
+    }
+
+    /// Images are assumed to be in the first device local heap
+    pub fn add_image(&mut self, subsystem: Subsystem, bytes: u64) {
+        let heap = self.heaps.iter().position(|h| h.device_local).map(|i| i as u32);
+        self.add(subsystem, heap, bytes);
+    }
+
+    fn add(&mut self, subsystem: Subsystem, heap: Option<u32>, bytes: u64) {
+        if let Some(heap) = heap {
+            self.heaps[heap as usize].tracked += bytes;
+        }
+
+        match self.subsystems.iter_mut().find(|(s, _)| *s == subsystem) {
+            Some((_, total)) => *total += bytes,
+            None => self.subsystems.push((subsystem, bytes)),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.subsystems.iter().map(|(_, bytes)| bytes).sum()
+    }
+
+    /// Largest share of a budget in use, over the heaps the renderer allocated in.
+    ///
+    /// Without `VK_EXT_memory_budget` the tracked memory is compared to the size of the heap.
+    pub fn budget_fraction(&self) -> f32 {
+        self.heaps
+            .iter()
+            .filter(|h| h.tracked > 0)
+            .map(|h| h.used() as f32 / h.budget().max(1) as f32)
+            .fold(0.0, f32::max)
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "GPU memory:")?;
+        for (subsystem, bytes) in self.subsystems.iter() {
+            writeln!(f, "  {:<18} {:>10}", subsystem.name(), mib(*bytes))?;
+        }
+        writeln!(f, "  {:<18} {:>10}", "total", mib(self.total()))?;
+
+        write!(f, "Heaps:")?;
+        for (i, heap) in self.heaps.iter().enumerate() {
+            write!(
+                f,
+                "\n  {} ({}): {} used of {} {}, {} tracked",
+                i,
+                if heap.device_local { "device local" } else { "host" },
+                mib(heap.used()),
+                mib(heap.budget()),
+                if heap.budget.is_some() { "budget" } else { "heap" },
+                mib(heap.tracked),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Budget and usage of every heap, `None` without `VK_EXT_memory_budget`
+fn query_budgets(physical: &PhysicalDevice) -> Option<Vec<(u64, u64)>> {
+    let instance = physical.instance();
+    // `vkGetPhysicalDeviceMemoryProperties2` is core since 1.1
+    if !physical.supported_extensions().ext_memory_budget
+        || instance.api_version() < Version::V1_1
+        || physical.api_version() < Version::V1_1
+    {
+        return None;
+    }
+
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceMemoryProperties2 {
+        p_next: &mut budget as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        (instance.fns().v1_1.get_physical_device_memory_properties2)(physical.handle(), &mut properties);
+    }
+
+    let count = properties.memory_properties.memory_heap_count as usize;
+    Some((0..count).map(|i| (budget.heap_budget[i], budget.heap_usage[i])).collect())
+}
+
+/// Warns once memory gets tight, lowers the render distance when it runs out and raises it back towards
+/// the setting once there is room again
+#[derive(Default)]
+pub struct BudgetWatcher {
+    warned: bool,
+    /// Checks left until the render distance may change again
+    settling: u32,
+}
+
+impl BudgetWatcher {
+    /// Returns the render distance to use from now on, `target_distance` is the one from the settings
+    pub fn check(&mut self, budget_fraction: f32, render_distance: u32, target_distance: u32) -> u32 {
+        let settled = self.settling == 0;
+        self.settling = self.settling.saturating_sub(1);
+
+        if settled && budget_fraction > DEGRADE_FRACTION && render_distance > MIN_RENDER_DISTANCE {
+            let lowered = (render_distance * 3 / 4).max(MIN_RENDER_DISTANCE);
+            println!(
+                "WARNING: GPU memory is at {:.0}% of the budget, lowering the render distance to {}",
+                budget_fraction * 100.0,
+                lowered,
+            );
+            self.settling = SETTLE_CHECKS;
+            return lowered;
+        }
+
+        if budget_fraction > WARN_FRACTION && !self.warned {
+            println!("WARNING: GPU memory is at {:.0}% of the budget", budget_fraction * 100.0);
+        }
+        self.warned = budget_fraction > WARN_FRACTION;
+
+        if settled && render_distance < target_distance {
+            let raised = (render_distance * 4 / 3).clamp(render_distance + 1, target_distance);
+            // Most of the memory is chunks, which grow with the area around the camera
+            let expected = budget_fraction * (raised as f32 / render_distance as f32).powi(2);
+            if expected < WARN_FRACTION {
+                println!(
+                    "GPU memory is at {:.0}% of the budget, raising the render distance to {}",
+                    budget_fraction * 100.0,
+                    raised,
+                );
+                self.settling = SETTLE_CHECKS;
+                return raised;
+            }
+        }
+        render_distance
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_distance_is_lowered_near_the_budget() {
+        let mut watcher = BudgetWatcher::default();
+        assert_eq!(watcher.check(0.5, 32, 32), 32);
+        assert_eq!(watcher.check(0.9, 32, 32), 32);
+        assert!(watcher.warned);
+        assert_eq!(watcher.check(0.99, 32, 32), 24);
+
+        // Waits for the far chunks to be unloaded before lowering it again
+        for _ in 0..SETTLE_CHECKS {
+            assert_eq!(watcher.check(0.99, 24, 32), 24);
+        }
+        assert_eq!(watcher.check(0.99, 24, 32), 18);
+
+        watcher.settling = 0;
+        assert_eq!(watcher.check(0.99, 9, 32), MIN_RENDER_DISTANCE);
+        watcher.settling = 0;
+        assert_eq!(watcher.check(0.99, MIN_RENDER_DISTANCE, 32), MIN_RENDER_DISTANCE);
+    }
+
+    #[test]
+    fn render_distance_is_restored_when_memory_frees_up() {
+        let mut watcher = BudgetWatcher::default();
+        // 24 to 32 is expected to take 0.5 to 0.89, above the warning
+        assert_eq!(watcher.check(0.5, 24, 32), 24);
+        assert_eq!(watcher.check(0.4, 24, 32), 32);
+
+        for _ in 0..SETTLE_CHECKS {
+            assert_eq!(watcher.check(0.1, 8, 32), 8);
+        }
+        assert_eq!(watcher.check(0.1, 8, 32), 10);
+        assert_eq!(watcher.check(0.1, 32, 32), 32);
+    }
+}
@@ -0,0 +1,195 @@
+use ultraviolet::{UVec3, Vec2, Vec3};
+
+use crate::{
+    render::vertex::Vertex,
+    util::util::{Facing, Sign},
+};
+
+use super::quad::QuadUV;
+
+/// Visible faces of neighbouring blocks with the same texture, merged into one rectangle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GreedyQuad {
+    pub facing: Facing,
+    /// Corner with the lowest coordinates, on the plane of the face
+    pub min: UVec3,
+    /// Length along the two axes that follow the facing axis, `X` comes after `Z`
+    pub size: [u32; 2],
+    pub texture: u32,
+}
+
+impl GreedyQuad {
+    /// Counter clockwise seen from the front. Texture coordinates are in blocks, so a repeating texture
+    /// covers every block of the quad once.
+    pub fn vertices(&self) -> [Vertex; 4] {
+        let d = axis_index(self.facing);
+        let mut extent = [0.0; 3];
+        extent[(d + 1) % 3] = self.size[0] as f32;
+        extent[(d + 2) % 3] = self.size[1] as f32;
+
+        let ((u_axis, _), (v_axis, _)) = texture_axes(self.facing);
+        let uv = QuadUV { min: Vec2::zero(), max: Vec2::new(extent[u_axis], extent[v_axis]) };
+        let min = Vec3::new(self.min.x as f32, self.min.y as f32, self.min.z as f32);
+        face_vertices(self.facing, min, extent.into(), &uv)
+    }
+}
+
+/// Merges the visible faces of a box of blocks into as few rectangles as possible.
+///
+/// `visible_face` returns the texture of a face, or `None` if it is culled or there is no block.
+pub fn greedy_mesh<F>(size: UVec3, visible_face: F) -> Vec<GreedyQuad>
+where
+    F: Fn(UVec3, Facing) -> Option<u32>,
+{
+    let dims = [size.x, size.y, size.z];
+    let mut quads = Vec::new();
+
+    for face in 0..6 {
+        let facing = Facing::from_num(face);
+        let d = axis_index(facing);
+        let (a, b) = ((d + 1) % 3, (d + 2) % 3);
+        let (len_a, len_b) = (dims[a] as usize, dims[b] as usize);
+        let mut mask = vec![None; len_a * len_b];
+
+        for layer in 0..dims[d] {
+            for j in 0..len_b {
+                for i in 0..len_a {
+                    let mut pos = [0; 3];
+                    pos[d] = layer;
+                    pos[a] = i as u32;
+                    pos[b] = j as u32;
+                    mask[j * len_a + i] = visible_face(UVec3::new(pos[0], pos[1], pos[2]), facing);
+                }
+            }
+
+            for j in 0..len_b {
+                let mut i = 0;
+                while i < len_a {
+                    let Some(texture) = mask[j * len_a + i] else {
+                        i += 1;
+                        continue;
+                    };
+
+                    let mut width = 1;
+                    while i + width < len_a && mask[j * len_a + i + width] == Some(texture) {
+                        width += 1;
+                    }
+
+                    let mut height = 1;
+                    while j + height < len_b
+                        && (i..i + width).all(|k| mask[(j + height) * len_a + k] == Some(texture))
+                    {
+                        height += 1;
+                    }
+
+                    for row in j..j + height {
+                        mask[row * len_a + i..row * len_a + i + width].fill(None);
+                    }
+
+                    let mut min = [0; 3];
+                    min[d] = layer + (facing.sign == Sign::Positive) as u32;
+                    min[a] = i as u32;
+                    min[b] = j as u32;
+                    quads.push(GreedyQuad {
+                        facing,
+                        min: UVec3::new(min[0], min[1], min[2]),
+                        size: [width as u32, height as u32],
+                        texture,
+                    });
+
+                    i += width;
+                }
+            }
+        }
+    }
+
+    quads
+}
+
+/// Quad on the side of a box facing `facing`, `extent` is the size of the face with 0 along the facing axis.
+///
+/// Side faces have the top of their texture pointing up, like the raytracer draws them.
+pub fn face_vertices(facing: Facing, min: Vec3, extent: Vec3, uv: &QuadUV) -> [Vertex; 4] {
+    let ((u_axis, u_sign), (v_axis, v_sign)) = texture_axes(facing);
+    let normal = match facing.sign {
+        Sign::Positive => facing.axis.unit_vec(),
+        Sign::Negative => -facing.axis.unit_vec(),
+    };
+
+    // Texture coordinates of the corners in the order they are returned
+    [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(s, t): (f32, f32)| {
+        let (min, extent) = (min.as_array(), extent.as_array());
+        let mut position = *min;
+        position[u_axis] += extent[u_axis] * if u_sign > 0.0 { s } else { 1.0 - s };
+        position[v_axis] += extent[v_axis] * if v_sign > 0.0 { t } else { 1.0 - t };
+
+        Vertex {
+            position: position.into(),
+            normal,
+            // Rows of the atlas go down
+            tex_coords: Vec2::new(
+                uv.min.x + (uv.max.x - uv.min.x) * s,
+                uv.max.y + (uv.min.y - uv.max.y) * t,
+            ),
+        }
+    })
+}
+
+/// Axis and direction the texture's `u` and `v` go along in the world, `u` cross `v` is the normal
+fn texture_axes(facing: Facing) -> ((usize, f32), (usize, f32)) {
+    match facing.to_num() {
+        0 => ((2, -1.0), (1, 1.0)),
+        1 => ((2, 1.0), (1, 1.0)),
+        2 => ((0, 1.0), (2, -1.0)),
+        3 => ((0, 1.0), (2, 1.0)),
+        4 => ((0, 1.0), (1, 1.0)),
+        _ => ((0, -1.0), (1, 1.0)),
+    }
+}
+
+fn axis_index(facing: Facing) -> usize {
+    facing.to_num() / 2
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn faces_of_one_texture_are_merged() {
+        // A 4x1x3 slab of texture 1 with a single block of texture 2 at the corner
+        let size = UVec3::new(4, 1, 3);
+        let quads = greedy_mesh(size, |pos, facing| {
+            let texture = if pos == UVec3::zero() { 2 } else { 1 };
+            (facing == Facing::UP).then_some(texture)
+        });
+
+        assert_eq!(quads.len(), 3);
+        assert!(quads.iter().all(|q| q.facing == Facing::UP && q.min.y == 1));
+
+        let area = quads.iter().map(|q| q.size[0] * q.size[1]).sum::<u32>();
+        assert_eq!(area, 12);
+        assert!(quads.contains(&GreedyQuad { facing: Facing::UP, min: UVec3::new(0, 1, 0), size: [1, 1], texture: 2 }));
+    }
+
+    #[test]
+    fn quads_face_outwards() {
+        for face in 0..6 {
+            let facing = Facing::from_num(face);
+            let quad = GreedyQuad { facing, min: UVec3::zero(), size: [1, 1], texture: 0 };
+            let v = quad.vertices();
+            let normal = (v[1].position - v[0].position).cross(v[2].position - v[0].position);
+            assert!(normal.dot(v[0].normal) > 0.0, "{:?}", facing);
+        }
+    }
+
+    #[test]
+    fn texture_coordinates_are_in_blocks() {
+        // 2 blocks along Z and 3 along X, the top's texture goes along X and then Z
+        let quad = GreedyQuad { facing: Facing::UP, min: UVec3::zero(), size: [2, 3], texture: 0 };
+        let coords = quad.vertices().map(|v| v.tex_coords);
+        let max = coords.iter().fold(Vec2::zero(), |a, b| Vec2::new(a.x.max(b.x), a.y.max(b.y)));
+        assert!(coords.contains(&Vec2::zero()));
+        assert_eq!(max, Vec2::new(3.0, 2.0));
+    }
+}
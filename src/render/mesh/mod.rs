@@ -2,4 +2,5 @@ pub mod quad;
 pub mod renderable;
 pub mod cube;
 pub mod chunk_render;
-pub mod model;
\ No newline at end of file
+pub mod model;
+pub mod greedy;
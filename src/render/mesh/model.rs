@@ -83,7 +83,7 @@ impl Model {
     pub fn create_plant_model(uv: QuadUV) -> Self {
         let min_max = uv.tex_coords();
         let binding = [min_max, min_max];
-        let mm = binding.flatten();
+        let mm = binding.as_flattened();
 
         let vertices = PLANT_INDICES
             .iter()
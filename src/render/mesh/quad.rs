@@ -1,7 +1,7 @@
 use bytemuck::{Pod, Zeroable};
 use ultraviolet::{Vec3, Vec2};
 
-use crate::{util::util::{Sign, Facing}, render::texture::TextureHandle};
+use crate::{util::util::{Sign, Facing}, render::{texture::TextureHandle, gpu_layout::{gpu_fields, GlslType, GpuField, GpuStruct, GpuStructKind}}};
 
 // Unused
 pub struct RawQuad {
@@ -71,6 +71,18 @@ impl From<TexelTexture> for TexelTexturePad {
     }
 }
 
+impl GpuStruct for TexelTexture {
+    const GLSL_NAME: &'static str = "Texture";
+    const KIND: GpuStructKind = GpuStructKind::Struct;
+
+    fn fields() -> Vec<GpuField> {
+        gpu_fields!(TexelTexture {
+            offset as "offset_xy": GlslType::Uint,
+            size as "size_xy": GlslType::Uint,
+        })
+    }
+}
+
 impl TexelTexture {
     pub const fn new(offset: [u16; 2], size: [u16; 2]) -> Self {
         Self { offset, size }
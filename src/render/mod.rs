@@ -1,5 +1,7 @@
 pub mod renderer;
 pub mod shaders;
+#[cfg(feature = "dev-shaders")]
+pub mod shader_compiler;
 pub mod util;
 pub mod vertex;
 pub mod fps_log;
@@ -9,4 +11,12 @@ pub mod mesh;
 pub mod texture;
 pub mod descriptor_sets;
 pub mod brick;
-pub mod accumulation;
\ No newline at end of file
+pub mod accumulation;
+pub mod sky;
+pub mod gpu_layout;
+pub mod pipeline_cache;
+pub mod device;
+pub mod hud;
+pub mod gpu_timer;
+pub mod memory;
+
@@ -0,0 +1,109 @@
+use std::{
+    fs,
+    io,
+    mem::size_of,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use bytemuck::{Pod, Zeroable};
+use vulkano::{
+    device::{physical::PhysicalDevice, Device},
+    pipeline::cache::PipelineCache,
+};
+
+const CACHE_MAGIC: [u8; 4] = *b"VKVP";
+const CACHE_VERSION: u32 = 1;
+
+/// Written in front of the driver's cache data.
+///
+/// Drivers are supposed to reject caches from other devices themselves, but not all of them do.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Pod, Zeroable)]
+struct CacheHeader {
+    magic: [u8; 4],
+    version: u32,
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+    pipeline_cache_uuid: [u8; 16],
+}
+
+impl CacheHeader {
+    fn new(physical: &PhysicalDevice) -> Self {
+        let properties = physical.properties();
+        Self {
+            magic: CACHE_MAGIC,
+            version: CACHE_VERSION,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+        }
+    }
+}
+
+/// Pipeline cache saved between runs, so pipelines aren't compiled from scratch on every start.
+pub struct PersistentPipelineCache {
+    pub cache: Arc<PipelineCache>,
+    header: CacheHeader,
+    path: PathBuf,
+}
+
+impl PersistentPipelineCache {
+    /// Starts with an empty cache if there is no usable one on disk.
+    pub fn load(device: Arc<Device>) -> Self {
+        let header = CacheHeader::new(device.physical_device());
+        let path = Self::default_path();
+
+        let cache = fs::read(&path)
+            .ok()
+            .and_then(|data| Self::from_data(device.clone(), &header, &data))
+            .unwrap_or_else(|| PipelineCache::empty(device).unwrap());
+
+        Self { cache, header, path }
+    }
+
+    fn from_data(device: Arc<Device>, header: &CacheHeader, data: &[u8]) -> Option<Arc<PipelineCache>> {
+        let header_size = size_of::<CacheHeader>();
+        if data.len() < header_size {
+            return None;
+        }
+
+        let saved_header: CacheHeader = bytemuck::pod_read_unaligned(&data[..header_size]);
+        if saved_header != *header {
+            println!("Pipeline cache is from another device or driver, starting over");
+            return None;
+        }
+
+        // Safety: the header matches, so the data was made by this driver for this device
+        unsafe { PipelineCache::with_data(device, &data[header_size..]) }.ok()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let data = self
+            .cache
+            .get_data()
+            .map_err(io::Error::other)?;
+
+        let mut contents = bytemuck::bytes_of(&self.header).to_vec();
+        contents.extend_from_slice(&data);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so a crash can't leave half a cache behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(tmp_path, &self.path)
+    }
+
+    /// In the user data directory, or the working directory if there is none
+    fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .map(|dir| dir.join("vk-voxel"))
+            .unwrap_or_else(|| PathBuf::from("./cache"))
+            .join("pipeline_cache.bin")
+    }
+}
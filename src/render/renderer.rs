@@ -1,9 +1,9 @@
 use std::sync::{Arc, Mutex};
 
 use bytemuck::{Pod, Zeroable};
-use ultraviolet::Mat4;
+use ultraviolet::{IVec3, Mat4, Vec3};
 use vulkano::{
-    buffer::{BufferUsage, Subbuffer},
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
         allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo},
         AutoCommandBufferBuilder, CommandBufferUsage, DrawIndirectCommand,
@@ -16,13 +16,10 @@ use vulkano::{
             DescriptorType,
         },
     },
-    device::{
-        physical::{PhysicalDevice, PhysicalDeviceType},
-        Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo, QueueFlags,
-    },
+    device::{physical::PhysicalDevice, Device, DeviceCreateInfo, Queue, QueueCreateInfo},
     image::{view::ImageView, ImageUsage, SwapchainImage},
     instance::{Instance, InstanceCreateInfo},
-    memory::allocator::StandardMemoryAllocator,
+    memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator},
     pipeline::{
         graphics::{
             color_blend::ColorBlendState,
@@ -30,7 +27,7 @@ use vulkano::{
             viewport::{Viewport, ViewportState},
         },
         layout::PipelineLayoutCreateInfo,
-        GraphicsPipeline, PipelineLayout,
+        ComputePipeline, GraphicsPipeline, PipelineBindPoint, PipelineLayout,
     },
     render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
     sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
@@ -51,17 +48,35 @@ use winit::{
 
 use crate::{
     event_handler::UserEvent,
+    profiler,
+    settings::{PresentModeSetting, Settings, TextureFilter},
     world::{block_data::StaticBlockData, world_blocks::WorldBlocks},
 };
 
 use super::{
-    buffer::vertex_buffer::ChunkVertexBuffer,
+    accumulation::buffers::SurfelBuffers,
+    brick::feedback::FeedbackBuffers,
+    buffer::{staging_ring::StagingRing, vertex_buffer::ChunkVertexBuffer},
     descriptor_sets::DescriptorSets,
+    device::{self, DeviceSelector},
+    gpu_timer::{GpuMark, GpuTimer},
+    memory::{BudgetWatcher, MemoryReport, Subsystem},
+    pipeline_cache::PersistentPipelineCache,
+    hud::Hud,
+    gpu_layout::{gpu_fields, GlslType, GpuField, GpuStruct, GpuStructKind},
     shaders::{ShaderPair, LoadFromPath},
+    sky::Sky,
     texture::TextureAtlas,
-    util::{GetWindow, ProgramInfo, RenderState},
-    vertex::Vertex2D,
+    util::{CreateInfoConvenience, GetWindow, ProgramInfo, RenderState},
+    vertex::{HudVertex, Vertex2D},
 };
+#[cfg(feature = "dev-shaders")]
+use super::shaders::ShaderWatcher;
+
+/// Frames between checks for surfels that are too far away
+const SURFEL_RECYCLE_INTERVAL: u32 = 60;
+const MEMORY_CHECK_INTERVAL: u32 = 120;
+const STAGING_RING_SIZE: u64 = 32 * 1024 * 1024;
 
 pub struct Renderer {
     pub vk_lib: Arc<VulkanLibrary>,
@@ -78,6 +93,7 @@ pub struct Renderer {
     pub vk_render_pass: Arc<RenderPass>,
     pub vk_frame_buffers: Vec<Arc<Framebuffer>>,
     pub pipelines: Pipelines,
+    pub pipeline_cache: PersistentPipelineCache,
 
     pub view: View,
     pub viewport: Viewport,
@@ -86,15 +102,32 @@ pub struct Renderer {
     pub indirect_buffer: Option<Subbuffer<[DrawIndirectCommand]>>,
     pub num_vertices: u32,
     pub cam_uniform: Option<Mat4>,
+    pub sky: Option<Sky>,
     pub texture_atlas: TextureAtlas,
     pub texture_sampler: Arc<Sampler>,
+    /// Text and the crosshair, drawn after the raytracing pass
+    pub hud: Hud,
     pub program_info: ProgramInfo,
+    /// Block position and face id under the crosshair, as of a few frames ago
+    pub debug_target: Option<(IVec3, usize)>,
+    /// Change through `apply_settings`
+    settings: Settings,
     pub descriptor_sets: DescriptorSets,
+    pub feedback_buffers: FeedbackBuffers,
+    pub surfel_buffers: SurfelBuffers,
+    /// Uploads to device local buffers go through here
+    staging_ring: StagingRing,
+    /// `None` if the device can't write timestamps
+    gpu_timer: Option<GpuTimer>,
+    budget_watcher: BudgetWatcher,
 
     pub upload_texture_atlas: bool,
 
     pub block_shader: ShaderPair,
+    pub hud_shader: ShaderPair,
     pub surfel_shader: Arc<ShaderModule>,
+    #[cfg(feature = "dev-shaders")]
+    shader_watcher: Option<ShaderWatcher>,
 
     // Complex type could be simplified using the `type` keyword.
     fences: Vec<Option<Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>>,
@@ -106,14 +139,10 @@ impl Renderer {
         event_loop: &EventLoop<UserEvent>,
         texture_atlas: TextureAtlas,
         block_data: &StaticBlockData,
+        settings: &Settings,
     ) -> Self {
         let vk_lib = VulkanLibrary::new().expect("no local Vulkan library/DLL");
 
-        let device_extensions = DeviceExtensions {
-            khr_swapchain: true,
-            ..DeviceExtensions::empty()
-        };
-
         let vk_instance = Instance::new(
             vk_lib.clone(),
             InstanceCreateInfo {
@@ -124,7 +153,7 @@ impl Renderer {
         .expect("failed to create instance");
 
         let vk_surface = WindowBuilder::new()
-            .with_inner_size(PhysicalSize::new(1600, 900))
+            .with_inner_size(PhysicalSize::new(settings.window_size[0], settings.window_size[1]))
             .with_title("VK Voxel")
             .build_vk_surface(event_loop, vk_instance.clone())
             .unwrap();
@@ -133,25 +162,30 @@ impl Renderer {
         window.set_cursor_grab(CursorGrabMode::Confined).unwrap();
         window.set_cursor_visible(false);
 
+        let selector = DeviceSelector::from_env_or_settings(settings);
         let (vk_physical, queue_family_indices) =
-            Self::select_physical_device(&vk_instance, &vk_surface, &device_extensions);
+            device::select_physical_device(&vk_instance, &vk_surface, selector.as_ref())
+                .unwrap_or_else(|e| panic!("{}", e));
+
+        let device_name = vk_physical.properties().device_name.clone();
+        println!("Using {} ({:?})", device_name, vk_physical.properties().device_type);
+
+        // Already checked while selecting the device
+        let enabled_features = device::negotiate_features(&vk_physical).unwrap();
 
         let (vk_device, mut queues) = Device::new(
             vk_physical.clone(),
             DeviceCreateInfo {
-                enabled_features: Features {
-                    shader_storage_texel_buffer_array_dynamic_indexing: true,
-                    multi_draw_indirect: true,
-                    ..Default::default()
-                },
+                enabled_features,
                 queue_create_infos: vec![QueueCreateInfo {
                     queue_family_index: queue_family_indices.graphics,
                     ..Default::default()
                 }],
-                enabled_extensions: device_extensions,
+                enabled_extensions: device::required_extensions(),
                 ..Default::default()
             },
-        ).expect("failed to create device");
+        )
+        .unwrap_or_else(|e| panic!("Could not create a device on {}: {}", device_name, e));
 
         let vk_graphics_queue = queues.next().unwrap();
 
@@ -163,6 +197,7 @@ impl Renderer {
         let vk_descriptor_set_allocator = StandardDescriptorSetAllocator::new(vk_device.clone());
 
         let vk_memory_allocator = StandardMemoryAllocator::new_default(vk_device.clone());
+        let staging_ring = StagingRing::new(&vk_memory_allocator, STAGING_RING_SIZE);
 
         let capabilities = vk_physical
             .surface_capabilities(&vk_surface, Default::default())
@@ -183,6 +218,8 @@ impl Renderer {
                 .0,
         );
 
+        let present_mode = Self::choose_present_mode(&vk_physical, &vk_surface, settings.present_mode);
+
         let (vk_swapchain, vk_swapchain_images) = Swapchain::new(
             vk_device.clone(),
             vk_surface.clone(),
@@ -193,7 +230,7 @@ impl Renderer {
                 image_usage: ImageUsage::COLOR_ATTACHMENT,
                 composite_alpha,
                 image_color_space: ColorSpace::SrgbNonLinear,
-                present_mode: PresentMode::Mailbox,
+                present_mode,
                 ..Default::default()
             },
         )
@@ -212,27 +249,48 @@ impl Renderer {
         let vk_frame_buffers = Self::get_framebuffers(&vk_swapchain_images, &vk_render_pass);
 
         let block_shader = ShaderPair::load(vk_device.clone(), "shader");
+        let hud_shader = ShaderPair::load(vk_device.clone(), "hud");
         let surfel_shader = ShaderModule::load(vk_device.clone(), "surfel.comp");
 
+        let pipeline_cache = PersistentPipelineCache::load(vk_device.clone());
+
         let pipelines = Self::get_pipelines(
             vk_device.clone(),
+            &pipeline_cache,
             &block_shader,
+            &hud_shader,
+            surfel_shader.clone(),
             vk_render_pass.clone(),
             viewport.clone(),
-        );
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+
+        #[cfg(feature = "dev-shaders")]
+        let shader_watcher = match ShaderWatcher::new() {
+            Ok(w) => Some(w),
+            Err(e) => {
+                println!("WARNING: Shaders will not be reloaded on changes: {}", e);
+                None
+            }
+        };
 
         let texture_sampler = Sampler::new(
             vk_device.clone(),
             SamplerCreateInfo {
-                mag_filter: Filter::Nearest,
-                min_filter: Filter::Nearest,
+                mag_filter: texture_filter(settings.texture_filter),
+                min_filter: texture_filter(settings.texture_filter),
                 address_mode: [SamplerAddressMode::ClampToEdge; 3],
                 ..Default::default()
             },
         )
         .unwrap();
 
-        let program_info = ProgramInfo::new();
+        let program_info = ProgramInfo {
+            shadow_samples: settings.shadow_samples,
+            ao_samples: settings.ao_samples,
+            max_ray_steps: settings.max_ray_steps,
+            ..ProgramInfo::new()
+        };
 
         let mut cbb = AutoCommandBufferBuilder::primary(
             &vk_command_buffer_allocator,
@@ -243,9 +301,14 @@ impl Renderer {
 
         let view = View {
             resolution: [window.inner_size().width, window.inner_size().height],
+            grid_origin: vertex_buffer.window.origin_array(),
+            fov: settings.fov,
             ..Default::default()
         };
 
+        let feedback_buffers = FeedbackBuffers::new(&vk_memory_allocator, vk_swapchain_images.len());
+        let surfel_buffers = SurfelBuffers::new(&vk_memory_allocator, &mut cbb);
+
         let descriptor_sets = DescriptorSets::new(
             &vk_memory_allocator,
             &vk_descriptor_set_allocator,
@@ -254,6 +317,8 @@ impl Renderer {
             &texture_atlas,
             block_data,
             texture_sampler.clone(),
+            &surfel_buffers,
+            &feedback_buffers,
         );
 
         let future = sync::now(vk_device.clone())
@@ -266,6 +331,13 @@ impl Renderer {
 
         let fences = vec![None; vk_swapchain_images.len()];
 
+        let gpu_timer = GpuTimer::new(
+            vk_device.clone(),
+            &vk_physical,
+            queue_family_indices.graphics,
+            vk_swapchain_images.len(),
+        );
+
         Self {
             vk_lib,
             vk_instance,
@@ -281,6 +353,7 @@ impl Renderer {
             vk_render_pass,
             vk_frame_buffers,
             pipelines,
+            pipeline_cache,
 
             view,
             viewport,
@@ -289,68 +362,70 @@ impl Renderer {
             indirect_buffer: None,
             num_vertices: 0,
             cam_uniform: None,
+            sky: None,
+            hud: Hud::new(&texture_atlas),
             texture_atlas,
             texture_sampler,
             program_info,
+            debug_target: None,
+            settings: settings.clone(),
             descriptor_sets,
+            feedback_buffers,
+            surfel_buffers,
+            staging_ring,
+            gpu_timer,
+            budget_watcher: BudgetWatcher::default(),
 
             upload_texture_atlas: true,
 
             block_shader,
+            hud_shader,
             surfel_shader,
+            #[cfg(feature = "dev-shaders")]
+            shader_watcher,
 
             fences,
             previous_fence_i: 0,
         }
     }
 
-    /// Select the best available phyisical device.
-    ///
-    /// Returns the device and queue family index.
-    fn select_physical_device(
-        instance: &Arc<Instance>,
-        surface: &Arc<Surface>,
-        device_extensions: &DeviceExtensions,
-    ) -> (Arc<PhysicalDevice>, QueueFamilyIndices) {
-        instance
-            .enumerate_physical_devices()
-            .expect("could not enumerate devices")
-            .filter(|p| p.supported_extensions().contains(device_extensions)) // Reference created here, but complier immediately dereferences it either way.
-            .filter_map(|p| {
-                let mut graphics = None;
-                for (i, q) in p.queue_family_properties().iter().enumerate() {
-                    if q.queue_flags.contains(QueueFlags::GRAPHICS)
-                        && p.surface_support(i as u32, surface).unwrap()
-                    {
-                        graphics = Some(i);
-                    }
-                }
+    /// Falls back to `Fifo`, the only mode every device has to support
+    fn choose_present_mode(
+        physical: &PhysicalDevice,
+        surface: &Surface,
+        setting: PresentModeSetting,
+    ) -> PresentMode {
+        let wanted = match setting {
+            PresentModeSetting::Fifo => PresentMode::Fifo,
+            PresentModeSetting::Mailbox => PresentMode::Mailbox,
+            PresentModeSetting::Immediate => PresentMode::Immediate,
+        };
 
-                Some((
-                    p,
-                    QueueFamilyIndices {
-                        graphics: graphics? as u32,
-                    },
-                ))
-            })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                _ => 4,
-            })
-            .expect("no device available")
+        let supported = physical
+            .surface_present_modes(surface)
+            .map(|mut modes| modes.any(|m| m == wanted))
+            .unwrap_or(false);
+
+        if supported {
+            wanted
+        } else {
+            println!("WARNING: Present mode {:?} is not supported, using Fifo", wanted);
+            PresentMode::Fifo
+        }
     }
 
     /// Get the graphics pipeline
     fn get_pipelines(
         device: Arc<Device>,
+        pipeline_cache: &PersistentPipelineCache,
         block_shader: &ShaderPair,
+        hud_shader: &ShaderPair,
+        surfel_shader: Arc<ShaderModule>,
         render_pass: Arc<RenderPass>,
         viewport: Viewport,
-    ) -> Pipelines {
-        fn create_fragment_layout_type(
+    ) -> Result<Pipelines, String> {
+        // Both the raytracing and the surfel pipeline use every set
+        fn create_layout_type(
             device: Arc<Device>,
             descriptor_type: DescriptorType,
         ) -> Arc<DescriptorSetLayout> {
@@ -360,7 +435,7 @@ impl Renderer {
                     bindings: [(
                         0,
                         DescriptorSetLayoutBinding {
-                            stages: ShaderStages::FRAGMENT,
+                            stages: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
                             ..DescriptorSetLayoutBinding::descriptor_type(descriptor_type)
                         },
                     )]
@@ -372,14 +447,18 @@ impl Renderer {
         }
 
         let set_layouts = vec![
-            create_fragment_layout_type(device.clone(), DescriptorType::CombinedImageSampler), // 0
-            create_fragment_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 1
-            create_fragment_layout_type(device.clone(), DescriptorType::UniformBuffer),        // 2
-            create_fragment_layout_type(device.clone(), DescriptorType::UniformBuffer),        // 3
-            create_fragment_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 4
-            create_fragment_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 5
-            create_fragment_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 6
-            create_fragment_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 7
+            create_layout_type(device.clone(), DescriptorType::CombinedImageSampler), // 0
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 1
+            create_layout_type(device.clone(), DescriptorType::UniformBuffer),        // 2
+            create_layout_type(device.clone(), DescriptorType::UniformBuffer),        // 3
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 4
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 5
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 6
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 7
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 8
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 9
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 10
+            create_layout_type(device.clone(), DescriptorType::StorageBuffer),        // 11
         ];
 
         let layout = PipelineLayout::new(
@@ -391,19 +470,43 @@ impl Renderer {
         )
         .unwrap();
 
+        let missing_main = || "Shader has no `main` entry point".to_string();
+
         let raytracing = GraphicsPipeline::start()
-            .vertex_shader(block_shader.vertex.entry_point("main").unwrap(), ())
-            .fragment_shader(block_shader.fragment.entry_point("main").unwrap(), ())
+            .vertex_shader(block_shader.vertex.entry_point("main").ok_or_else(missing_main)?, ())
+            .fragment_shader(block_shader.fragment.entry_point("main").ok_or_else(missing_main)?, ())
             .color_blend_state(ColorBlendState::new(1))
             .vertex_input_state(Vertex2D::per_vertex())
             .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([
-                viewport, // Redundant clone.
+                viewport.clone(),
             ]))
-            .render_pass(Subpass::from(render_pass, 0).unwrap()) // Redundant clone for `render_pass`
-            .with_pipeline_layout(device, layout.clone())
-            .unwrap();
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build_with_cache(pipeline_cache.cache.clone())
+            .with_pipeline_layout(device.clone(), layout.clone())
+            .map_err(|e| format!("Error creating raytracing pipeline: {}", e))?;
+
+        // Shares the layout so the atlas bound for raytracing can be sampled
+        let hud = GraphicsPipeline::start()
+            .vertex_shader(hud_shader.vertex.entry_point("main").ok_or_else(missing_main)?, ())
+            .fragment_shader(hud_shader.fragment.entry_point("main").ok_or_else(missing_main)?, ())
+            .color_blend_state(ColorBlendState::new(1).blend_alpha())
+            .vertex_input_state(HudVertex::per_vertex())
+            .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([viewport]))
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build_with_cache(pipeline_cache.cache.clone())
+            .with_pipeline_layout(device.clone(), layout.clone())
+            .map_err(|e| format!("Error creating HUD pipeline: {}", e))?;
+
+        let surfel = ComputePipeline::with_pipeline_layout(
+            device,
+            surfel_shader.entry_point("main").ok_or_else(missing_main)?,
+            &(),
+            layout.clone(),
+            Some(pipeline_cache.cache.clone()),
+        )
+        .map_err(|e| format!("Error creating surfel pipeline: {}", e))?;
 
-        Pipelines { raytracing, layout }
+        Ok(Pipelines { raytracing, surfel, hud, layout })
     }
 
     fn get_render_pass(device: Arc<Device>, swapchain: &Arc<Swapchain>) -> Arc<RenderPass> {
@@ -455,6 +558,7 @@ impl Renderer {
     pub fn recreate_swapchain(&mut self) {
         let dimensions = self.vk_surface.get_window().unwrap().inner_size();
         self.viewport.dimensions = dimensions.into();
+        self.view.resolution = dimensions.into();
 
         let (new_swapchain, new_images) = match self.vk_swapchain.recreate(SwapchainCreateInfo {
             image_extent: dimensions.into(),
@@ -468,16 +572,112 @@ impl Renderer {
         self.vk_swapchain = new_swapchain;
 
         self.vk_frame_buffers = Self::get_framebuffers(&new_images, &self.vk_render_pass);
+        self.set_image_count(new_images.len());
+    }
+
+    /// A recreated swapchain can have a different number of images, the fences and timer pools follow it
+    fn set_image_count(&mut self, image_count: usize) {
+        if self.fences.len() == image_count {
+            return;
+        }
+
+        // Dropped fences wait for their frame to finish
+        self.fences.resize(image_count, None);
+        if self.previous_fence_i >= image_count {
+            self.previous_fence_i = 0;
+        }
+        self.gpu_timer = GpuTimer::new(
+            self.vk_device.clone(),
+            &self.vk_physical,
+            self.vk_graphics_queue.queue_family_index(),
+            image_count,
+        );
+    }
+
+    /// Applies the settings that changed since the last call.
+    ///
+    /// The texture filter is only read when the renderer is created.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let window = self.vk_surface.get_window().unwrap();
+
+        if settings.window_size != self.settings.window_size {
+            // The resize event recreates the swapchain
+            window.set_inner_size(PhysicalSize::new(settings.window_size[0], settings.window_size[1]));
+        }
+
+        if settings.present_mode != self.settings.present_mode {
+            let present_mode = Self::choose_present_mode(&self.vk_physical, &self.vk_surface, settings.present_mode);
+            match self.vk_swapchain.recreate(SwapchainCreateInfo {
+                present_mode,
+                ..self.vk_swapchain.create_info()
+            }) {
+                Ok((new_swapchain, new_images)) => {
+                    self.vk_swapchain = new_swapchain;
+                    self.vk_frame_buffers = Self::get_framebuffers(&new_images, &self.vk_render_pass);
+                    self.set_image_count(new_images.len());
+                }
+                Err(e) => println!("WARNING: Could not change the present mode: {:?}", e),
+            }
+        }
+
+        self.view.fov = settings.fov;
+        self.program_info.shadow_samples = settings.shadow_samples;
+        self.program_info.ao_samples = settings.ao_samples;
+        self.program_info.max_ray_steps = settings.max_ray_steps;
+
+        self.settings = settings.clone();
     }
 
     /// Recreates the graphics pipeline of this renderer
     pub fn recreate_pipeline(&mut self) {
+        // The shaders already made a pipeline once
         self.pipelines = Self::get_pipelines(
             self.vk_device.clone(),
+            &self.pipeline_cache,
             &self.block_shader,
+            &self.hud_shader,
+            self.surfel_shader.clone(),
             self.vk_render_pass.clone(),
             self.viewport.clone(),
-        );
+        )
+        .unwrap();
+    }
+
+    /// Recompiles the shaders and swaps in new pipelines.
+    ///
+    /// The previous pipelines stay active if anything fails.
+    pub fn reload_shaders(&mut self) -> Result<(), String> {
+        let block_shader = ShaderPair::try_load(self.vk_device.clone(), "shader")?;
+        let hud_shader = ShaderPair::try_load(self.vk_device.clone(), "hud")?;
+        let surfel_shader = ShaderModule::try_load(self.vk_device.clone(), "surfel.comp")?;
+
+        self.pipelines = Self::get_pipelines(
+            self.vk_device.clone(),
+            &self.pipeline_cache,
+            &block_shader,
+            &hud_shader,
+            surfel_shader.clone(),
+            self.vk_render_pass.clone(),
+            self.viewport.clone(),
+        )?;
+        self.block_shader = block_shader;
+        self.hud_shader = hud_shader;
+        self.surfel_shader = surfel_shader;
+
+        Ok(())
+    }
+
+    /// Reloads the shaders if any of them changed on disk, `None` if there was nothing to do.
+    #[cfg(feature = "dev-shaders")]
+    pub fn reload_changed_shaders(&mut self) -> Option<Result<(), String>> {
+        let changed = self.shader_watcher.as_mut()?.poll();
+        changed.then(|| self.reload_shaders())
+    }
+
+    /// Embedded shaders never change
+    #[cfg(not(feature = "dev-shaders"))]
+    pub fn reload_changed_shaders(&mut self) -> Option<Result<(), String>> {
+        None
     }
 
     /// Get a command buffer that will upload `self`'s texture atlas to the GPU when executed.
@@ -525,8 +725,14 @@ impl Renderer {
         )
         .unwrap();
 
-        let (brickgrid_swapped, texture_pointer_swapped, brickmaps_swapped) =
-            self.vertex_buffer.update();
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.begin(&mut builder, image_index);
+            timer.mark(&mut builder, image_index, GpuMark::FrameStart);
+        }
+
+        let (brickgrid_swapped, texture_pointer_swapped, brickmaps_swapped) = self
+            .vertex_buffer
+            .record_uploads(&self.vk_memory_allocator, &mut self.staging_ring, &mut builder);
         if brickmaps_swapped {
             self.descriptor_sets.brickmap.replace(
                 &self.vk_descriptor_set_allocator,
@@ -548,7 +754,15 @@ impl Renderer {
             );
         }
 
+        self.surfel_buffers.record_uploads(
+            &self.vk_memory_allocator,
+            &mut self.staging_ring,
+            &mut builder,
+        );
+
         self.program_info.frame_number += 1;
+        self.feedback_buffers.recorded(image_index, self.program_info.frame_number);
+        self.program_info.surfel_count = self.surfel_buffers.surfel_buffer.top() as u32;
         self.descriptor_sets.program_info.replace(
             &self.vk_descriptor_set_allocator,
             super::util::make_device_only_buffer_sized(
@@ -559,9 +773,18 @@ impl Renderer {
             ),
         );
 
-        if let Some(mat) = self.cam_uniform.take() {
+        let cam_uniform = self.cam_uniform.take();
+        let sky = self.sky.take();
+
+        if let Some(mat) = cam_uniform {
             self.view.camera = mat.as_array().to_owned();
+        }
 
+        if let Some(sky) = sky {
+            self.view.set_sky(&sky);
+        }
+
+        if cam_uniform.is_some() || sky.is_some() {
             self.descriptor_sets.view.replace(
                 &self.vk_descriptor_set_allocator,
                 super::util::make_device_only_buffer_sized(
@@ -573,6 +796,28 @@ impl Renderer {
             );
         }
 
+        if let Some(timer) = &self.gpu_timer {
+            timer.mark(&mut builder, image_index, GpuMark::UploadsDone);
+        }
+
+        // Gather light for the surfels before the frame samples them
+        if self.program_info.surfel_count > 0 {
+            builder.bind_pipeline_compute(self.pipelines.surfel.clone());
+            self.descriptor_sets.bind_raytracing(
+                &mut builder,
+                PipelineBindPoint::Compute,
+                self.pipelines.layout.clone(),
+                image_index,
+            );
+            builder
+                .dispatch([self.program_info.surfel_count.div_ceil(256), 1, 1])
+                .unwrap();
+        }
+
+        if let Some(timer) = &self.gpu_timer {
+            timer.mark(&mut builder, image_index, GpuMark::SurfelsDone);
+        }
+
         // Redundant `if let` useage.
         if self.fullscreen_quad.is_none() {
             self.fullscreen_quad = Some(super::util::make_device_only_buffer_sized(
@@ -598,7 +843,7 @@ impl Renderer {
                 RenderPassBeginInfo {
                     clear_values: vec![
                         // Color
-                        Some([0.1, 0.1, 0.1, 1.0].into()),
+                        Some([self.view.sky_horizon[0], self.view.sky_horizon[1], self.view.sky_horizon[2], 1.0].into()),
                         Some([0.0, 0.0, 0.0, 0.0].into()),
                         Some([0.0, 0.0, 0.0, 0.0].into()),
                         Some(1.0.into()),
@@ -612,14 +857,38 @@ impl Renderer {
 
         // Render blocks
         builder.bind_pipeline_graphics(self.pipelines.raytracing.clone());
-        self.descriptor_sets.bind_raytracing(&mut builder, self.pipelines.layout.clone());
+        self.descriptor_sets.bind_raytracing(
+            &mut builder,
+            PipelineBindPoint::Graphics,
+            self.pipelines.layout.clone(),
+            image_index,
+        );
         builder
             .bind_vertex_buffers(0, self.fullscreen_quad.clone().unwrap())
             .draw(3, 1, 0, 0)
             .unwrap();
 
+        // The layout is the same, so the descriptor sets bound above stay bound
+        let hud_buffer = Buffer::from_iter(
+            &self.vk_memory_allocator,
+            BufferCreateInfo::usage(BufferUsage::VERTEX_BUFFER),
+            AllocationCreateInfo::usage(MemoryUsage::Upload),
+            self.hud.take_vertices(self.view.resolution),
+        )
+        .unwrap();
+        let hud_vertex_count = hud_buffer.len() as u32;
+        builder
+            .bind_pipeline_graphics(self.pipelines.hud.clone())
+            .bind_vertex_buffers(0, hud_buffer)
+            .draw(hud_vertex_count, 1, 0, 0)
+            .unwrap();
+
         builder.end_render_pass().unwrap();
 
+        if let Some(timer) = &self.gpu_timer {
+            timer.mark(&mut builder, image_index, GpuMark::PassDone);
+        }
+
         Arc::new(builder.build().unwrap())
     }
 
@@ -628,19 +897,85 @@ impl Renderer {
         world_blocks: Arc<Mutex<WorldBlocks>>,
         block_data: &StaticBlockData,
     ) {
+        let _scope = profiler::scope("update_vertex_buffers");
         let mut lock = world_blocks.lock().unwrap();
+
+        if self.vertex_buffer.recenter(lock.center_chunk()) {
+            self.view.grid_origin = self.vertex_buffer.window.origin_array();
+
+            // Loaded chunks that were outside of the old window
+            for (pos, chunk) in lock.loaded_chunks.iter() {
+                if !self.vertex_buffer.has_chunk(*pos) {
+                    self.vertex_buffer.insert_chunk(chunk, block_data);
+                }
+            }
+        }
+
+        // Requests the world dropped before loading them, so rays can request them again
+        let dropped_requests = lock.dropped_requests.drain(..).collect::<Vec<_>>();
+        for chunk_pos in dropped_requests {
+            if !lock.loaded_chunks.contains_key(&chunk_pos) {
+                self.vertex_buffer.remove_chunk(chunk_pos);
+            }
+        }
+
         let updated_chunks = lock.updated_chunks.drain(..).collect::<Vec<_>>();
         for chunk_pos in updated_chunks {
+            // Surfels may be floating or buried now, new ones get spawned where needed
+            self.surfel_buffers.clear_chunk(chunk_pos);
+
             if let Some(chunk) = lock.loaded_chunks.get(&chunk_pos) {
                 self.vertex_buffer.insert_chunk(chunk, block_data);
             } else {
                 self.vertex_buffer.remove_chunk(chunk_pos);
             }
         }
+
+        // Chunks the shader hit while they were not loaded
+        let requests = self.feedback_buffers.collect();
+        if !requests.chunks.is_empty() {
+            for chunk_pos in lock.request_chunks(requests.chunks) {
+                self.vertex_buffer.mark_requested(chunk_pos);
+            }
+        }
+        if self.program_info.frame_number.is_multiple_of(MEMORY_CHECK_INTERVAL) {
+            let budget_fraction = self.memory_report().budget_fraction();
+            lock.render_distance =
+                self.budget_watcher.check(budget_fraction, lock.render_distance, self.settings.render_distance);
+        }
+        drop(lock);
+
+        self.vertex_buffer.compact();
+        self.surfel_buffers.spawn_requested(&requests.surfels);
+
+        self.debug_target = requests.target;
+        self.program_info.debug_target = match requests.target {
+            Some((pos, face)) => [pos.x, pos.y, pos.z, face as i32 + 1],
+            None => [0; 4],
+        };
+
+        if self.program_info.frame_number.is_multiple_of(SURFEL_RECYCLE_INTERVAL) {
+            let camera = self.view.camera;
+            self.surfel_buffers
+                .recycle_far(Vec3::new(camera[12], camera[13], camera[14]));
+        }
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::new(&self.vk_physical);
+        self.vertex_buffer.report_memory(&mut report);
+        self.surfel_buffers.report_memory(&mut report);
+        self.staging_ring.report_memory(&mut report);
+        report.add_image(Subsystem::Atlas, self.texture_atlas.data.data.len() as u64);
+        for buffer in self.feedback_buffers.buffers.iter() {
+            report.add_buffer(Subsystem::Other, buffer);
+        }
+        report
     }
 
     /// Get the command buffers to be executed on the GPU this frame.
     fn get_command_buffers(&mut self, image_index: usize) -> Vec<Arc<PrimaryAutoCommandBuffer>> {
+        let _scope = profiler::scope("command buffers");
         let mut ret = Vec::new();
         if self.upload_texture_atlas {
             self.upload_texture_atlas = false;
@@ -651,6 +986,7 @@ impl Renderer {
     }
 
     /// Renders the scene
+    #[allow(clippy::arc_with_non_send_sync)]
     pub fn render(
         &mut self,
         world_blocks: Arc<Mutex<WorldBlocks>>,
@@ -705,6 +1041,7 @@ impl Renderer {
             )
             .boxed() // Box it into a dyn GpuFuture for easier handling
             .then_signal_fence_and_flush();
+        self.staging_ring.submitted();
 
         self.fences[image_i as usize] = match present_future {
             Ok(value) => Some(Arc::new(value)),
@@ -724,21 +1061,72 @@ impl Renderer {
     }
 }
 
+fn texture_filter(setting: TextureFilter) -> Filter {
+    match setting {
+        TextureFilter::Nearest => Filter::Nearest,
+        TextureFilter::Linear => Filter::Linear,
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Zeroable, Pod)]
 pub struct View {
     camera: [f32; 16],
     resolution: [u32; 2],
     fov: f32,
+    _pad: u32,
+    /// Chunk position of the brickgrid window's corner
+    grid_origin: [i32; 2],
+    _pad2: [u32; 2],
+    light_direction: [f32; 3],
+    _pad3: u32,
+    light_color: [f32; 3],
+    _pad4: u32,
+    sky_zenith: [f32; 3],
+    _pad5: u32,
+    sky_horizon: [f32; 3],
+    _pad6: u32,
+}
+
+impl View {
+    pub fn set_sky(&mut self, sky: &Sky) {
+        self.light_direction = sky.light_direction.into();
+        self.light_color = sky.light_color.into();
+        self.sky_zenith = sky.zenith.into();
+        self.sky_horizon = sky.horizon.into();
+    }
+}
+
+impl GpuStruct for View {
+    const GLSL_NAME: &'static str = "View";
+    const KIND: GpuStructKind = GpuStructKind::UniformBlock;
+
+    fn fields() -> Vec<GpuField> {
+        gpu_fields!(View {
+            camera: GlslType::Mat4,
+            resolution: GlslType::UVec2,
+            fov: GlslType::Float,
+            grid_origin: GlslType::IVec2,
+            light_direction: GlslType::Vec3,
+            light_color: GlslType::Vec3,
+            sky_zenith: GlslType::Vec3,
+            sky_horizon: GlslType::Vec3,
+        })
+    }
 }
 
 impl Default for View {
     fn default() -> Self {
-        Self {
+        let mut ret = Self {
             camera: Mat4::identity().as_array().to_owned(),
             resolution: [0; 2],
             fov: 90.0,
-        }
+            _pad: 0,
+            grid_origin: [0; 2],
+            ..Self::zeroed()
+        };
+        ret.set_sky(&Sky::from_time_of_day(0.5));
+        ret
     }
 }
 
@@ -751,13 +1139,6 @@ pub struct FaceLighting {
     _pad2: u32,
 }
 
-const BLOCK_FACE_LIGHTING: FaceLighting = FaceLighting {
-    positive: [0.6, 1.0, 0.8],
-    negative: [0.6, 0.4, 0.8],
-    _pad1: 0,
-    _pad2: 0,
-};
-
 impl Default for FaceLighting {
     fn default() -> Self {
         Self {
@@ -769,11 +1150,9 @@ impl Default for FaceLighting {
     }
 }
 
-struct QueueFamilyIndices {
-    graphics: u32,
-}
-
 pub struct Pipelines {
     pub raytracing: Arc<GraphicsPipeline>,
+    pub surfel: Arc<ComputePipeline>,
+    pub hud: Arc<GraphicsPipeline>,
     pub layout: Arc<PipelineLayout>,
 }
@@ -0,0 +1,79 @@
+// Also used by `build.rs`, so this can't depend on anything else in the crate.
+
+use std::{
+    ffi::OsStr,
+    fs::{self},
+    path::Path,
+};
+
+use shaderc::{CompileOptions, OptimizationLevel, ShaderKind, IncludeType, IncludeCallbackResult, ResolvedInclude};
+
+/// Shaders with a `main`, the rest of the shader directory is only included by them
+pub const ENTRY_SHADERS: [&str; 5] = ["shader.vert", "shader.frag", "surfel.comp", "hud.vert", "hud.frag"];
+
+/// Compiles `path` inside `shader_dir` to SPIR-V, resolving `#include`s relative to it.
+pub fn compile(shader_dir: &Path, path: &str) -> Result<Vec<u32>, String> {
+    let compiler = shaderc::Compiler::new().unwrap();
+
+    let shader_path = shader_dir.join(path);
+    let src = match fs::read_to_string(shader_path) {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Error reading shader file '{}': {}", path, e)),
+    };
+
+    let extension = Path::new(path).extension().and_then(OsStr::to_str).unwrap();
+    let shader_kind = match_shader_ext(extension);
+    let mut compile_options = CompileOptions::new().unwrap();
+
+    let include_dir = shader_dir.to_path_buf();
+    compile_options.set_include_callback(move |name, include_type, source_name, depth| {
+        include_callback(&include_dir, name, include_type, source_name, depth)
+    });
+    compile_options.set_generate_debug_info();
+    compile_options.set_optimization_level(OptimizationLevel::Performance);
+
+    let shader_binary = match compiler.compile_into_spirv(
+        &src,
+        shader_kind,
+        path,
+        "main",
+        Some(&compile_options),
+    ) {
+        Ok(b) => b,
+        Err(e) => return Err(format!("Error compiling shader file '{}': {}", path, e)),
+    };
+
+    Ok(shader_binary.as_binary().to_vec())
+}
+
+fn include_callback(
+    shader_dir: &Path,
+    name: &str,
+    _include_type: IncludeType,
+    source_name: &str,
+    _depth: usize,
+) -> IncludeCallbackResult {
+    let source_path = shader_dir.join(source_name);
+
+    let include_path = source_path.parent().unwrap().join(name);
+
+    let result = fs::read_to_string(include_path);
+    let include_file = match result {
+        Ok(file) => file,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    Ok(ResolvedInclude {
+        resolved_name: name.to_string(),
+        content: include_file,
+    })
+}
+
+fn match_shader_ext(ext: &str) -> ShaderKind {
+    match ext {
+        "vert" => ShaderKind::Vertex,
+        "frag" => ShaderKind::Fragment,
+        "comp" => ShaderKind::Compute,
+        e => panic!("Unsupported shader extension: {}", e),
+    }
+}
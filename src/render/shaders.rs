@@ -1,89 +1,63 @@
+use std::sync::Arc;
+#[cfg(feature = "dev-shaders")]
 use std::{
-    ffi::OsStr,
-    fs::{self},
     path::Path,
-    sync::Arc,
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
 };
 
-use shaderc::{CompileOptions, OptimizationLevel, ShaderKind, IncludeType, IncludeCallbackResult, ResolvedInclude};
+#[cfg(feature = "dev-shaders")]
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use vulkano::{device::Device, shader::ShaderModule};
 
-pub trait LoadFromPath {
-    fn load(device: Arc<Device>, path: &str) -> Arc<Self>;
-}
+#[cfg(feature = "dev-shaders")]
+use super::shader_compiler;
 
-impl LoadFromPath for ShaderModule {
-    fn load(device: Arc<Device>, path: &str) -> Arc<Self> {
-        let compiler = shaderc::Compiler::new().unwrap();
+/// Only read in `dev-shaders` builds, others embed the shaders compiled by `build.rs`
+pub const SHADER_DIR: &str = "./src/shaders";
 
-        let shader_path = format!("./src/shaders/{}", path);
-        let src = match fs::read_to_string(shader_path.clone()) {
+/// Editors tend to write a file in several steps, wait for them to finish
+#[cfg(feature = "dev-shaders")]
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+pub trait LoadFromPath {
+    /// Panics if the shader can't be loaded
+    fn load(device: Arc<Device>, path: &str) -> Arc<Self> {
+        match Self::try_load(device, path) {
             Ok(s) => s,
-            Err(e) => panic!("Error reading shader file '{}': {}", path, e),
-        };
-
-        let extension = Path::new(path).extension().and_then(OsStr::to_str).unwrap();
-        let shader_kind = match_shader_ext(extension);
-        let mut compile_options = CompileOptions::new().unwrap();
-
-        compile_options.set_include_callback(include_callback);
-        compile_options.set_generate_debug_info();
-        compile_options.set_optimization_level(OptimizationLevel::Performance);
-
-        // let shader_pre = match compiler.compile_into_spirv_assembly(
-        //     &src,
-        //     shader_kind,
-        //     path,
-        //     "main",
-        //     Some(&compile_options),
-        // ) {
-        //     Ok(b) => b,
-        //     Err(e) => panic!("Error compiling shader file '{}': {}", path, e),
-        // };
-
-        // let mut asm_out = File::create(shader_path + ".spv").unwrap();
-        // asm_out.write_all(shader_pre.as_text().as_bytes()).unwrap();
-
-        let shader_binary = match compiler.compile_into_spirv(
-            &src,
-            shader_kind,
-            path,
-            "main",
-            Some(&compile_options),
-        ) {
-            Ok(b) => b,
-            Err(e) => panic!("Error compiling shader file '{}': {}", path, e),
-        };
-
-        unsafe { ShaderModule::from_words(device, shader_binary.as_binary()).unwrap() }
+            Err(e) => panic!("{}", e),
+        }
     }
+
+    fn try_load(device: Arc<Device>, path: &str) -> Result<Arc<Self>, String>;
 }
 
-fn include_callback(name: &str, _include_type: IncludeType, source_name: &str, _depth: usize) -> IncludeCallbackResult {
-    let source_path_str = format!("./src/shaders/{}", source_name);
-    let source_path = Path::new(&source_path_str);
+impl LoadFromPath for ShaderModule {
+    #[cfg(feature = "dev-shaders")]
+    fn try_load(device: Arc<Device>, path: &str) -> Result<Arc<Self>, String> {
+        let words = shader_compiler::compile(Path::new(SHADER_DIR), path)?;
 
-    let include_path = source_path.parent().unwrap().join(name);
+        unsafe { ShaderModule::from_words(device, &words) }
+            .map_err(|e| format!("Error creating shader module '{}': {}", path, e))
+    }
 
-    let result = fs::read_to_string(include_path);
-    let include_file = match result {
-        Ok(file) => file,
-        Err(e) => return Err(e.to_string()),
-    };
+    #[cfg(not(feature = "dev-shaders"))]
+    fn try_load(device: Arc<Device>, path: &str) -> Result<Arc<Self>, String> {
+        let words = embedded_spirv(path)
+            .ok_or_else(|| format!("Shader '{}' is not embedded in the binary", path))?;
 
-    Ok(ResolvedInclude {
-        resolved_name: name.to_string(),
-        content: include_file,
-    })
+        unsafe { ShaderModule::from_words(device, words) }
+            .map_err(|e| format!("Error creating shader module '{}': {}", path, e))
+    }
 }
 
-fn match_shader_ext(ext: &str) -> ShaderKind {
-    match ext {
-        "vert" => ShaderKind::Vertex,
-        "frag" => ShaderKind::Fragment,
-        "comp" => ShaderKind::Compute,
-        e => panic!("Unsupported shader extension: {}", e),
-    }
+/// SPIR-V of every shader in `shader_compiler::ENTRY_SHADERS`, compiled by `build.rs`
+#[cfg(not(feature = "dev-shaders"))]
+const EMBEDDED_SHADERS: &[(&str, &[u32])] = include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+
+#[cfg(not(feature = "dev-shaders"))]
+fn embedded_spirv(path: &str) -> Option<&'static [u32]> {
+    EMBEDDED_SHADERS.iter().find(|(name, _)| *name == path).map(|(_, words)| *words)
 }
 
 pub struct ShaderPair {
@@ -102,4 +76,60 @@ impl ShaderPair {
             fragment: ShaderModule::load(device, &format!("{path}.frag")), // Redundant clone for `device`
         }
     }
+
+    pub fn try_load(device: Arc<Device>, path: &str) -> Result<Self, String> {
+        Ok(Self {
+            vertex: ShaderModule::try_load(device.clone(), &format!("{path}.vert"))?,
+            fragment: ShaderModule::try_load(device, &format!("{path}.frag"))?,
+        })
+    }
+}
+
+/// Watches the shader directory so shaders can be recompiled while running.
+#[cfg(feature = "dev-shaders")]
+pub struct ShaderWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<()>,
+    last_change: Option<Instant>,
+}
+
+#[cfg(feature = "dev-shaders")]
+impl ShaderWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            match res {
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    let _ = sender.send(());
+                }
+                Ok(_) => (),
+                Err(e) => println!("WARNING: Shader watcher error: {}", e),
+            }
+        })?;
+        watcher.watch(Path::new(SHADER_DIR), RecursiveMode::Recursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            last_change: None,
+        })
+    }
+
+    /// Returns `true` once the shaders have stopped changing for a moment.
+    ///
+    /// Any file can be included by any shader, so there is no telling which ones changed.
+    pub fn poll(&mut self) -> bool {
+        if self.events.try_iter().count() > 0 {
+            self.last_change = Some(Instant::now());
+        }
+
+        match self.last_change {
+            Some(t) if t.elapsed() >= RELOAD_DELAY => {
+                self.last_change = None;
+                true
+            }
+            _ => false,
+        }
+    }
 }
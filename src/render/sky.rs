@@ -0,0 +1,63 @@
+use std::f32::consts::TAU;
+
+use ultraviolet::Vec3;
+
+/// Tilts the sun's path so it is never straight overhead
+const SUN_TILT: f32 = 0.4;
+
+const NOON_SUN_COLOR: Vec3 = Vec3::new(1.0, 0.97, 0.9);
+const HORIZON_SUN_COLOR: Vec3 = Vec3::new(1.0, 0.55, 0.25);
+const MOON_COLOR: Vec3 = Vec3::new(0.08, 0.1, 0.16);
+
+const DAY_ZENITH: Vec3 = Vec3::new(0.35, 0.55, 0.99);
+const DAY_HORIZON: Vec3 = Vec3::new(0.65, 0.78, 0.99);
+const NIGHT_ZENITH: Vec3 = Vec3::new(0.005, 0.01, 0.03);
+const NIGHT_HORIZON: Vec3 = Vec3::new(0.02, 0.035, 0.07);
+const SUNSET_GLOW: Vec3 = Vec3::new(0.6, 0.25, 0.05);
+
+/// Lighting of the scene at one time of day.
+#[derive(Copy, Clone, Debug)]
+pub struct Sky {
+    /// Points towards the sun during the day and towards the moon at night
+    pub light_direction: Vec3,
+    pub light_color: Vec3,
+    pub zenith: Vec3,
+    pub horizon: Vec3,
+}
+
+impl Sky {
+    /// `time_of_day` is in `[0, 1)`, `0` is midnight and `0.5` is noon.
+    pub fn from_time_of_day(time_of_day: f32) -> Self {
+        // Rises at 0.25 and sets at 0.75
+        let angle = (time_of_day - 0.25) * TAU;
+        let sun_direction = Vec3::new(angle.cos(), angle.sin(), SUN_TILT).normalized();
+        let sun_height = sun_direction.y;
+
+        // Both fade out around the horizon so switching between them is not visible
+        let (light_direction, light_color) = if sun_height >= 0.0 {
+            let color = lerp(HORIZON_SUN_COLOR, NOON_SUN_COLOR, smoothstep(0.0, 0.4, sun_height));
+            (sun_direction, color * smoothstep(-0.05, 0.1, sun_height))
+        } else {
+            (-sun_direction, MOON_COLOR * smoothstep(-0.05, 0.1, -sun_height))
+        };
+
+        let day = smoothstep(-0.15, 0.2, sun_height);
+        let glow = (1.0 - (sun_height.abs() * 5.0)).max(0.0);
+
+        Self {
+            light_direction,
+            light_color,
+            zenith: lerp(NIGHT_ZENITH, DAY_ZENITH, day),
+            horizon: lerp(NIGHT_HORIZON, DAY_HORIZON, day) + SUNSET_GLOW * glow,
+        }
+    }
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    a + (b - a) * t
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
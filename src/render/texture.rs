@@ -120,6 +120,46 @@ impl TextureAtlas {
         let alloc = self.allocations[handle.inner_index as usize];
         alloc.to_quad_uv()
     }
+
+    /// Average color of the visible texels of a texture, opaque
+    pub fn average_color(&self, texture: u32) -> [u8; 4] {
+        let texel = self.uvs[texture as usize];
+        let row_len = self.data.dimensions.x as usize;
+
+        let mut sum = [0u64; 3];
+        let mut count = 0;
+        for y in texel.offset[1] as usize..(texel.offset[1] + texel.size[1]) as usize {
+            for x in texel.offset[0] as usize..(texel.offset[0] + texel.size[0]) as usize {
+                let rgba = &self.data.data[(y * row_len + x) * 4..][..4];
+                if rgba[3] > 0 {
+                    sum.iter_mut().zip(rgba).for_each(|(s, c)| *s += *c as u64);
+                    count += 1;
+                }
+            }
+        }
+
+        let [r, g, b] = sum.map(|s| (s / count.max(1)) as u8);
+        [r, g, b, 255]
+    }
+
+    /// Copy of a single texture out of the atlas
+    pub fn texture_image(&self, texture: u32) -> ImageData {
+        let texel = self.uvs[texture as usize];
+        let row_len = self.data.dimensions.x as usize;
+        let width = texel.size[0] as usize;
+
+        let mut data = Vec::with_capacity(width * texel.size[1] as usize * 4);
+        for y in texel.offset[1] as usize..(texel.offset[1] + texel.size[1]) as usize {
+            let start = y * row_len + texel.offset[0] as usize;
+            data.extend_from_slice(&self.data.data[start * 4..(start + width) * 4]);
+        }
+        ImageData::new(data, UVec2::new(texel.size[0] as u32, texel.size[1] as u32))
+    }
+
+    /// File name of a texture without the extension
+    pub fn texture_name(&self, texture: u32) -> Option<&str> {
+        self.name_index_map.iter().find(|(_, index)| **index == texture as usize).map(|(name, _)| name.as_str())
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
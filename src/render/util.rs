@@ -14,7 +14,10 @@ use vulkano::{
 };
 use winit::window::Window;
 
-use super::mesh::quad::TexelTexture;
+use super::{
+    gpu_layout::{gpu_fields, GlslType, GpuField, GpuStruct, GpuStructKind},
+    mesh::quad::TexelTexture,
+};
 
 pub trait GetWindow {
     fn get_window(&self) -> Option<Arc<Window>>;
@@ -22,10 +25,7 @@ pub trait GetWindow {
 
 impl GetWindow for Arc<Surface> {
     fn get_window(&self) -> Option<Arc<Window>> {
-        match self.object().unwrap().clone().downcast::<Window>() {
-            Ok(w) => Some(w),
-            Err(_) => None,
-        }
+        self.object().unwrap().clone().downcast::<Window>().ok()
     }
 }
 
@@ -202,11 +202,92 @@ where
     ret
 }
 
+/// What the raytracer shows instead of the normal image, stored in `ProgramInfo::debug_mode`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugMode {
+    None,
+    /// Colors every surface by the surfel covering it, uncovered surfaces are magenta
+    SurfelCoverage,
+    /// Draws chunk borders in yellow and section borders in cyan
+    ChunkBorders,
+    /// Colors the brickgrid cell each ray stopped in by its `BrickmapPointer` state
+    PointerState,
+    /// Colors each pixel by the number of traversal steps its ray took
+    StepHeatmap,
+}
+
+impl DebugMode {
+    pub const ALL: [Self; 5] = [
+        Self::None,
+        Self::SurfelCoverage,
+        Self::ChunkBorders,
+        Self::PointerState,
+        Self::StepHeatmap,
+    ];
+
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_id(id: u32) -> Self {
+        Self::ALL.get(id as usize).copied().unwrap_or(Self::None)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::SurfelCoverage => "surfel_coverage",
+            Self::ChunkBorders => "chunk_borders",
+            Self::PointerState => "pointer_state",
+            Self::StepHeatmap => "step_heatmap",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    pub fn next(self) -> Self {
+        Self::from_id((self.id() + 1) % Self::ALL.len() as u32)
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct ProgramInfo {
     pub frame_number: u32,
     pub start: u32,
+    /// `0` renders normally
+    pub debug_mode: u32,
+    /// Number of surfel slots the compute shader has to update
+    pub surfel_count: u32,
+    /// Shadow rays per pixel, jittered over the sun's disk
+    pub shadow_samples: u32,
+    /// Ambient occlusion rays per pixel
+    pub ao_samples: u32,
+    /// Brickgrid cells a ray can pass before giving up
+    pub max_ray_steps: u32,
+    pub _pad: u32,
+    /// Block outlined in the debug modes, `w` is `0` if there is none
+    pub debug_target: [i32; 4],
+}
+
+impl GpuStruct for ProgramInfo {
+    const GLSL_NAME: &'static str = "ProgramInfo";
+    const KIND: GpuStructKind = GpuStructKind::UniformBlock;
+
+    fn fields() -> Vec<GpuField> {
+        gpu_fields!(ProgramInfo {
+            frame_number: GlslType::Uint,
+            start: GlslType::Uint,
+            debug_mode: GlslType::Uint,
+            surfel_count: GlslType::Uint,
+            shadow_samples: GlslType::Uint,
+            ao_samples: GlslType::Uint,
+            max_ray_steps: GlslType::Uint,
+            debug_target: GlslType::IVec4,
+        })
+    }
 }
 
 // https://rust-lang.github.io/rust-clippy/master/index.html#/new_without_default
@@ -224,6 +305,13 @@ impl ProgramInfo {
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
                 .subsec_nanos(),
+            debug_mode: 0,
+            surfel_count: 0,
+            shadow_samples: 2,
+            ao_samples: 2,
+            max_ray_steps: 180,
+            _pad: 0,
+            debug_target: [0; 4],
         }
     }
 }
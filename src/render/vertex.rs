@@ -20,6 +20,18 @@ pub struct Vertex2D {
     pub position: [f32; 2],
 }
 
+/// In normalized device coordinates, `uv` is in texels of the texture atlas
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Zeroable, Pod, VertDerive)]
+pub struct HudVertex {
+    #[format(R32G32_SFLOAT)]
+    pub position: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub color: [f32; 4],
+}
+
 #[derive(Debug, Clone)]
 pub struct Vertex {
     pub position: Vec3,
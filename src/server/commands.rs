@@ -0,0 +1,294 @@
+use ultraviolet::{IVec2, IVec3};
+
+use crate::world::{export::ExportFormat, schematic::PasteOptions};
+
+/// Admin commands typed into the console, the server runs them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Prints the current time
+    TimeQuery,
+    /// Sets the time of day in hours
+    TimeSet(f32),
+    TimeAdd(f32),
+    TimeFreeze(bool),
+    Save,
+    /// Prints frame time percentiles of every profiled scope
+    ProfileSummary,
+    /// Writes a Chrome trace of the recent frames, to `profiler::TRACE_PATH` without a path
+    ProfileExport(Option<String>),
+}
+
+/// Console commands for graphics settings, debug views, GPU memory, the texture atlas, the loaded blocks
+/// and the clipboard, which only the client has. See `client::execute_command`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientCommand {
+    /// Prints the graphics settings
+    Settings,
+    /// Changes one graphics setting, the value is JSON
+    Set(String, String),
+    /// Sets the debug view by name, lists them without one
+    Debug(Option<String>),
+    /// Prints the usage and fragmentation of the brickmap buffers
+    Buffers,
+    /// Prints the GPU memory used by each part of the renderer and the heap budgets
+    Memory,
+    /// Meshes the blocks between two corners and writes them with their textures, to `export::EXPORT_DIR` without a path
+    Export(ExportFormat, (IVec3, IVec3), Option<String>),
+    /// Places a `.vox` file with its lowest corner at a position, turned a number of quarter turns
+    VoxImport(String, IVec3, u32),
+    /// Writes the blocks between two corners to a `.vox` file
+    VoxExport(String, (IVec3, IVec3)),
+    /// Writes map tiles of the loaded chunks between two chunk corners, to `map::MAP_DIR` without a directory
+    Map((IVec2, IVec2), Option<String>),
+    /// Copies the blocks between two corners into the clipboard
+    SchematicCopy((IVec3, IVec3)),
+    /// Places the clipboard with its lowest corner at a position
+    SchematicPaste(IVec3, PasteOptions),
+    /// Writes the clipboard to a file, bare names go in `schematic::SCHEMATIC_DIR`
+    SchematicSave(String),
+    /// Reads a file into the clipboard
+    SchematicLoad(String),
+}
+
+/// A line typed into the console
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsoleCommand {
+    Server(Command),
+    Client(ClientCommand),
+}
+
+impl From<Command> for ConsoleCommand {
+    fn from(command: Command) -> Self {
+        Self::Server(command)
+    }
+}
+
+impl From<ClientCommand> for ConsoleCommand {
+    fn from(command: ClientCommand) -> Self {
+        Self::Client(command)
+    }
+}
+
+impl ConsoleCommand {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim().trim_start_matches('/');
+        let args = input.split_whitespace().collect::<Vec<_>>();
+
+        match args.as_slice() {
+            ["time"] => Ok(Command::TimeQuery.into()),
+            ["time", "set", value] => Ok(Command::TimeSet(parse_hours(value)?).into()),
+            ["time", "add", value] => Ok(Command::TimeAdd(parse_number(value)?).into()),
+            ["time", "freeze"] => Ok(Command::TimeFreeze(true).into()),
+            ["time", "unfreeze"] => Ok(Command::TimeFreeze(false).into()),
+            ["save"] => Ok(Command::Save.into()),
+            ["settings"] => Ok(ClientCommand::Settings.into()),
+            ["debug"] => Ok(ClientCommand::Debug(None).into()),
+            ["debug", mode] => Ok(ClientCommand::Debug(Some(mode.to_string())).into()),
+            ["profile"] => Ok(Command::ProfileSummary.into()),
+            ["buffers"] => Ok(ClientCommand::Buffers.into()),
+            ["memory"] => Ok(ClientCommand::Memory.into()),
+            ["profile", "export"] => Ok(Command::ProfileExport(None).into()),
+            ["profile", "export", path] => Ok(Command::ProfileExport(Some(path.to_string())).into()),
+            ["export", format, args @ ..] if args.len() == 6 || args.len() == 7 => {
+                let format = ExportFormat::parse(format)
+                    .ok_or_else(|| format!("Unknown format \"{}\", use obj or gltf", format))?;
+                let corners = (parse_block_pos(&args[0..3])?, parse_block_pos(&args[3..6])?);
+                Ok(ClientCommand::Export(format, corners, args.get(6).map(|p| p.to_string())).into())
+            },
+            ["vox", "import", path, args @ ..] if args.len() == 3 || args.len() == 4 => {
+                let turns = match args.get(3) {
+                    Some(turns) => turns.parse::<u32>().map_err(|_| format!("\"{}\" is not a number of quarter turns", turns))?,
+                    None => 0,
+                };
+                Ok(ClientCommand::VoxImport(path.to_string(), parse_block_pos(&args[0..3])?, turns).into())
+            },
+            ["vox", "export", path, args @ ..] if args.len() == 6 => {
+                let corners = (parse_block_pos(&args[0..3])?, parse_block_pos(&args[3..6])?);
+                Ok(ClientCommand::VoxExport(path.to_string(), corners).into())
+            },
+            ["map", args @ ..] if args.len() == 4 || args.len() == 5 => {
+                let corners = (parse_chunk_pos(&args[0..2])?, parse_chunk_pos(&args[2..4])?);
+                Ok(ClientCommand::Map(corners, args.get(4).map(|dir| dir.to_string())).into())
+            },
+            ["schematic", "copy", args @ ..] if args.len() == 6 => {
+                Ok(ClientCommand::SchematicCopy((parse_block_pos(&args[0..3])?, parse_block_pos(&args[3..6])?)).into())
+            },
+            ["schematic", "paste", args @ ..] if args.len() >= 3 => {
+                Ok(ClientCommand::SchematicPaste(parse_block_pos(&args[0..3])?, parse_paste_options(&args[3..])?).into())
+            },
+            ["schematic", "save", name] => Ok(ClientCommand::SchematicSave(name.to_string()).into()),
+            ["schematic", "load", name] => Ok(ClientCommand::SchematicLoad(name.to_string()).into()),
+            ["set", name, value @ ..] if !value.is_empty() => {
+                Ok(ClientCommand::Set(name.to_string(), value.join(" ")).into())
+            },
+            [] => Err("Empty command".to_string()),
+            _ => Err(format!("Unknown command \"{}\"", input)),
+        }
+    }
+}
+
+fn parse_hours(value: &str) -> Result<f32, String> {
+    match value {
+        "midnight" => Ok(0.0),
+        "sunrise" => Ok(6.0),
+        "day" => Ok(8.0),
+        "noon" => Ok(12.0),
+        "sunset" => Ok(18.0),
+        "night" => Ok(21.0),
+        _ => parse_number(value),
+    }
+}
+
+fn parse_block_pos(values: &[&str]) -> Result<IVec3, String> {
+    let coords = values
+        .iter()
+        .map(|v| v.parse::<i32>().map_err(|_| format!("\"{}\" is not a block coordinate", v)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(IVec3::new(coords[0], coords[1], coords[2]))
+}
+
+/// Also parses the arguments of the command line tools
+pub(crate) fn parse_chunk_pos(values: &[impl AsRef<str>]) -> Result<IVec2, String> {
+    let coords = values
+        .iter()
+        .map(|v| v.as_ref().parse::<i32>().map_err(|_| format!("\"{}\" is not a chunk coordinate", v.as_ref())))
+        .collect::<Result<Vec<_>, _>>()?;
+    match coords[..] {
+        [x, z] => Ok(IVec2::new(x, z)),
+        _ => Err(format!("A chunk position needs 2 coordinates, not {}", coords.len())),
+    }
+}
+
+/// A number of quarter turns and the flags `mirror_x`, `mirror_z` and `mask_air`, in any order
+fn parse_paste_options(values: &[&str]) -> Result<PasteOptions, String> {
+    let mut options = PasteOptions::default();
+    for value in values {
+        match *value {
+            "mirror_x" => options.mirror_x = true,
+            "mirror_z" => options.mirror_z = true,
+            "mask_air" => options.mask_air = true,
+            turns => {
+                options.quarter_turns = turns
+                    .parse::<u32>()
+                    .map_err(|_| format!("\"{}\" is not a number of quarter turns or a paste option", turns))?
+            },
+        }
+    }
+    Ok(options)
+}
+
+fn parse_number(value: &str) -> Result<f32, String> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| format!("\"{}\" is not a number", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_time_commands() {
+        assert_eq!(ConsoleCommand::parse("time"), Ok(Command::TimeQuery.into()));
+        assert_eq!(ConsoleCommand::parse("/time set noon"), Ok(Command::TimeSet(12.0).into()));
+        assert_eq!(ConsoleCommand::parse("time set 13.5"), Ok(Command::TimeSet(13.5).into()));
+        assert_eq!(ConsoleCommand::parse("time add -2"), Ok(Command::TimeAdd(-2.0).into()));
+        assert_eq!(ConsoleCommand::parse("time freeze"), Ok(Command::TimeFreeze(true).into()));
+        assert!(ConsoleCommand::parse("time set later").is_err());
+        assert!(ConsoleCommand::parse("").is_err());
+    }
+
+    #[test]
+    fn parse_set_command() {
+        assert_eq!(ConsoleCommand::parse("settings"), Ok(ClientCommand::Settings.into()));
+        assert_eq!(
+            ConsoleCommand::parse("/set window_size [800, 600]"),
+            Ok(ClientCommand::Set("window_size".to_string(), "[800, 600]".to_string()).into()),
+        );
+        assert!(ConsoleCommand::parse("set fov").is_err());
+    }
+
+    #[test]
+    fn parse_debug_command() {
+        assert_eq!(ConsoleCommand::parse("debug"), Ok(ClientCommand::Debug(None).into()));
+        assert_eq!(ConsoleCommand::parse("debug step_heatmap"), Ok(ClientCommand::Debug(Some("step_heatmap".to_string())).into()));
+    }
+
+    #[test]
+    fn parse_profile_command() {
+        assert_eq!(ConsoleCommand::parse("profile"), Ok(Command::ProfileSummary.into()));
+        assert_eq!(ConsoleCommand::parse("profile export"), Ok(Command::ProfileExport(None).into()));
+        assert_eq!(ConsoleCommand::parse("/profile export trace.json"), Ok(Command::ProfileExport(Some("trace.json".to_string())).into()));
+        assert!(ConsoleCommand::parse("profile clear").is_err());
+    }
+
+    #[test]
+    fn parse_export_command() {
+        assert_eq!(
+            ConsoleCommand::parse("export obj 0 60 0 -16 80 16"),
+            Ok(ClientCommand::Export(ExportFormat::Obj, (IVec3::new(0, 60, 0), IVec3::new(-16, 80, 16)), None).into()),
+        );
+        assert_eq!(
+            ConsoleCommand::parse("export gltf 0 0 0 1 1 1 house.gltf"),
+            Ok(ClientCommand::Export(ExportFormat::Gltf, (IVec3::zero(), IVec3::one()), Some("house.gltf".to_string())).into()),
+        );
+        assert!(ConsoleCommand::parse("export fbx 0 0 0 1 1 1").is_err());
+        assert!(ConsoleCommand::parse("export obj 0 0 0 1 1").is_err());
+        assert!(ConsoleCommand::parse("export obj 0 0 0 1 1 1.5").is_err());
+    }
+
+    #[test]
+    fn parse_vox_commands() {
+        assert_eq!(
+            ConsoleCommand::parse("vox import castle.vox 10 64 -20 1"),
+            Ok(ClientCommand::VoxImport("castle.vox".to_string(), IVec3::new(10, 64, -20), 1).into()),
+        );
+        assert_eq!(
+            ConsoleCommand::parse("vox import castle.vox 10 64 -20"),
+            Ok(ClientCommand::VoxImport("castle.vox".to_string(), IVec3::new(10, 64, -20), 0).into()),
+        );
+        assert_eq!(
+            ConsoleCommand::parse("vox export out.vox 0 0 0 8 8 8"),
+            Ok(ClientCommand::VoxExport("out.vox".to_string(), (IVec3::zero(), IVec3::new(8, 8, 8))).into()),
+        );
+        assert!(ConsoleCommand::parse("vox import castle.vox 10 64").is_err());
+        assert!(ConsoleCommand::parse("vox import castle.vox 10 64 -20 -1").is_err());
+    }
+
+    #[test]
+    fn parse_map_command() {
+        assert_eq!(
+            ConsoleCommand::parse("map -4 -4 4 4"),
+            Ok(ClientCommand::Map((IVec2::new(-4, -4), IVec2::new(4, 4)), None).into()),
+        );
+        assert_eq!(
+            ConsoleCommand::parse("map 0 0 1 1 review"),
+            Ok(ClientCommand::Map((IVec2::zero(), IVec2::one()), Some("review".to_string())).into()),
+        );
+        assert!(ConsoleCommand::parse("map 0 0 1").is_err());
+    }
+
+    #[test]
+    fn parse_schematic_commands() {
+        assert_eq!(
+            ConsoleCommand::parse("schematic copy 0 60 0 8 70 8"),
+            Ok(ClientCommand::SchematicCopy((IVec3::new(0, 60, 0), IVec3::new(8, 70, 8))).into()),
+        );
+        assert_eq!(
+            ConsoleCommand::parse("schematic paste 4 64 -4"),
+            Ok(ClientCommand::SchematicPaste(IVec3::new(4, 64, -4), PasteOptions::default()).into()),
+        );
+        assert_eq!(
+            ConsoleCommand::parse("schematic paste 4 64 -4 mask_air 3 mirror_z"),
+            Ok(ClientCommand::SchematicPaste(
+                IVec3::new(4, 64, -4),
+                PasteOptions { quarter_turns: 3, mirror_x: false, mirror_z: true, mask_air: true },
+            ).into()),
+        );
+        assert_eq!(ConsoleCommand::parse("schematic save house"), Ok(ClientCommand::SchematicSave("house".to_string()).into()));
+        assert!(ConsoleCommand::parse("schematic paste 4 64 -4 upside_down").is_err());
+        assert!(ConsoleCommand::parse("schematic copy 0 0 0 1 1").is_err());
+    }
+}
@@ -1,4 +1,7 @@
 pub mod server;
 pub mod components;
 pub mod actions;
-pub mod hierarchy;
\ No newline at end of file
+pub mod hierarchy;
+pub mod world_time;
+pub mod save;
+pub mod commands;
\ No newline at end of file
@@ -0,0 +1,35 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::world_time::WorldTime;
+
+pub const SAVE_PATH: &str = "./saves/world.json";
+
+/// World state that is not stored in the chunks themselves.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WorldSave {
+    pub time: WorldTime,
+}
+
+impl WorldSave {
+    /// Returns `None` if there is no save yet.
+    pub fn load(path: impl AsRef<Path>) -> Option<Self> {
+        let src = fs::read_to_string(path.as_ref()).ok()?;
+        match serde_json::from_str(&src) {
+            Ok(save) => Some(save),
+            Err(e) => {
+                println!("WARNING: Could not read save {:?}: {}", path.as_ref(), e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
@@ -3,13 +3,14 @@ use std::f32::consts::PI;
 use ultraviolet::{Vec3, Vec2, Rotor2};
 use winit::event::VirtualKeyCode;
 
-use crate::{physics::solver::PhysicsSolver, render::camera::camera::Camera, event_handler::InputHandler, world::{world_blocks::WorldBlocks, block_data::StaticBlockData}};
+use crate::{physics::solver::PhysicsSolver, profiler, render::camera::camera::Camera, event_handler::InputHandler, settings::Settings, world::{world_blocks::WorldBlocks, block_data::StaticBlockData}};
 
-use super::{components::{Player, Translation, Velocity, PhysicsEntity, Hitbox, Gravity}, hierarchy::{Hierarchy, Parent}};
+use super::{components::{Player, Translation, Velocity, PhysicsEntity, Hitbox, Gravity}, hierarchy::{Hierarchy, Parent}, world_time::WorldTime, save::{WorldSave, SAVE_PATH}, commands::Command};
 
 pub struct Server {
     pub world: hecs::World,
     pub physics_solver: PhysicsSolver,
+    pub time: WorldTime,
 }
 
 impl Server {
@@ -19,12 +20,16 @@ impl Server {
             physics_solver: PhysicsSolver {
                 sub_steps: 4,
                 gravity: -1.5,
-                ..Default::default()
-            }
+            },
+            time: WorldTime::default(),
         }
     }
 
     pub fn init_single_player(&mut self) {
+        if let Some(save) = WorldSave::load(SAVE_PATH) {
+            self.time = save.time;
+        }
+
         let player = Player::new("player");
         let translation = Translation(Vec3::new(0.0, 100.0, 0.0));
         let velocity = Velocity(Vec3::zero());
@@ -49,6 +54,9 @@ impl Server {
     }
 
     pub fn tick(&mut self, delta_time: f32, input_handler: &InputHandler, world_blocks: &WorldBlocks, block_data: &StaticBlockData) {
+        let _scope = profiler::scope("server tick");
+        self.time.advance(delta_time);
+
         let binding = self.world.query_mut::<&mut Camera>();
         let (_, cam) = binding.into_iter().next().unwrap();
 
@@ -104,6 +112,59 @@ impl Server {
         self.physics_solver.tick(delta_time, &mut self.world, world_blocks, block_data);
     }
 
+    pub fn save(&self) {
+        let save = WorldSave {
+            time: self.time.clone(),
+        };
+
+        if let Err(e) = save.save(SAVE_PATH) {
+            println!("WARNING: Could not save the world: {}", e);
+        }
+    }
+
+    /// Runs a command and returns the message for whoever issued it.
+    pub fn execute_command(&mut self, command: Command) -> String {
+        match command {
+            Command::TimeQuery => format!(
+                "Day {}, {:.2}h{}",
+                self.time.day,
+                self.time.hours(),
+                if self.time.frozen { " (frozen)" } else { "" },
+            ),
+            Command::TimeSet(hours) => {
+                self.time.set_hours(hours);
+                format!("Set the time to {:.2}h", self.time.hours())
+            },
+            Command::TimeAdd(hours) => {
+                self.time.add_hours(hours);
+                format!("Set the time to {:.2}h", self.time.hours())
+            },
+            Command::TimeFreeze(frozen) => {
+                self.time.frozen = frozen;
+                if frozen { "Froze the time" } else { "Unfroze the time" }.to_string()
+            },
+            Command::Save => {
+                self.save();
+                "Saved the world".to_string()
+            },
+            Command::ProfileSummary => profiler::summary(),
+            Command::ProfileExport(path) => {
+                let path = path.unwrap_or_else(|| profiler::TRACE_PATH.to_string());
+                match profiler::export_chrome_trace(&path) {
+                    Ok(()) => format!("Wrote the trace of the last frames to {}", path),
+                    Err(e) => format!("Could not write {}: {}", path, e),
+                }
+            },
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        for (_, cam) in self.world.query_mut::<&mut Camera>() {
+            cam.fov = settings.fov.to_radians();
+            cam.far = settings.far;
+        }
+    }
+
     pub fn camera(&self) -> Camera {
         let mut binding = self.world.query::<(&Camera, &Parent, &Translation)>();
         let (_, (cam, player, cam_translation)) = binding.into_iter().next().unwrap();
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+/// Real seconds in one in-game day
+pub const DAY_LENGTH: f32 = 1200.0;
+const HOURS_PER_DAY: f32 = 24.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldTime {
+    /// Days since the world was created
+    pub day: u32,
+    /// Seconds into the current day
+    pub day_time: f32,
+    /// Frozen time does not advance on ticks, commands can still change it
+    pub frozen: bool,
+}
+
+impl Default for WorldTime {
+    fn default() -> Self {
+        let mut ret = Self {
+            day: 0,
+            day_time: 0.0,
+            frozen: false,
+        };
+        // Start in the morning
+        ret.set_hours(8.0);
+        ret
+    }
+}
+
+impl WorldTime {
+    pub fn advance(&mut self, delta_time: f32) {
+        if !self.frozen {
+            self.add_seconds(delta_time);
+        }
+    }
+
+    /// Fraction of the day in `[0, 1)`, `0` is midnight and `0.5` is noon.
+    pub fn time_of_day(&self) -> f32 {
+        self.day_time / DAY_LENGTH
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.time_of_day() * HOURS_PER_DAY
+    }
+
+    /// Sets the time of the current day.
+    pub fn set_hours(&mut self, hours: f32) {
+        let day_time = (hours / HOURS_PER_DAY * DAY_LENGTH).rem_euclid(DAY_LENGTH);
+        // Just before midnight can round up to the end of the day
+        self.day_time = if day_time >= DAY_LENGTH { 0.0 } else { day_time };
+    }
+
+    pub fn add_hours(&mut self, hours: f32) {
+        self.add_seconds(hours / HOURS_PER_DAY * DAY_LENGTH);
+    }
+
+    fn add_seconds(&mut self, seconds: f32) {
+        let total = self.day_time + seconds;
+        let mut days = total.div_euclid(DAY_LENGTH) as i64;
+        let mut day_time = total.rem_euclid(DAY_LENGTH);
+        // Just before midnight can round up to the end of the day, which is the start of the next one
+        if day_time >= DAY_LENGTH {
+            days += 1;
+            day_time -= DAY_LENGTH;
+        }
+
+        self.day = (self.day as i64 + days).max(0) as u32;
+        self.day_time = day_time;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_time(time: &WorldTime, day: u32, hours: f32) {
+        assert_eq!(time.day, day);
+        assert!((time.hours() - hours).abs() < 1e-3, "{} hours instead of {}", time.hours(), hours);
+        assert!(time.day_time >= 0.0 && time.day_time < DAY_LENGTH);
+    }
+
+    #[test]
+    fn set_hours() {
+        let mut time = WorldTime::default();
+        assert_time(&time, 0, 8.0);
+        time.set_hours(30.0);
+        assert_time(&time, 0, 6.0);
+        time.set_hours(-2.0);
+        assert_time(&time, 0, 22.0);
+        time.set_hours(-1e-6);
+        assert_time(&time, 0, 0.0);
+    }
+
+    #[test]
+    fn add_hours_across_midnight() {
+        let mut time = WorldTime { day: 3, ..Default::default() };
+        time.set_hours(22.0);
+        time.add_hours(4.0);
+        assert_time(&time, 4, 2.0);
+        time.add_hours(-4.0);
+        assert_time(&time, 3, 22.0);
+        time.add_hours(-46.0);
+        assert_time(&time, 2, 0.0);
+
+        // Rounds to the end of day 1, which is the start of day 2
+        time.add_seconds(-1e-5);
+        assert_time(&time, 2, 0.0);
+        time.add_seconds(-1.0);
+        assert_time(&time, 1, 24.0 - 24.0 / DAY_LENGTH);
+    }
+
+    #[test]
+    fn frozen() {
+        let mut time = WorldTime { frozen: true, ..Default::default() };
+        time.advance(DAY_LENGTH / 2.0);
+        assert_time(&time, 0, 8.0);
+        time.add_hours(1.0);
+        assert_time(&time, 0, 9.0);
+
+        time.frozen = false;
+        time.advance(DAY_LENGTH / 2.0);
+        assert_time(&time, 0, 21.0);
+    }
+}
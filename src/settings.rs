@@ -0,0 +1,218 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const SETTINGS_PATH: &str = "./settings.json";
+
+/// The brickgrid is 1024 chunks wide and centered on the player
+const MAX_RENDER_DISTANCE: u32 = 511;
+/// More steps than it takes to cross the brickgrid only make rays that miss take longer
+const MAX_RAY_STEPS: u32 = 2048;
+/// Rays per pixel for shadows and ambient occlusion, anything above stalls the GPU
+const MAX_SAMPLES: u32 = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentModeSetting {
+    /// Vsync, always supported
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureFilter {
+    Nearest,
+    Linear,
+}
+
+/// Graphics settings, read from `SETTINGS_PATH`.
+///
+/// Settings missing from the file keep their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Device index, type (`discrete`, `integrated`, `virtual`, `cpu`) or part of its name,
+    /// `None` picks one automatically. Overridden by the `VK_VOXEL_DEVICE` environment variable.
+    pub device: Option<String>,
+    pub window_size: [u32; 2],
+    pub present_mode: PresentModeSetting,
+    /// Only read at startup
+    pub texture_filter: TextureFilter,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+    pub far: f32,
+    /// Minimum seconds between frames, `0` doesn't limit the frame rate
+    pub frame_time: f64,
+    /// In chunks
+    pub render_distance: u32,
+    /// Brickgrid cells a ray can pass before giving up
+    pub max_ray_steps: u32,
+    pub shadow_samples: u32,
+    pub ao_samples: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            device: None,
+            window_size: [1600, 900],
+            present_mode: PresentModeSetting::Mailbox,
+            texture_filter: TextureFilter::Nearest,
+            fov: 90.0,
+            far: 1000.0,
+            frame_time: 0.0,
+            render_distance: 32,
+            max_ray_steps: 180,
+            shadow_samples: 2,
+            ao_samples: 2,
+        }
+    }
+}
+
+impl Settings {
+    /// Settings that `Renderer::apply_settings` can't change on a running renderer
+    pub const STARTUP_ONLY: &'static [&'static str] = &["device", "texture_filter"];
+
+    /// Falls back to the defaults if the file is missing or broken, writing them out if it's missing.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let mut settings = match fs::read_to_string(path) {
+            Ok(src) => serde_json::from_str(&src).unwrap_or_else(|e| {
+                println!("WARNING: Could not read settings {:?}, using the defaults: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => {
+                let settings = Self::default();
+                if let Err(e) = settings.save(path) {
+                    println!("WARNING: Could not write default settings {:?}: {}", path, e);
+                }
+                settings
+            }
+        };
+
+        for warning in settings.validate() {
+            println!("WARNING: {}", warning);
+        }
+        settings
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Clamps out of range values, returns what was changed.
+    pub fn validate(&mut self) -> Vec<String> {
+        let defaults = Self::default();
+        let mut warnings = Vec::new();
+
+        let mut clamp = |name: &str, value: &mut f32, min: f32, max: f32| {
+            if !(min..=max).contains(value) {
+                let clamped = if value.is_nan() { min } else { value.clamp(min, max) };
+                warnings.push(format!("{} must be between {} and {}, using {}", name, min, max, clamped));
+                *value = clamped;
+            }
+        };
+
+        clamp("fov", &mut self.fov, 10.0, 170.0);
+        clamp("far", &mut self.far, 1.0, 100_000.0);
+
+        if !(self.frame_time >= 0.0 && self.frame_time.is_finite()) {
+            warnings.push(format!("frame_time must be positive, using {}", defaults.frame_time));
+            self.frame_time = defaults.frame_time;
+        }
+
+        if self.window_size.contains(&0) {
+            warnings.push(format!("window_size can't be zero, using {:?}", defaults.window_size));
+            self.window_size = defaults.window_size;
+        }
+
+        if self.render_distance == 0 || self.render_distance > MAX_RENDER_DISTANCE {
+            let clamped = self.render_distance.clamp(1, MAX_RENDER_DISTANCE);
+            warnings.push(format!("render_distance must be between 1 and {}, using {}", MAX_RENDER_DISTANCE, clamped));
+            self.render_distance = clamped;
+        }
+
+        let mut clamp_int = |name: &str, value: &mut u32, min: u32, max: u32| {
+            if !(min..=max).contains(value) {
+                let clamped = (*value).clamp(min, max);
+                warnings.push(format!("{} must be between {} and {}, using {}", name, min, max, clamped));
+                *value = clamped;
+            }
+        };
+
+        clamp_int("max_ray_steps", &mut self.max_ray_steps, 1, MAX_RAY_STEPS);
+        clamp_int("shadow_samples", &mut self.shadow_samples, 0, MAX_SAMPLES);
+        clamp_int("ao_samples", &mut self.ao_samples, 0, MAX_SAMPLES);
+
+        warnings
+    }
+
+    /// Sets one setting by name, `value` is parsed as JSON or used as a string if that doesn't fit.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let json = serde_json::to_value(&*self).map_err(|e| e.to_string())?;
+        if json.get(name).is_none() {
+            return Err(format!("Unknown setting \"{}\"", name));
+        }
+
+        let with_value = |value: Value| {
+            let mut json = json.clone();
+            json[name] = value;
+            serde_json::from_value::<Self>(json)
+        };
+
+        let mut settings = match serde_json::from_str(value).map(with_value) {
+            Ok(Ok(settings)) => settings,
+            _ => with_value(Value::String(value.to_string()))
+                .map_err(|e| format!("Invalid value for {}: {}", name, e))?,
+        };
+        if let Some(warning) = settings.validate().into_iter().next() {
+            return Err(warning);
+        }
+
+        *self = settings;
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_by_name() {
+        let mut settings = Settings::default();
+        settings.set("fov", "70").unwrap();
+        settings.set("present_mode", "fifo").unwrap();
+        settings.set("window_size", "[800, 600]").unwrap();
+
+        assert_eq!(settings.fov, 70.0);
+        assert_eq!(settings.present_mode, PresentModeSetting::Fifo);
+        assert_eq!(settings.window_size, [800, 600]);
+
+        settings.set("device", "1").unwrap();
+        assert_eq!(settings.device.as_deref(), Some("1"));
+        settings.set("device", "null").unwrap();
+        assert_eq!(settings.device, None);
+
+        assert!(settings.set("fov", "1000").is_err());
+        assert!(settings.set("shadow_samples", "100000").is_err());
+        assert!(settings.set("max_ray_steps", "0").is_err());
+        assert!(settings.set("render_distance", "far").is_err());
+        assert!(settings.set("brightness", "1").is_err());
+        assert_eq!(settings.fov, 70.0);
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let settings: Settings = serde_json::from_str(r#"{ "fov": 60.0 }"#).unwrap();
+        assert_eq!(settings.fov, 60.0);
+        assert_eq!(settings.render_distance, Settings::default().render_distance);
+    }
+}
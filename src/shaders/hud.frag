@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+// Same binding as the raytracing pipeline, so the atlas stays bound
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
+void main() {
+    vec4 texel = texelFetch(tex, ivec2(v_uv), 0);
+    f_color = v_color * texel;
+}
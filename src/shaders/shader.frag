@@ -8,11 +8,11 @@ layout(location = 0) out vec4 f_color;
 #include "descriptor_sets.comp"
 
 layout(set = 8, binding = 0) readonly buffer SurfelBuffer {
-    uint surfel_count;
     Surfel surfels[];
 } surfel_buffer;
 
 #include "raytracing.comp"
+#include "debug.comp"
 
 void main() {
     state = uint(gl_FragCoord.x * view.resolution.y + gl_FragCoord.y) + program_info.start + program_info.frame_number;
@@ -41,4 +41,18 @@ void main() {
 
     Intersection intersection;
     f_color = raymarch_brickgrid(ray_origin, ray_dir, intersection);
+
+    if (intersection.hit && !intersection.covered) {
+        request_surfel(intersection.pos, intersection.face_id);
+    }
+
+    // The CPU reads back what the crosshair points at
+    if (ivec2(gl_FragCoord.xy) == ivec2(view.resolution / 2u)) {
+        ivec3 block_pos = ivec3(floor(intersection.pos - intersection.normal * 0.001));
+        feedback.target = intersection.hit ? ivec4(block_pos, int(intersection.face_id) + 1) : ivec4(0);
+    }
+
+    if (program_info.debug_mode != DEBUG_NONE) {
+        f_color = debug_color(f_color, intersection, ray_origin);
+    }
 }
\ No newline at end of file
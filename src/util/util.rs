@@ -74,6 +74,19 @@ impl Facing {
         }
     }
 
+    /// Offset to the neighbouring block on this side
+    pub fn offset(&self) -> IVec3 {
+        let unit = match self.axis {
+            Axis::X => IVec3::new(1, 0, 0),
+            Axis::Y => IVec3::new(0, 1, 0),
+            Axis::Z => IVec3::new(0, 0, 1),
+        };
+        match self.sign {
+            Sign::Positive => unit,
+            Sign::Negative => -unit,
+        }
+    }
+
     pub fn opposite(&self) -> Self {
         Self { 
             axis: self.axis, 
@@ -2,8 +2,8 @@ use ahash::HashMap;
 use bytemuck::{Pod, Zeroable};
 
 use crate::render::{
-    mesh::{cube::UnitCube, model::Model, quad::QuadUV},
-    texture::TextureAtlas,
+    mesh::cube::UnitCube,
+    texture::{TextureAtlas, TextureHandle},
 };
 
 /// Static block data, should be initialized at startup and probably left alone.
@@ -61,8 +61,23 @@ impl StaticBlockData {
             ),
             BlockType::Full,
         ));
-
-        // self.add(InitBlockData::new_plant("grass", atlas.get_uv(atlas.get_handle("grass").unwrap())));
+        self.add(InitBlockData::new_block(
+            "glass",
+            Some(UnitCube::new([atlas.get_handle("glass").unwrap()].to_vec()).unwrap()),
+            BlockType::Translucent,
+        ));
+        self.add(InitBlockData::new_block(
+            "water",
+            Some(UnitCube::new([atlas.get_handle("water").unwrap()].to_vec()).unwrap()),
+            BlockType::Translucent,
+        ));
+        self.add(InitBlockData::new_plant("grass", atlas.get_handle("grass").unwrap()));
+        self.add(InitBlockData::new_shaped(
+            "stone_slab",
+            UnitCube::new([atlas.get_handle("stone").unwrap()].to_vec()).unwrap(),
+            Shape::SlabBottom,
+            BlockType::Full,
+        ));
     }
 
     pub fn add(&mut self, data: InitBlockData) -> BlockHandle {
@@ -94,6 +109,10 @@ pub enum Blocks {
     Grass,
     Leaves,
     Log,
+    Glass,
+    Water,
+    GrassPlant,
+    StoneSlab,
 }
 
 impl Blocks {
@@ -127,12 +146,53 @@ impl InitBlockData {
         }
     }
 
-    pub fn new_plant(id: &str, uv: QuadUV) -> Self {
-        let plant_model = Model::create_plant_model(uv);
+    /// Two crossed quads with the same texture on both
+    pub fn new_plant(id: &str, texture: TextureHandle) -> Self {
+        Self::new_shaped(
+            id,
+            UnitCube::new([texture].to_vec()).unwrap(),
+            Shape::Cross,
+            BlockType::Transparent,
+        )
+    }
+
+    pub fn new_shaped(id: &str, textures: UnitCube, shape: Shape, block_type: BlockType) -> Self {
         Self {
             id: id.to_string(),
-            model: ModelType::Plant(plant_model),
-            block_type: BlockType::Transparent,
+            model: ModelType::Partial(textures, shape),
+            block_type,
+        }
+    }
+}
+
+/// The material is stored above the block id in the texture pointers
+pub const MATERIAL_SHIFT: u32 = 24;
+/// And the shape above the material
+pub const SHAPE_SHIFT: u32 = 26;
+
+/// Texture pointer of a voxel, as read by the shaders
+pub fn texture_pointer(block: BlockHandle, data: &InitBlockData) -> Option<u32> {
+    let shape = data.model.shape()?;
+    Some(block.inner() | (data.block_type.material() << MATERIAL_SHIFT) | (shape.id() << SHAPE_SHIFT))
+}
+
+/// How the raytracer intersects a voxel, the ids reach the shaders as the `SHAPE_*` defines of `gpu_layout::defines`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Cube,
+    /// Two quads crossing diagonally, used for plants
+    Cross,
+    SlabBottom,
+    SlabTop,
+}
+
+impl Shape {
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Cube => 0,
+            Self::Cross => 1,
+            Self::SlabBottom => 2,
+            Self::SlabTop => 3,
         }
     }
 }
@@ -140,14 +200,33 @@ impl InitBlockData {
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub enum BlockType {
     Full,
+    /// Alpha tested, rays pass through fully transparent texels
     Transparent,
+    /// Tints the rays passing through it
+    Translucent,
     None,
 }
 
+impl BlockType {
+    /// Material id used by the shaders
+    pub fn material(&self) -> u32 {
+        match self {
+            Self::Full | Self::None => 0,
+            Self::Transparent => 1,
+            Self::Translucent => 2,
+        }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        matches!(self, Self::Full)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ModelType {
     FullBlock(UnitCube),
-    Plant(Model),
+    /// Takes up part of the voxel, rays can pass the rest of it
+    Partial(UnitCube, Shape),
     None,
 }
 
@@ -176,6 +255,22 @@ impl ModelType {
         }*/
         matches!(self, Self::FullBlock(_))
     }
+
+    /// `None` if there is nothing to intersect
+    pub fn shape(&self) -> Option<Shape> {
+        match self {
+            Self::FullBlock(_) => Some(Shape::Cube),
+            Self::Partial(_, shape) => Some(*shape),
+            Self::None => None,
+        }
+    }
+
+    pub fn textures(&self) -> BlockTexture {
+        match self {
+            Self::FullBlock(m) | Self::Partial(m, _) => BlockTexture::from(m.clone()),
+            Self::None => BlockTexture::zeroed(),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -0,0 +1,106 @@
+use std::{fs, io, path::Path};
+
+use serde_json::json;
+
+use super::{file_name, TextureFile, TextureMesh};
+
+// Constants from the glTF 2.0 specification
+const FLOAT: u32 = 5126;
+const ARRAY_BUFFER: u32 = 34962;
+const NEAREST: u32 = 9728;
+const REPEAT: u32 = 10497;
+
+/// Writes the meshes to `path` as a single mesh with one primitive and material per texture,
+/// the vertices go in a `.bin` with the same name
+pub fn write(meshes: &[TextureMesh], textures: &[TextureFile], path: &Path) -> io::Result<()> {
+    let mut bin = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut primitives = Vec::new();
+    for (i, mesh) in meshes.iter().enumerate() {
+        let vertices = &mesh.model.vertices;
+        let positions = vertices.iter().map(|v| *v.position.as_array()).collect::<Vec<[f32; 3]>>();
+        let normals = vertices.iter().map(|v| *v.normal.as_array()).collect::<Vec<[f32; 3]>>();
+        let tex_coords = vertices.iter().map(|v| *v.tex_coords.as_array()).collect::<Vec<[f32; 2]>>();
+
+        let first_view = views.len();
+        for data in [
+            bytemuck::cast_slice::<_, u8>(&positions),
+            bytemuck::cast_slice(&normals),
+            bytemuck::cast_slice(&tex_coords),
+        ] {
+            views.push(json!({
+                "buffer": 0,
+                "byteOffset": bin.len(),
+                "byteLength": data.len(),
+                "target": ARRAY_BUFFER,
+            }));
+            bin.extend_from_slice(data);
+        }
+
+        // Positions are the only accessor that needs bounds
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in positions.iter() {
+            for ((min, max), v) in min.iter_mut().zip(max.iter_mut()).zip(p) {
+                *min = min.min(*v);
+                *max = max.max(*v);
+            }
+        }
+
+        let count = vertices.len();
+        let first_accessor = accessors.len();
+        accessors.extend([
+            json!({ "bufferView": first_view, "componentType": FLOAT, "count": count, "type": "VEC3", "min": min, "max": max }),
+            json!({ "bufferView": first_view + 1, "componentType": FLOAT, "count": count, "type": "VEC3" }),
+            json!({ "bufferView": first_view + 2, "componentType": FLOAT, "count": count, "type": "VEC2" }),
+        ]);
+        primitives.push(json!({
+            "attributes": {
+                "POSITION": first_accessor,
+                "NORMAL": first_accessor + 1,
+                "TEXCOORD_0": first_accessor + 2,
+            },
+            "material": i,
+        }));
+    }
+
+    let materials = textures
+        .iter()
+        .enumerate()
+        .map(|(i, texture)| {
+            json!({
+                "name": texture.name,
+                "pbrMetallicRoughness": {
+                    "baseColorTexture": { "index": i },
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0,
+                },
+                // Leaves and plants are cut out with the alpha channel
+                "alphaMode": "MASK",
+            })
+        })
+        .collect::<Vec<_>>();
+    let gltf_textures = (0..textures.len()).map(|i| json!({ "source": i, "sampler": 0 })).collect::<Vec<_>>();
+    let images = textures.iter().map(|texture| json!({ "uri": texture.uri })).collect::<Vec<_>>();
+
+    let bin_path = path.with_extension("bin");
+    let gltf = json!({
+        "asset": { "version": "2.0", "generator": "vk-voxel" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": primitives }],
+        "materials": materials,
+        "textures": gltf_textures,
+        "images": images,
+        // Merged faces have texture coordinates past 1 and repeat their texture
+        "samplers": [{ "magFilter": NEAREST, "minFilter": NEAREST, "wrapS": REPEAT, "wrapT": REPEAT }],
+        "buffers": [{ "uri": file_name(&bin_path), "byteLength": bin.len() }],
+        "bufferViews": views,
+        "accessors": accessors,
+    });
+
+    fs::write(&bin_path, bin)?;
+    fs::write(path, serde_json::to_string_pretty(&gltf)?)
+}
@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use ultraviolet::{IVec3, Vec2, Vec3};
+
+use crate::{
+    render::{
+        mesh::{
+            greedy::{face_vertices, greedy_mesh},
+            model::Model,
+            quad::QuadUV,
+        },
+        texture::TextureAtlas,
+    },
+    util::util::{Facing, Sign},
+};
+
+use super::{
+    block_data::{Shape, StaticBlockData},
+    map::write_png,
+    region::RegionBlocks,
+    section::BlockCull,
+    world_blocks::WorldBlocks,
+};
+
+pub mod gltf;
+pub mod obj;
+
+pub const EXPORT_DIR: &str = "./exports";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Wavefront OBJ with an MTL material
+    Obj,
+    /// glTF 2.0 with the vertices in a separate `.bin`
+    Gltf,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "obj" => Some(Self::Obj),
+            "gltf" => Some(Self::Gltf),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Obj => "obj",
+            Self::Gltf => "gltf",
+        }
+    }
+}
+
+/// A face is culled by full opaque blocks, and by the same block if it lets light through.
+///
+/// Nothing outside of the region culls, so the mesh is closed.
+fn cull(region: &RegionBlocks, pos: IVec3, block_data: &StaticBlockData) -> BlockCull {
+    let block = region.get(pos);
+    let opaque = block_data.get(&block).block_type.is_opaque();
+
+    let mut cull = BlockCull::none();
+    for face in 0..6 {
+        let neighbor = region.get(pos + Facing::from_num(face).offset());
+        let data = block_data.get(&neighbor);
+        let hidden = (data.model.is_full() && data.block_type.is_opaque()) || (!opaque && neighbor == block);
+        cull.set_face(face, hidden);
+    }
+    cull
+}
+
+/// The faces of a region with the same texture
+pub struct TextureMesh {
+    pub texture: u32,
+    pub model: Model,
+}
+
+/// Meshes the blocks between two corners, both included, into one mesh per texture. The mesh starts at the origin.
+///
+/// Faces of full blocks are greedy meshed. Their texture coordinates are in blocks, so a merged face
+/// repeats its texture once per block when the texture is sampled with wrapping.
+pub fn mesh_region(
+    world: &WorldBlocks,
+    block_data: &StaticBlockData,
+    corner_a: IVec3,
+    corner_b: IVec3,
+) -> io::Result<Vec<TextureMesh>> {
+    let region = RegionBlocks::copy(world, corner_a, corner_b)?;
+
+    let mut meshes = BTreeMap::new();
+    let quads = greedy_mesh(region.size, |pos, facing| {
+        let pos = IVec3::new(pos.x as i32, pos.y as i32, pos.z as i32);
+        let data = block_data.get(&region.get(pos));
+        if !data.model.is_full() || cull(&region, pos, block_data).is_culled(facing) {
+            return None;
+        }
+        Some(data.model.textures().textures[facing.to_num()])
+    });
+    for quad in quads {
+        mesh(&mut meshes, quad.texture).push_quad(quad.vertices());
+    }
+
+    // Partial blocks are added one by one
+    for (pos, block) in region.iter() {
+        let data = block_data.get(&block);
+        let textures = data.model.textures().textures;
+        let corner = Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32);
+
+        match data.model.shape() {
+            Some(Shape::Cross) => {
+                let plant = Model::create_plant_model(WHOLE_TEXTURE);
+                let center = corner + Vec3::broadcast(0.5);
+                mesh(&mut meshes, textures[0]).vertices.extend(plant.with_translation(center).vertices);
+            }
+            Some(shape @ (Shape::SlabBottom | Shape::SlabTop)) => {
+                let bottom = if shape == Shape::SlabTop { 0.5 } else { 0.0 };
+                let min = corner + Vec3::new(0.0, bottom, 0.0);
+                let faces = cull(&region, pos, block_data);
+                push_box(&mut meshes, min, Vec3::new(1.0, 0.5, 1.0), faces, textures);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(meshes.into_iter().map(|(texture, model)| TextureMesh { texture, model }).collect())
+}
+
+/// Texture coordinates of a face that shows its texture once
+const WHOLE_TEXTURE: QuadUV = QuadUV { min: Vec2::new(0.0, 0.0), max: Vec2::new(1.0, 1.0) };
+
+fn mesh(meshes: &mut BTreeMap<u32, Model>, texture: u32) -> &mut Model {
+    meshes.entry(texture).or_default()
+}
+
+/// Adds the faces of a box smaller than a block. Only faces on the side of the block can be culled.
+fn push_box(meshes: &mut BTreeMap<u32, Model>, min: Vec3, size: Vec3, cull: BlockCull, textures: [u32; 6]) {
+    let block_min = Vec3::new(min.x.floor(), min.y.floor(), min.z.floor());
+    let block_max = block_min + Vec3::one();
+    let max = min + size;
+
+    for (face, texture) in textures.into_iter().enumerate() {
+        let facing = Facing::from_num(face);
+        let axis = facing.axis.unit_vec();
+        let on_side = match facing.sign {
+            Sign::Positive => max.dot(axis) == block_max.dot(axis),
+            Sign::Negative => min.dot(axis) == block_min.dot(axis),
+        };
+        if on_side && cull.is_culled(facing) {
+            continue;
+        }
+
+        let start = match facing.sign {
+            Sign::Positive => min + axis * size.dot(axis),
+            Sign::Negative => min,
+        };
+        let extent = size - axis * size.dot(axis);
+        mesh(meshes, texture).push_quad(face_vertices(facing, start, extent, &WHOLE_TEXTURE));
+    }
+}
+
+/// Meshes a region and writes it with its textures next to it, returns the number of triangles
+pub fn export_region(
+    world: &WorldBlocks,
+    block_data: &StaticBlockData,
+    atlas: &TextureAtlas,
+    corners: (IVec3, IVec3),
+    format: ExportFormat,
+    path: &Path,
+) -> io::Result<usize> {
+    let meshes = mesh_region(world, block_data, corners.0, corners.1)?;
+    if meshes.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "there are no blocks in the region"));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let textures = write_textures(atlas, &meshes, path)?;
+
+    match format {
+        ExportFormat::Obj => obj::write(&meshes, &textures, path)?,
+        ExportFormat::Gltf => gltf::write(&meshes, &textures, path)?,
+    }
+
+    Ok(meshes.iter().map(|mesh| mesh.model.vertices.len() / 3).sum())
+}
+
+/// A texture of the export, written next to the mesh
+pub struct TextureFile {
+    /// Name of the texture in the atlas, also the name of its material
+    pub name: String,
+    /// Path of the image relative to the mesh
+    pub uri: String,
+}
+
+/// Writes the textures of the meshes to a folder named after the mesh, in the same order
+fn write_textures(atlas: &TextureAtlas, meshes: &[TextureMesh], path: &Path) -> io::Result<Vec<TextureFile>> {
+    let dir_name = format!("{}_textures", path.file_stem().unwrap_or_default().to_string_lossy());
+    let dir = path.with_file_name(&dir_name);
+    fs::create_dir_all(&dir)?;
+
+    meshes
+        .iter()
+        .map(|mesh| {
+            let name = match atlas.texture_name(mesh.texture) {
+                Some(name) => name.to_string(),
+                None => format!("texture_{}", mesh.texture),
+            };
+            let image = atlas.texture_image(mesh.texture);
+            let file = format!("{}.png", name);
+            write_png(&dir.join(&file), image.dimensions, &image.data)?;
+            Ok(TextureFile { name, uri: format!("{}/{}", dir_name, file) })
+        })
+        .collect()
+}
+
+/// File name of `path` for references from the files next to it
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
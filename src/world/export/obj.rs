@@ -0,0 +1,58 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::{file_name, TextureFile, TextureMesh};
+
+/// Writes the meshes to `path` with one material per texture, the materials go next to it with the same name
+pub fn write(meshes: &[TextureMesh], textures: &[TextureFile], path: &Path) -> io::Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    write_materials(&mtl_path, textures)?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "mtllib {}", file_name(&mtl_path))?;
+
+    let vertices = || meshes.iter().flat_map(|mesh| mesh.model.vertices.iter());
+    for v in vertices() {
+        writeln!(out, "v {} {} {}", v.position.x, v.position.y, v.position.z)?;
+    }
+    // OBJ texture coordinates start at the bottom of the image
+    for v in vertices() {
+        writeln!(out, "vt {} {}", v.tex_coords.x, 1.0 - v.tex_coords.y)?;
+    }
+    for v in vertices() {
+        writeln!(out, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z)?;
+    }
+
+    // Every vertex has its own position, texture coordinate and normal, indices start at 1
+    let mut first = 1;
+    for (mesh, texture) in meshes.iter().zip(textures) {
+        writeln!(out, "usemtl {}", texture.name)?;
+        for triangle in 0..mesh.model.vertices.len() / 3 {
+            let [a, b, c] = [0, 1, 2].map(|i| first + triangle * 3 + i);
+            writeln!(out, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        first += mesh.model.vertices.len();
+    }
+
+    out.flush()
+}
+
+/// Textures repeat outside of 0 to 1 by default, which covers the merged faces
+fn write_materials(path: &Path, textures: &[TextureFile]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for texture in textures {
+        writeln!(out, "newmtl {}", texture.name)?;
+        writeln!(out, "Ka 0 0 0")?;
+        writeln!(out, "Kd 1 1 1")?;
+        writeln!(out, "Ks 0 0 0")?;
+        writeln!(out, "illum 1")?;
+        writeln!(out, "map_Kd {}", texture.uri)?;
+        // Leaves and plants are cut out with the alpha channel
+        writeln!(out, "map_d {}", texture.uri)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
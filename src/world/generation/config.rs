@@ -0,0 +1,87 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// Terrain config of the game, the defaults are used without one
+pub const TERRAIN_CONFIG_PATH: &str = "./terrain.json";
+
+/// How the shape of the terrain is made
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TerrainShape {
+    /// Columns filled up to the height noise
+    #[default]
+    Heightmap,
+    /// The height noise bent by 3D noise, see `density::ChunkDensitySampler`
+    Density,
+}
+
+/// Knobs of `TerrainGenerator`, entries missing from a config file keep their defaults
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainConfig {
+    pub shape: TerrainShape,
+    /// Scale of the height noise, smaller makes wider hills
+    pub noise_scale: f32,
+    /// Layers of height noise, each with half the size and strength of the one before
+    pub octaves: u32,
+    /// Surface height where the height noise is 0
+    pub base_height: f32,
+    /// Height the noise adds on top of `base_height` where it is 1
+    pub height_range: f32,
+    /// Scale of the 3D noise of density terrain
+    pub density_scale: f32,
+    /// Blocks the 3D noise can move the surface of density terrain by
+    pub density_amplitude: f32,
+    pub caves: bool,
+    pub cave_radius: f32,
+    /// Places the schematics from `schematic::STRUCTURE_DIR`
+    pub structures: bool,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            shape: TerrainShape::Heightmap,
+            noise_scale: 0.01,
+            octaves: 4,
+            base_height: 50.0,
+            height_range: 50.0,
+            density_scale: 0.03,
+            density_amplitude: 24.0,
+            caves: true,
+            cave_radius: 4.0,
+            structures: true,
+        }
+    }
+}
+
+impl TerrainConfig {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Falls back to the defaults if the file is missing, or with a warning if it's broken
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                println!("WARNING: Could not read the terrain config {:?}, using the defaults: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_entries_keep_defaults() {
+        let config = serde_json::from_str::<TerrainConfig>(r#"{ "shape": "density", "caves": false }"#).unwrap();
+        assert_eq!(config, TerrainConfig { shape: TerrainShape::Density, caves: false, ..Default::default() });
+    }
+}
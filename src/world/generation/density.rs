@@ -0,0 +1,104 @@
+use ndarray::{Array3, Axis};
+use ultraviolet::{IVec2, Vec2, Vec3};
+
+use crate::{
+    util::util::AdditionalSwizzles,
+    world::{
+        chunk::CHUNK_HEIGHT,
+        section::{F_SECTION_SIZE, I_SECTION_SIZE, SECTION_SIZE},
+    },
+};
+
+use super::{
+    config::TerrainConfig,
+    noise::{ScaleNoise2D, ScaleNoise3D},
+};
+
+/// Blocks between the points density is calculated at, everything in between is interpolated
+const STEP: Vec3 = Vec3::new(4.0, 8.0, 4.0);
+
+/// Density of a chunk on a coarse grid, blocks are solid where it is above 0.
+///
+/// Density is the height of the 2D height noise above a block plus 3D noise, so the surface follows
+/// the heightmap but the 3D noise can push it sideways into overhangs, arches and floating islands.
+pub struct ChunkDensitySampler {
+    data: Array3<f32>,
+}
+
+impl ChunkDensitySampler {
+    pub fn new(chunk_pos: IVec2, config: &TerrainConfig, planar_noise: &ScaleNoise2D, world_noise: &ScaleNoise3D) -> Self {
+        let offset = chunk_pos * I_SECTION_SIZE.xz();
+        let points = |size: f32, step: f32| (size / step) as usize + 1;
+        let shape = (
+            points(F_SECTION_SIZE.x, STEP.x),
+            points((CHUNK_HEIGHT * SECTION_SIZE.y) as f32, STEP.y),
+            points(F_SECTION_SIZE.z, STEP.z),
+        );
+
+        let mut data = Array3::zeros(shape);
+        for ((x, z), mut column) in data
+            .lanes_mut(Axis(1))
+            .into_iter()
+            .enumerate()
+            .map(|(i, column)| ((i / shape.2, i % shape.2), column))
+        {
+            let pos = Vec2::new(offset.x as f32 + x as f32 * STEP.x, offset.y as f32 + z as f32 * STEP.z);
+            let height = planar_noise.sample(pos, config.octaves) as f32 * config.height_range + config.base_height;
+
+            for (y, density) in column.iter_mut().enumerate() {
+                let y = y as f32 * STEP.y;
+                let noise = world_noise.get(Vec3::new(pos.x, y, pos.y)) as f32;
+                // Half a block up so a flat density surface lands where the rounded heightmap would
+                *density = height + 0.5 - y + noise * config.density_amplitude;
+            }
+        }
+
+        Self { data }
+    }
+
+    /// Trilinear interpolation, relative to the lowest corner of the chunk in blocks
+    pub fn sample(&self, pos: Vec3) -> f32 {
+        let shape = self.data.shape();
+        let grid = [pos.x / STEP.x, pos.y / STEP.y, pos.z / STEP.z];
+        let cell = [0, 1, 2].map(|i| (grid[i].floor().max(0.0) as usize).min(shape[i] - 2));
+        let t = [0, 1, 2].map(|i| grid[i] - cell[i] as f32);
+
+        let corner = |dx: usize, dy: usize, dz: usize| self.data[(cell[0] + dx, cell[1] + dy, cell[2] + dz)];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), t[0]);
+        let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), t[0]);
+        let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), t[0]);
+        let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), t[0]);
+        lerp(lerp(x00, x10, t[1]), lerp(x01, x11, t[1]), t[2])
+    }
+
+    /// Sampled through the middle of the column, at the bottom of the block
+    pub fn is_solid(&self, x: u32, y: u32, z: u32) -> bool {
+        self.sample(Vec3::new(x as f32 + 0.5, y as f32, z as f32 + 0.5)) > 0.0
+    }
+
+    /// Highest solid block of a column
+    pub fn surface(&self, x: u32, z: u32) -> Option<u32> {
+        (0..CHUNK_HEIGHT * SECTION_SIZE.y).rev().find(|y| self.is_solid(x, *y, z))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn density_sampler() {
+        // Density falls by one per block upwards from 100, and rises by one per block along X
+        let sampler = ChunkDensitySampler {
+            data: Array3::from_shape_fn((3, 33, 3), |(x, y, _)| 100.0 - y as f32 * STEP.y + x as f32 * STEP.x),
+        };
+
+        assert_eq!(sampler.sample(Vec3::new(2.0, 50.0, 3.0)), 52.0);
+        assert_eq!(sampler.sample(Vec3::new(7.5, 255.5, 7.5)), -148.0);
+        assert!(sampler.is_solid(0, 100, 0));
+        assert!(!sampler.is_solid(0, 101, 0));
+        assert_eq!(sampler.surface(4, 0), Some(104));
+    }
+}
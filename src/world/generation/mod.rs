@@ -1,3 +1,7 @@
+pub mod config;
+pub mod density;
 pub mod noise;
+pub mod preview;
+pub mod structures;
 pub mod terrain;
 pub mod transformer;
\ No newline at end of file
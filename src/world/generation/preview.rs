@@ -0,0 +1,191 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ahash::HashMap;
+use rayon::prelude::*;
+use ultraviolet::{IVec2, UVec2, UVec3};
+
+use crate::world::{
+    block_access::BlockAccess,
+    block_data::{BlockHandle, Blocks, StaticBlockData},
+    chunk::CHUNK_HEIGHT,
+    map::{chunks_between, write_png, BlockColors},
+    section::{I_SECTION_SIZE, SECTION_SIZE},
+};
+
+use super::{
+    config::TerrainConfig,
+    terrain::{TerrainChunk, TerrainGenerator},
+};
+
+/// Blocks in a column of a chunk
+const COLUMN_HEIGHT: u32 = CHUNK_HEIGHT * SECTION_SIZE.y;
+
+pub struct GeneratedChunk {
+    pub chunk: TerrainChunk,
+    pub time: Duration,
+}
+
+/// Generates the chunks between two chunk corners, both included, with one generator per thread.
+///
+/// The terrain doesn't depend on which generator makes a chunk or in which order.
+pub fn generate_region(
+    seed: u32,
+    config: TerrainConfig,
+    corners: (IVec2, IVec2),
+    block_data: &StaticBlockData,
+) -> Vec<GeneratedChunk> {
+    let positions = chunks_between(corners).collect::<Vec<_>>();
+    let per_thread = positions.len().div_ceil(rayon::current_num_threads()).max(1);
+
+    positions
+        .par_chunks(per_thread)
+        .flat_map_iter(|part| {
+            let mut generator = TerrainGenerator::with_config(seed, config, block_data);
+            part.iter().map(move |pos| {
+                let start = Instant::now();
+                let chunk = generator.gen_terrain_chunk(*pos);
+                GeneratedChunk { chunk, time: start.elapsed() }
+            })
+        })
+        .collect()
+}
+
+fn column_block(chunk: &TerrainChunk, x: u32, y: u32, z: u32) -> BlockHandle {
+    let section = &chunk.blocks.sections[(y / SECTION_SIZE.y) as usize];
+    section.get_block(UVec3::new(x, y % SECTION_SIZE.y, z))
+}
+
+pub struct RegionStats {
+    /// Number of blocks of every block id in the region
+    pub histogram: BTreeMap<String, u64>,
+    pub min_height: u32,
+    pub max_height: u32,
+    /// Air at or below the terrain surface, carved out by caves. Density terrain adds the air under its overhangs.
+    pub cave_volume: u64,
+    /// Sorted from fastest to slowest
+    pub chunk_times: Vec<Duration>,
+}
+
+impl RegionStats {
+    pub fn new(chunks: &[GeneratedChunk], block_data: &StaticBlockData) -> Self {
+        let mut counts = vec![0u64; block_data.block_data().len()];
+        let mut cave_volume = 0;
+        for generated in chunks {
+            let chunk = &generated.chunk;
+            for section in chunk.blocks.sections.iter() {
+                section.blocks.iter().for_each(|block| counts[block.inner() as usize] += 1);
+            }
+            for ((x, z), height) in chunk.height.indexed_iter() {
+                cave_volume += (0..=(*height).min(COLUMN_HEIGHT - 1))
+                    .filter(|y| column_block(chunk, x as u32, *y, z as u32) == Blocks::Air.handle())
+                    .count() as u64;
+            }
+        }
+
+        let histogram = block_data
+            .block_data()
+            .iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(data, count)| (data.id.clone(), count))
+            .collect();
+
+        let heights = chunks.iter().flat_map(|c| c.chunk.height.iter().copied());
+        let mut chunk_times = chunks.iter().map(|c| c.time).collect::<Vec<_>>();
+        chunk_times.sort();
+
+        Self {
+            histogram,
+            min_height: heights.clone().min().unwrap_or(0),
+            max_height: heights.max().unwrap_or(0),
+            cave_volume,
+            chunk_times,
+        }
+    }
+
+    /// `wall_time` is how long the whole region took, with the chunks generated in parallel
+    pub fn report(&self, wall_time: Duration) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let percentile = |p: f64| {
+            let i = ((self.chunk_times.len() as f64 - 1.0) * p).round() as usize;
+            self.chunk_times.get(i).copied().unwrap_or_default()
+        };
+
+        let mut ret = format!(
+            "Generated {} chunks in {:.2} s\nTime per chunk: {:.2} ms median, {:.2} ms 99th percentile, {:.2} ms max\n",
+            self.chunk_times.len(),
+            wall_time.as_secs_f64(),
+            ms(percentile(0.5)),
+            ms(percentile(0.99)),
+            ms(percentile(1.0)),
+        );
+        ret += &format!("Surface height: {} to {}\n", self.min_height, self.max_height);
+        ret += &format!("Cave volume: {} blocks\n", self.cave_volume);
+
+        let total = self.histogram.values().sum::<u64>().max(1);
+        ret += "Blocks:\n";
+        for (id, count) in self.histogram.iter() {
+            ret += &format!("  {:<12} {:>12} {:>6.2}%\n", id, count, *count as f64 / total as f64 * 100.0);
+        }
+        ret
+    }
+}
+
+/// Writes `heightmap.png` with the surface height as the brightness of each column, and `cross_section.png`
+/// with the blocks of the row of columns through the middle of the region along X, from the top of the world down
+pub fn write_images(chunks: &[GeneratedChunk], colors: &BlockColors, dir: &Path) -> io::Result<()> {
+    let Some((min, max)) = chunk_bounds(chunks) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "there are no chunks to draw"));
+    };
+    let by_pos = chunks.iter().map(|c| (c.chunk.blocks.pos, &c.chunk)).collect::<HashMap<_, _>>();
+    let size = UVec2::new(
+        (max.x - min.x + 1) as u32 * SECTION_SIZE.x,
+        (max.y - min.y + 1) as u32 * SECTION_SIZE.z,
+    );
+    // Chunk and position in it of a column of the image
+    let locate = |x: u32, z: u32| {
+        let pos = IVec2::new(min.x * I_SECTION_SIZE.x + x as i32, min.y * I_SECTION_SIZE.z + z as i32);
+        let chunk_pos = IVec2::new(pos.x.div_euclid(I_SECTION_SIZE.x), pos.y.div_euclid(I_SECTION_SIZE.z));
+        let local = (pos.x.rem_euclid(I_SECTION_SIZE.x) as u32, pos.y.rem_euclid(I_SECTION_SIZE.z) as u32);
+        by_pos.get(&chunk_pos).map(|chunk| (*chunk, local))
+    };
+
+    fs::create_dir_all(dir)?;
+
+    let mut heightmap = Vec::with_capacity((size.x * size.y * 4) as usize);
+    for z in 0..size.y {
+        for x in 0..size.x {
+            heightmap.extend(match locate(x, z) {
+                Some((chunk, (x, z))) => {
+                    let height = chunk.height[(x as usize, z as usize)].min(255) as u8;
+                    [height, height, height, 255]
+                }
+                None => [0; 4],
+            });
+        }
+    }
+    write_png(&dir.join("heightmap.png"), size, &heightmap)?;
+
+    let mut cross_section = Vec::with_capacity((size.x * COLUMN_HEIGHT * 4) as usize);
+    for y in (0..COLUMN_HEIGHT).rev() {
+        for x in 0..size.x {
+            cross_section.extend(match locate(x, size.y / 2) {
+                Some((chunk, (x, z))) => colors.get(column_block(chunk, x, y, z)),
+                None => [0; 4],
+            });
+        }
+    }
+    write_png(&dir.join("cross_section.png"), UVec2::new(size.x, COLUMN_HEIGHT), &cross_section)
+}
+
+fn chunk_bounds(chunks: &[GeneratedChunk]) -> Option<(IVec2, IVec2)> {
+    let positions = chunks.iter().map(|c| c.chunk.blocks.pos);
+    let min = positions.clone().reduce(|a, b| IVec2::new(a.x.min(b.x), a.y.min(b.y)))?;
+    let max = positions.reduce(|a, b| IVec2::new(a.x.max(b.x), a.y.max(b.y)))?;
+    Some((min, max))
+}
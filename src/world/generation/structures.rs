@@ -0,0 +1,79 @@
+use std::{fs, path::Path};
+
+use ultraviolet::{IVec3, UVec3};
+
+use crate::world::{
+    block_data::{BlockHandle, StaticBlockData},
+    schematic::{PasteOptions, Schematic, StructureSettings},
+};
+
+/// A schematic world generation places, with its blocks turned every way ahead of time
+pub struct Structure {
+    pub settings: StructureSettings,
+    turns: [Vec<(IVec3, BlockHandle)>; 4],
+    sizes: [UVec3; 4],
+}
+
+/// Where a structure goes in the area of a transformer location
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StructurePlacement {
+    /// Lowest corner relative to the area, in blocks
+    pub min: IVec3,
+    pub quarter_turns: u32,
+}
+
+impl Structure {
+    /// `None` if the schematic has no structure settings
+    pub fn new(schematic: &Schematic, block_data: &StaticBlockData) -> Option<Self> {
+        let settings = schematic.metadata.structure?;
+        let palette = schematic.resolve(block_data);
+        let turns = [0, 1, 2, 3].map(|quarter_turns| {
+            let options = PasteOptions { quarter_turns, mask_air: !settings.carve, ..Default::default() };
+            schematic.placements(&palette, options)
+        });
+
+        Some(Self {
+            settings,
+            turns,
+            sizes: [0, 1, 2, 3].map(|turns| schematic.turned_size(turns)),
+        })
+    }
+
+    pub fn size(&self, quarter_turns: u32) -> UVec3 {
+        self.sizes[quarter_turns as usize % 4]
+    }
+
+    /// Blocks relative to the lowest corner
+    pub fn blocks(&self, quarter_turns: u32) -> &[(IVec3, BlockHandle)] {
+        &self.turns[quarter_turns as usize % 4]
+    }
+}
+
+/// Every schematic with structure settings in a directory, in file name order so the world is the same every time.
+///
+/// Files that can't be read are skipped with a warning, a missing directory has no structures.
+pub fn load_structures(dir: impl AsRef<Path>, block_data: &StaticBlockData) -> Vec<Structure> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| match Schematic::load(&path) {
+            Ok(schematic) => {
+                let structure = Structure::new(&schematic, block_data);
+                if structure.is_none() {
+                    println!("WARNING: The schematic {:?} has no structure settings, it is not placed", path);
+                }
+                structure
+            }
+            Err(e) => {
+                println!("WARNING: Could not read the structure {:?}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
@@ -11,38 +11,45 @@ use turborand::rng::Rng;
 use ultraviolet::{IVec2, Vec2, Vec3, IVec3, UVec2, DVec3, UVec3};
 
 use crate::util::more_vec::UsizeVec3;
-use crate::util::util::{MoreCmp, VecRounding, MoreVecConstructors, AdditionalSwizzles};
+use crate::util::util::{MoreCmp, VecRounding, MoreVecConstructors, AdditionalSwizzles, UVecToSigned};
 use crate::world::block_data::Blocks;
 use crate::world::chunk::{Chunk, CHUNK_HEIGHT};
 use crate::world::section::{F_SECTION_SIZE, SECTION_SIZE, I_SECTION_SIZE};
 use crate::world::{block_data::{StaticBlockData, BlockHandle}, section::Section};
+use crate::world::block_access::BlockAccess;
+use crate::world::schematic::STRUCTURE_DIR;
 
+use super::config::{TerrainConfig, TerrainShape};
+use super::density::ChunkDensitySampler;
 use super::noise::{ScaleNoise2D, ScaleNoise3D};
+use super::structures::{load_structures, Structure, StructurePlacement};
 use super::transformer::TerrainTransformer;
 
-const CAVE_RADIUS: f32 = 4.0;
-
 pub struct TerrainGenerator {
     pub planar_noise: ScaleNoise2D,
     pub world_noise: ScaleNoise3D,
     pub overall_height: ScaleNoise2D,
     pub seed: u32,
-    cave_transformer: TerrainTransformer<Vec<Vec3>>,
-    rng: Xoshiro128StarStar,
-    cache: [BlockHandle; 4]
+    pub config: TerrainConfig,
+    cave_transformer: Option<TerrainTransformer<Vec<Vec3>>>,
+    structure_transformers: Vec<TerrainTransformer<Option<StructurePlacement>>>,
+    cache: [BlockHandle; 5]
 }
 
 impl TerrainGenerator {
-    const NOISE_SCALE: f32 = 0.01;
     const OVERALL_SCALE: f32 = 0.001;
 
     pub fn new(seed: u32, block_data: &StaticBlockData) -> Self {
+        Self::with_config(seed, TerrainConfig::default(), block_data)
+    }
+
+    pub fn with_config(seed: u32, config: TerrainConfig, block_data: &StaticBlockData) -> Self {
         let planar_noise = ScaleNoise2D::new(
-            Vec2::new(Self::NOISE_SCALE, Self::NOISE_SCALE), 
+            Vec2::new(config.noise_scale, config.noise_scale), 
             seed
         );
         let world_noise = ScaleNoise3D::new(
-            Vec3::new(Self::NOISE_SCALE, Self::NOISE_SCALE, Self::NOISE_SCALE), 
+            Vec3::new(config.density_scale, config.density_scale, config.density_scale), 
             seed
         );
 
@@ -51,32 +58,43 @@ impl TerrainGenerator {
             seed
         );
 
-        let rng = Xoshiro128StarStar::seed_from_u64(seed as u64);
-
         let cache = [
             block_data.get_handle("air").unwrap(),
             block_data.get_handle("grass_block").unwrap(),
             block_data.get_handle("dirt").unwrap(),
             block_data.get_handle("stone").unwrap(),
+            block_data.get_handle("grass").unwrap(),
         ];
 
+        let structures = match config.structures {
+            true => load_structures(STRUCTURE_DIR, block_data),
+            false => Vec::new(),
+        };
+        let structure_transformers = structures
+            .into_iter()
+            .enumerate()
+            .map(|(i, structure)| Self::structure_transformer(seed, config, i as u32, structure))
+            .collect();
+
         Self {
             planar_noise, 
             world_noise, 
             overall_height, 
             seed,
-            cave_transformer: Self::cave_transformer(seed),
-            rng, cache
+            config,
+            cave_transformer: config.caves.then(|| Self::cave_transformer(seed, config.cave_radius)),
+            structure_transformers,
+            cache
         }
     }
 
-    fn cave_transformer(seed: u32) -> TerrainTransformer<Vec<Vec3>> {
+    fn cave_transformer(seed: u32, radius: f32) -> TerrainTransformer<Vec<Vec3>> {
         TerrainTransformer::new(
             UVec2::new(20, 20),
             UVec2::new(7, 7),
             move |offset, size| {
                 let seed_i = Self::basic_mix(seed, [offset.x as i64, offset.y as i64]);
-                let seed: u64 = unsafe { std::mem::transmute(seed_i) };
+                let seed = seed_i as u64;
 
                 let mut rng = Xoshiro128StarStar::seed_from_u64(seed);
                 let noise = SuperSimplex::new(rng.next_u32());
@@ -96,7 +114,7 @@ impl TerrainGenerator {
                 let middle = Vec2::from(size) * (F_SECTION_SIZE.xz() * 0.5);
                 let mut worm_pos = Vec3::new(middle.x, 20.0, middle.y);
 
-                let min_accept = Vec2::splat(CAVE_RADIUS);
+                let min_accept = Vec2::splat(radius);
                 let max_accept = Vec2::from(size * SECTION_SIZE.xz()) - min_accept;
 
                 let mut ret = Vec::new();
@@ -116,8 +134,8 @@ impl TerrainGenerator {
 
                 ret
             },
-            |chunk, offset, _size, data| {
-                const RAD_SQ: f32 = CAVE_RADIUS * CAVE_RADIUS;
+            move |chunk, offset, _size, data| {
+                let rad_sq = radius * radius;
                 let relative_pos = (chunk.blocks.pos - offset) * I_SECTION_SIZE.xz();
 
                 let min = relative_pos;
@@ -129,8 +147,8 @@ impl TerrainGenerator {
                 let max_chunk = Vec3::new(max.x as f32, max_h, max.y as f32);
 
                 for carve in data.iter() {
-                    let min_carve = (*carve - Vec3::one() * (CAVE_RADIUS + 1.0)).clamped(Vec3::zero(), Vec3::one() * max_h).floor();
-                    let max_carve = (*carve + Vec3::one() * (CAVE_RADIUS + 1.0)).clamped(Vec3::zero(), Vec3::one() * max_h).ceil();
+                    let min_carve = (*carve - Vec3::one() * (radius + 1.0)).clamped(Vec3::zero(), Vec3::one() * max_h).floor();
+                    let max_carve = (*carve + Vec3::one() * (radius + 1.0)).clamped(Vec3::zero(), Vec3::one() * max_h).ceil();
 
                     if min_carve.any_greater_than(&max_chunk) || max_carve.any_less_than(&min_chunk) {
                         continue;
@@ -162,7 +180,7 @@ impl TerrainGenerator {
                                 Vec3::new(min_chunk.x, y_off, min_chunk.z) + 
                                 (0.5 * Vec3::one());
 
-                            if (*carve - block_pos).mag_sq() <= RAD_SQ {
+                            if (*carve - block_pos).mag_sq() <= rad_sq {
                                 *block = Blocks::Air.handle();
                             }
                         });
@@ -172,6 +190,88 @@ impl TerrainGenerator {
         )
     }
 
+    /// Places a structure at a random place and turn in each area, on the terrain under its middle.
+    ///
+    /// `salt` keeps structures with the same spacing from all appearing at the same locations.
+    fn structure_transformer(seed: u32, config: TerrainConfig, salt: u32, structure: Structure) -> TerrainTransformer<Option<StructurePlacement>> {
+        let settings = structure.settings;
+        let footprint = (0..4).map(|turns| structure.size(turns)).fold(0, |max, size| max.max(size.x));
+        // Enough room in the area for the structure to move around in
+        let chunks = footprint.div_ceil(SECTION_SIZE.x) + 1;
+        let size = UVec2::new(chunks, chunks);
+        let spacing = UVec2::new(settings.spacing.max(chunks), settings.spacing.max(chunks));
+
+        let noise = ScaleNoise2D::new(Vec2::new(config.noise_scale, config.noise_scale), seed);
+        let world_noise = ScaleNoise3D::new(Vec3::broadcast(config.density_scale), seed);
+        let sizes: [UVec3; 4] = array::from_fn(|turns| structure.size(turns as u32));
+
+        TerrainTransformer::new(
+            size,
+            spacing,
+            move |location, size| {
+                let seed_i = Self::basic_mix(seed, [location.x as i64, location.y as i64, salt as i64]);
+                let mut rng = Xoshiro128StarStar::seed_from_u64(seed_i as u64);
+                if rng.next_u32() as f64 / u32::MAX as f64 >= settings.chance as f64 {
+                    return None;
+                }
+
+                let quarter_turns = rng.next_u32() % 4;
+                let turned = sizes[quarter_turns as usize];
+                let area = size * SECTION_SIZE.xz();
+                let x = rng.next_u32() % (area.x - turned.x + 1);
+                let z = rng.next_u32() % (area.y - turned.z + 1);
+
+                // Sampled the same way as the chunk under it, so the structure sits right on the surface
+                let middle = location * spacing.signed() * I_SECTION_SIZE.xz()
+                    + IVec2::new((x + turned.x / 2) as i32, (z + turned.z / 2) as i32);
+                let chunk_pos = IVec2::new(middle.x.div_euclid(I_SECTION_SIZE.x), middle.y.div_euclid(I_SECTION_SIZE.z));
+                let column = middle - chunk_pos * I_SECTION_SIZE.xz();
+                let height = match config.shape {
+                    TerrainShape::Heightmap => {
+                        let sampler = ChunkHeightSampler::new(
+                            (chunk_pos * I_SECTION_SIZE.xz()).into(),
+                            NonZeroUsize::new(8).unwrap(),
+                            &noise,
+                            config.octaves
+                        );
+                        Self::surface_height(&config, &sampler, column.x as usize, column.y as usize)
+                    }
+                    TerrainShape::Density => {
+                        let sampler = ChunkDensitySampler::new(chunk_pos, &config, &noise, &world_noise);
+                        sampler.surface(column.x as u32, column.y as u32).unwrap_or(0)
+                    }
+                };
+
+                Some(StructurePlacement {
+                    min: IVec3::new(x as i32, (height + 1) as i32 - settings.floor as i32, z as i32),
+                    quarter_turns,
+                })
+            },
+            move |chunk, offset, _size, data| {
+                let Some(placement) = data else { return };
+                let relative_pos = (chunk.blocks.pos - offset) * I_SECTION_SIZE.xz();
+                let max_h = (CHUNK_HEIGHT * SECTION_SIZE.y) as i32;
+
+                for (pos, block) in structure.blocks(placement.quarter_turns) {
+                    let pos = placement.min + *pos;
+                    let (x, z) = (pos.x - relative_pos.x, pos.z - relative_pos.y);
+                    if x < 0 || z < 0 || x >= I_SECTION_SIZE.x || z >= I_SECTION_SIZE.z || pos.y < 0 || pos.y >= max_h {
+                        continue;
+                    }
+
+                    let section = &mut chunk.blocks.sections[(pos.y / I_SECTION_SIZE.y) as usize];
+                    section.set_block(UVec3::new(x as u32, (pos.y % I_SECTION_SIZE.y) as u32, z as u32), *block);
+                }
+            }
+        )
+    }
+
+    /// Height of the grass block of a column in the chunk the sampler is for
+    fn surface_height(config: &TerrainConfig, sampler: &ChunkHeightSampler, x: usize, z: usize) -> u32 {
+        let pos = Vec2::new(x as f32 + 0.5, z as f32 + 0.5);
+        (sampler.sample(pos) * config.height_range + config.base_height).round() as u32
+    }
+
     // Should be replaced by just a noise function
     fn basic_mix(seed: u32, n: impl IntoIterator<Item = i64>) -> i64 {
         // very large RSA numbers
@@ -197,33 +297,45 @@ impl TerrainGenerator {
         ret
     }
 
-    pub fn new_random(block_data: &StaticBlockData) -> Self {
+    pub fn new_random(config: TerrainConfig, block_data: &StaticBlockData) -> Self {
         let seed = Rng::new().u32(..);
-        Self::new(seed, block_data)
+        Self::with_config(seed, config, block_data)
     }
 
     pub fn gen_chunk(&mut self, chunk_pos: IVec2) -> Chunk {
-        let height_sampler = ChunkHeightSampler::new(
-            (chunk_pos * I_SECTION_SIZE.xz()).into(), 
-            NonZeroUsize::new(8).unwrap(), 
-            &self.planar_noise, 
-            4
-        );
+        self.gen_terrain_chunk(chunk_pos).blocks
+    }
 
-        let mut chunk = self.chunk_from_height(height_sampler, chunk_pos);
-        self.cave_transformer.apply(&mut chunk);
-        chunk.blocks
+    /// A chunk with the height of its terrain surface, which caves and structures are not part of
+    pub fn gen_terrain_chunk(&mut self, chunk_pos: IVec2) -> TerrainChunk {
+        let mut chunk = match self.config.shape {
+            TerrainShape::Heightmap => {
+                let height_sampler = ChunkHeightSampler::new(
+                    (chunk_pos * I_SECTION_SIZE.xz()).into(), 
+                    NonZeroUsize::new(8).unwrap(), 
+                    &self.planar_noise, 
+                    self.config.octaves
+                );
+                self.chunk_from_height(height_sampler, chunk_pos)
+            }
+            TerrainShape::Density => self.chunk_from_density(chunk_pos),
+        };
+        if let Some(transformer) = self.cave_transformer.as_mut() {
+            transformer.apply(&mut chunk);
+        }
+        for transformer in self.structure_transformers.iter_mut() {
+            transformer.apply(&mut chunk);
+        }
+        chunk
     }
 
     fn chunk_from_height(&mut self, height_sampler: ChunkHeightSampler, chunk_pos: IVec2) -> TerrainChunk {
-        let off = Vec2::new(0.5, 0.5);
         let mut lowest = 999;
         let mut highest = 0;
         let height_array = Array2::from_shape_fn(
             (SECTION_SIZE.x as usize, SECTION_SIZE.z as usize), 
             |(x_step, y_step)| {
-                let pos = off + Vec2::new(x_step as f32, y_step as f32);
-                let height = (height_sampler.sample(pos) * 50.0 + 50.0).round() as u32;
+                let height = Self::surface_height(&self.config, &height_sampler, x_step, y_step);
                 let low_gen = height.saturating_sub(4);
                 if low_gen < lowest { lowest = low_gen; }
                 if height > highest { highest = height; }
@@ -274,7 +386,7 @@ impl TerrainGenerator {
                 continue;
             }
 
-            // let can_gen_grass = height >= height_offset.saturating_sub(1) && relative_height < SECTION_SIZE.y - 1;
+            let can_gen_grass = relative_height < SECTION_SIZE.y - 1;
             let stone_end = relative_height.saturating_sub(3).min(SECTION_SIZE.y - 1) as usize;
             let dirt_end = relative_height.min(SECTION_SIZE.y - 1) as usize;
             let mut c = [self.cache[0]; SECTION_SIZE.y as usize];
@@ -283,23 +395,72 @@ impl TerrainGenerator {
             c[stone_end..dirt_end].fill(self.cache[2]);
             c[relative_height.min(SECTION_SIZE.y - 1) as usize] = self.cache[1];
 
-            // let grass_pos = IVec3::new(
-            //     column_pos.x, 
-            //     ((section_num * SECTION_SIZE.y) + relative_height + 1) as i32, 
-            //     column_pos.y
-            // );
+            let grass_pos = IVec3::new(
+                column_pos.x, 
+                ((section_num * SECTION_SIZE.y) + relative_height + 1) as i32, 
+                column_pos.y
+            );
 
-            // if can_gen_grass && self.gen_grass_at(grass_pos) {
-            //     c[relative_height as usize + 1] = self.cache[1];
-            // }
+            if can_gen_grass && Self::gen_grass_at(self.seed, grass_pos) {
+                c[relative_height as usize + 1] = self.cache[4];
+            }
 
             column.assign(&arr1(&c));
         }
         ret
     }
 
-    fn gen_grass_at(&mut self, _pos: IVec3) -> bool {
-        self.rng.next_u32() & 1 == 0
+    /// Solid blocks get grass on top and dirt under it like heightmap terrain, counted from the air above each of them.
+    /// The height of a column is its highest solid block.
+    fn chunk_from_density(&mut self, chunk_pos: IVec2) -> TerrainChunk {
+        let sampler = ChunkDensitySampler::new(chunk_pos, &self.config, &self.planar_noise, &self.world_noise);
+        let mut blocks = Chunk::empty(chunk_pos);
+        let mut height = Array2::zeros((SECTION_SIZE.x as usize, SECTION_SIZE.z as usize));
+        let column_height = CHUNK_HEIGHT * SECTION_SIZE.y;
+
+        for x in 0..SECTION_SIZE.x {
+            for z in 0..SECTION_SIZE.z {
+                // Solid blocks since the last air above
+                let mut depth = None;
+                for y in (0..column_height).rev() {
+                    if !sampler.is_solid(x, y, z) {
+                        depth = None;
+                        continue;
+                    }
+                    let d = depth.map_or(0, |d| d + 1);
+                    depth = Some(d);
+                    if height[(x as usize, z as usize)] == 0 {
+                        height[(x as usize, z as usize)] = y;
+                    }
+
+                    let block = match d {
+                        0 => self.cache[1],
+                        1..=3 => self.cache[2],
+                        _ => self.cache[3],
+                    };
+                    let section = &mut blocks.sections[(y / SECTION_SIZE.y) as usize];
+                    section.set_block(UVec3::new(x, y % SECTION_SIZE.y, z), block);
+
+                    let grass_pos = IVec3::new(
+                        chunk_pos.x * I_SECTION_SIZE.x + x as i32,
+                        y as i32 + 1,
+                        chunk_pos.y * I_SECTION_SIZE.z + z as i32,
+                    );
+                    if d == 0 && y + 1 < column_height && Self::gen_grass_at(self.seed, grass_pos) {
+                        let section = &mut blocks.sections[((y + 1) / SECTION_SIZE.y) as usize];
+                        section.set_block(UVec3::new(x, (y + 1) % SECTION_SIZE.y, z), self.cache[4]);
+                    }
+                }
+            }
+        }
+
+        TerrainChunk { height, blocks }
+    }
+
+    /// A quarter of the surface gets grass, only depending on the position so chunks can be generated in any order
+    fn gen_grass_at(seed: u32, pos: IVec3) -> bool {
+        let seed_i = Self::basic_mix(seed, [pos.x as i64, pos.y as i64, pos.z as i64]);
+        Xoshiro128StarStar::seed_from_u64(seed_i as u64).next_u32() & 3 == 0
     }
 }
 
@@ -338,8 +499,9 @@ impl ChunkHeightSampler {
         let res = (self.height_data.len_of(Axis(0)) - 1) as f32;
         let mul = Vec2::splat(res) / F_SECTION_SIZE.xz();
 
-        let rounded_x = (relative_pos.x * mul.x).floor();
-        let rounded_y = (relative_pos.y * mul.y).floor();
+        // The far edges are the end of the last cell, not the start of one past it
+        let rounded_x = (relative_pos.x * mul.x).floor().min(res - 1.0);
+        let rounded_y = (relative_pos.y * mul.y).floor().min(res - 1.0);
         let data_x = rounded_x as usize;
         let data_y = rounded_y as usize;
 
@@ -378,6 +540,14 @@ mod test {
             height_data: arr2(&[[0.0, 0.0], [1.0, 1.0]])
         };
 
-        assert_eq!(sampler.sample(Vec2::new(8.0, 4.9743)), 0.5);
+        assert_eq!(sampler.sample(Vec2::new(4.0, 4.9743)), 0.5);
+        assert_eq!(sampler.sample(Vec2::new(8.0, 8.0)), 1.0);
+    }
+
+    #[test]
+    fn grass_covers_a_quarter() {
+        let positions = (0..64).flat_map(|x| (0..64).map(move |z| IVec3::new(x - 32, 60 + x % 3, z)));
+        let grass = positions.filter(|pos| TerrainGenerator::gen_grass_at(7, *pos)).count();
+        assert!((900..1150).contains(&grass), "{} of 4096 columns have grass", grass);
     }
 }
\ No newline at end of file
@@ -0,0 +1,272 @@
+use std::{fs, fs::File, io, io::BufWriter, path::Path};
+
+use ndarray::Array2;
+use ultraviolet::{IVec2, UVec2, UVec3};
+
+use crate::{render::texture::TextureAtlas, util::util::Facing};
+
+use super::{
+    block_access::BlockAccess,
+    block_data::{BlockHandle, Shape, StaticBlockData},
+    chunk::Chunk,
+    generation::terrain::TerrainGenerator,
+    section::SECTION_SIZE,
+    world_blocks::WorldBlocks,
+};
+
+pub const MAP_DIR: &str = "./maps";
+/// Pixels per side of a tile
+pub const TILE_SIZE: u32 = 256;
+/// Each level halves the resolution of the one before
+pub const ZOOM_LEVELS: u32 = 4;
+
+/// Columns at the bottom of this range get the darkest shade, columns at the top the brightest
+const SHADE_HEIGHTS: (f32, f32) = (32.0, 160.0);
+const SHADE_FACTORS: (f32, f32) = (0.6, 1.2);
+
+/// Average color of the top texture of every block, indexed by block handle
+pub struct BlockColors(Vec<[u8; 4]>);
+
+impl BlockColors {
+    pub fn new(block_data: &StaticBlockData, atlas: &TextureAtlas) -> Self {
+        let colors = block_data
+            .block_data()
+            .iter()
+            .map(|data| match data.model.is_some() {
+                true => atlas.average_color(data.model.textures().textures[Facing::UP.to_num()]),
+                false => [0; 4],
+            })
+            .collect();
+        Self(colors)
+    }
+
+    pub fn get(&self, block: BlockHandle) -> [u8; 4] {
+        self.0[block.inner() as usize]
+    }
+}
+
+/// A chunk seen from above, one pixel per column indexed by x then z. Columns without blocks are transparent.
+pub struct ChunkMap {
+    pub pos: IVec2,
+    pub colors: Array2<[u8; 4]>,
+}
+
+impl ChunkMap {
+    /// Shades columns by `heights` from `TerrainChunk` when given, otherwise by the height of their top block
+    pub fn render(chunk: &Chunk, heights: Option<&Array2<u32>>, block_data: &StaticBlockData, colors: &BlockColors) -> Self {
+        let shape = (SECTION_SIZE.x as usize, SECTION_SIZE.z as usize);
+        let colors = Array2::from_shape_fn(shape, |(x, z)| {
+            let Some((block, top)) = top_block(chunk, x as u32, z as u32, block_data) else { return [0; 4] };
+            let height = heights.map_or(top, |heights| heights[(x, z)]);
+            shade(colors.get(block), height)
+        });
+
+        Self { pos: chunk.pos, colors }
+    }
+}
+
+/// Highest block of a column that isn't air, and its height. Plants are seen through so grass doesn't speckle the map.
+fn top_block(chunk: &Chunk, x: u32, z: u32, block_data: &StaticBlockData) -> Option<(BlockHandle, u32)> {
+    for (i, section) in chunk.sections.iter().enumerate().rev() {
+        for y in (0..SECTION_SIZE.y).rev() {
+            let block = section.get_block(UVec3::new(x, y, z));
+            let model = &block_data.get(&block).model;
+            if model.is_some() && model.shape() != Some(Shape::Cross) {
+                return Some((block, i as u32 * SECTION_SIZE.y + y));
+            }
+        }
+    }
+    None
+}
+
+fn shade(color: [u8; 4], height: u32) -> [u8; 4] {
+    let t = ((height as f32 - SHADE_HEIGHTS.0) / (SHADE_HEIGHTS.1 - SHADE_HEIGHTS.0)).clamp(0.0, 1.0);
+    let factor = SHADE_FACTORS.0 + (SHADE_FACTORS.1 - SHADE_FACTORS.0) * t;
+    let [r, g, b, a] = color;
+    let [r, g, b] = [r, g, b].map(|c| (c as f32 * factor).min(255.0) as u8);
+    [r, g, b, a]
+}
+
+/// Generates the chunks between two chunk corners, both included, without loading them into a world
+pub fn generate_chunks(
+    generator: &mut TerrainGenerator,
+    corners: (IVec2, IVec2),
+    block_data: &StaticBlockData,
+    colors: &BlockColors,
+) -> Vec<ChunkMap> {
+    chunks_between(corners)
+        .map(|pos| {
+            let chunk = generator.gen_terrain_chunk(pos);
+            ChunkMap::render(&chunk.blocks, Some(&chunk.height), block_data, colors)
+        })
+        .collect()
+}
+
+/// The loaded chunks between two chunk corners, both included
+pub fn loaded_chunks(
+    world: &WorldBlocks,
+    corners: (IVec2, IVec2),
+    block_data: &StaticBlockData,
+    colors: &BlockColors,
+) -> Vec<ChunkMap> {
+    chunks_between(corners)
+        .filter_map(|pos| world.loaded_chunks.get(&pos))
+        .map(|chunk| ChunkMap::render(chunk, None, block_data, colors))
+        .collect()
+}
+
+pub fn chunks_between((a, b): (IVec2, IVec2)) -> impl Iterator<Item = IVec2> {
+    let (min, max) = (IVec2::new(a.x.min(b.x), a.y.min(b.y)), IVec2::new(a.x.max(b.x), a.y.max(b.y)));
+    (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |z| IVec2::new(x, z)))
+}
+
+/// Writes `ZOOM_LEVELS` levels of tiles to `dir/<blocks per pixel>/<x>_<z>.png`, returns how many were written.
+///
+/// Tiles line up with the world, so maps of neighbouring regions can be written into the same directory.
+pub fn write_map(chunks: &[ChunkMap], dir: impl AsRef<Path>) -> io::Result<usize> {
+    let Some(mut image) = MapImage::from_chunks(chunks) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "there are no chunks to draw"));
+    };
+
+    let mut tiles = 0;
+    for level in 0..ZOOM_LEVELS {
+        if level > 0 {
+            image = image.downsample();
+        }
+        tiles += image.write_tiles(&dir.as_ref().join((1 << level).to_string()))?;
+    }
+    Ok(tiles)
+}
+
+/// Part of the map at one zoom level, in pixels. Rows go along +Z.
+struct MapImage {
+    min: IVec2,
+    size: UVec2,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl MapImage {
+    fn from_chunks(chunks: &[ChunkMap]) -> Option<Self> {
+        let chunk_size = IVec2::new(SECTION_SIZE.x as i32, SECTION_SIZE.z as i32);
+        let min_chunk = chunks.iter().map(|c| c.pos).reduce(|a, b| IVec2::new(a.x.min(b.x), a.y.min(b.y)))?;
+        let max_chunk = chunks.iter().map(|c| c.pos).reduce(|a, b| IVec2::new(a.x.max(b.x), a.y.max(b.y)))?;
+
+        let min = min_chunk * chunk_size;
+        let size = (max_chunk - min_chunk + IVec2::one()) * chunk_size;
+        let mut image = Self {
+            min,
+            size: UVec2::new(size.x as u32, size.y as u32),
+            pixels: vec![[0; 4]; (size.x * size.y) as usize],
+        };
+
+        for chunk in chunks {
+            let corner = chunk.pos * chunk_size - min;
+            for ((x, z), color) in chunk.colors.indexed_iter() {
+                let index = (corner.y as usize + z) * image.size.x as usize + corner.x as usize + x;
+                image.pixels[index] = *color;
+            }
+        }
+        Some(image)
+    }
+
+    /// Transparent outside of the image
+    fn get(&self, pos: IVec2) -> [u8; 4] {
+        let local = pos - self.min;
+        if local.x < 0 || local.y < 0 || local.x as u32 >= self.size.x || local.y as u32 >= self.size.y {
+            return [0; 4];
+        }
+        self.pixels[(local.y as u32 * self.size.x + local.x as u32) as usize]
+    }
+
+    /// Half the resolution, every pixel is the average of the visible pixels it covers
+    fn downsample(&self) -> Self {
+        let max = self.min + IVec2::new(self.size.x as i32, self.size.y as i32);
+        let min = IVec2::new(self.min.x.div_euclid(2), self.min.y.div_euclid(2));
+        let size = IVec2::new((max.x + 1).div_euclid(2), (max.y + 1).div_euclid(2)) - min;
+
+        let mut pixels = Vec::with_capacity((size.x * size.y) as usize);
+        for y in min.y..min.y + size.y {
+            for x in min.x..min.x + size.x {
+                let covered = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .map(|(dx, dy)| self.get(IVec2::new(x * 2 + dx, y * 2 + dy)));
+                let visible = covered.iter().filter(|c| c[3] > 0).collect::<Vec<_>>();
+                if visible.is_empty() {
+                    pixels.push([0; 4]);
+                    continue;
+                }
+
+                let mut sum = [0u32; 4];
+                visible.iter().for_each(|c| sum.iter_mut().zip(c.iter()).for_each(|(s, c)| *s += *c as u32));
+                pixels.push(sum.map(|s| (s / visible.len() as u32) as u8));
+            }
+        }
+
+        Self { min, size: UVec2::new(size.x as u32, size.y as u32), pixels }
+    }
+
+    /// Skips tiles with nothing in them
+    fn write_tiles(&self, dir: &Path) -> io::Result<usize> {
+        let tile = TILE_SIZE as i32;
+        let max = self.min + IVec2::new(self.size.x as i32 - 1, self.size.y as i32 - 1);
+        let (min_tile, max_tile) = (
+            IVec2::new(self.min.x.div_euclid(tile), self.min.y.div_euclid(tile)),
+            IVec2::new(max.x.div_euclid(tile), max.y.div_euclid(tile)),
+        );
+
+        fs::create_dir_all(dir)?;
+        let mut written = 0;
+        for tile_x in min_tile.x..=max_tile.x {
+            for tile_z in min_tile.y..=max_tile.y {
+                let corner = IVec2::new(tile_x * tile, tile_z * tile);
+                let mut data = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 4) as usize);
+                for y in 0..tile {
+                    for x in 0..tile {
+                        data.extend(self.get(corner + IVec2::new(x, y)));
+                    }
+                }
+                if data.chunks(4).all(|c| c[3] == 0) {
+                    continue;
+                }
+
+                write_png(&dir.join(format!("{}_{}.png", tile_x, tile_z)), UVec2::new(TILE_SIZE, TILE_SIZE), &data)?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+}
+
+pub fn write_png(path: &Path, dimensions: UVec2, rgba: &[u8]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, dimensions.x, dimensions.y);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn downsampling_averages_visible_pixels() {
+        // Starts at an odd pixel, so the first column of the half size image only covers one column
+        let image = MapImage {
+            min: IVec2::new(-1, 0),
+            size: UVec2::new(3, 2),
+            pixels: vec![
+                [10, 10, 10, 255], [20, 20, 20, 255], [0; 4],
+                [30, 30, 30, 255], [40, 40, 40, 255], [0; 4],
+            ],
+        };
+
+        let half = image.downsample();
+        assert_eq!(half.min, IVec2::new(-1, 0));
+        assert_eq!(half.size, UVec2::new(2, 1));
+        assert_eq!(half.get(IVec2::new(-1, 0)), [20, 20, 20, 255]);
+        assert_eq!(half.get(IVec2::new(0, 0)), [30, 30, 30, 255]);
+    }
+}
@@ -3,4 +3,9 @@ pub mod block_data;
 pub mod chunk;
 pub mod section;
 pub mod block_access;
-pub mod generation;
\ No newline at end of file
+pub mod generation;
+pub mod export;
+pub mod region;
+pub mod vox;
+pub mod schematic;
+pub mod map;
\ No newline at end of file
@@ -0,0 +1,105 @@
+use std::io;
+
+use ahash::HashSet;
+use ultraviolet::{IVec2, IVec3, UVec3};
+
+use super::{
+    block_data::{BlockHandle, Blocks},
+    section::I_SECTION_SIZE,
+    world_blocks::WorldBlocks,
+};
+
+/// Larger regions take too long to copy on the main thread
+const MAX_REGION_VOLUME: i64 = 256 * 256 * 256;
+
+/// Blocks of a box in the world, copied out so they can be read without going through the chunk map
+#[derive(Clone, Debug)]
+pub struct RegionBlocks {
+    /// World position of the lowest corner
+    pub min: IVec3,
+    pub size: UVec3,
+    blocks: Vec<BlockHandle>,
+}
+
+impl RegionBlocks {
+    /// Copies the blocks between two corners, both included.
+    ///
+    /// Errors if a chunk in the region isn't loaded, blocks above and below the world are air.
+    pub fn copy(world: &WorldBlocks, corner_a: IVec3, corner_b: IVec3) -> io::Result<Self> {
+        let min = IVec3::new(corner_a.x.min(corner_b.x), corner_a.y.min(corner_b.y), corner_a.z.min(corner_b.z));
+        let max = IVec3::new(corner_a.x.max(corner_b.x), corner_a.y.max(corner_b.y), corner_a.z.max(corner_b.z));
+
+        let size = max - min + IVec3::one();
+        let volume = size.x as i64 * size.y as i64 * size.z as i64;
+        if volume > MAX_REGION_VOLUME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the region has {} blocks, at most {} can be copied", volume, MAX_REGION_VOLUME),
+            ));
+        }
+
+        let mut missing = HashSet::default();
+        for x in min.x..=max.x {
+            for z in min.z..=max.z {
+                let chunk_pos = IVec2::new(x.div_euclid(I_SECTION_SIZE.x), z.div_euclid(I_SECTION_SIZE.z));
+                if !world.loaded_chunks.contains_key(&chunk_pos) {
+                    missing.insert(chunk_pos);
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} chunks of the region are not loaded", missing.len()),
+            ));
+        }
+
+        let size = UVec3::new(size.x as u32, size.y as u32, size.z as u32);
+        let mut blocks = Vec::with_capacity(volume as usize);
+        for x in 0..size.x as i32 {
+            for y in 0..size.y as i32 {
+                for z in 0..size.z as i32 {
+                    let block = world.get_block(min + IVec3::new(x, y, z));
+                    blocks.push(block.unwrap_or(Blocks::Air.handle()));
+                }
+            }
+        }
+
+        Ok(Self { min, size, blocks })
+    }
+
+    /// Relative to the lowest corner, air outside of the region
+    pub fn get(&self, pos: IVec3) -> BlockHandle {
+        let inside = pos.x >= 0
+            && pos.y >= 0
+            && pos.z >= 0
+            && (pos.x as u32) < self.size.x
+            && (pos.y as u32) < self.size.y
+            && (pos.z as u32) < self.size.z;
+
+        if !inside {
+            return Blocks::Air.handle();
+        }
+        let (x, y, z) = (pos.x as u32, pos.y as u32, pos.z as u32);
+        self.blocks[((x * self.size.y + y) * self.size.z + z) as usize]
+    }
+
+    /// Every block with its position relative to the lowest corner
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, BlockHandle)> + '_ {
+        let (size_y, size_z) = (self.size.y as usize, self.size.z as usize);
+        self.blocks.iter().enumerate().map(move |(i, block)| {
+            let pos = IVec3::new((i / (size_y * size_z)) as i32, (i / size_z % size_y) as i32, (i % size_z) as i32);
+            (pos, *block)
+        })
+    }
+}
+
+/// Quarter turns around the Y axis, counter clockwise seen from above
+pub fn rotate_y(pos: IVec3, quarter_turns: u32) -> IVec3 {
+    match quarter_turns % 4 {
+        0 => pos,
+        1 => IVec3::new(pos.z, pos.y, -pos.x),
+        2 => IVec3::new(-pos.x, pos.y, -pos.z),
+        _ => IVec3::new(-pos.z, pos.y, pos.x),
+    }
+}
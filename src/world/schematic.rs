@@ -0,0 +1,250 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ahash::HashMap;
+use serde::{Deserialize, Serialize};
+use ultraviolet::{IVec3, UVec3};
+
+use super::{
+    block_data::{BlockHandle, Blocks, StaticBlockData},
+    region::{rotate_y, RegionBlocks},
+    world_blocks::WorldBlocks,
+};
+
+pub const SCHEMATIC_DIR: &str = "./schematics";
+/// World generation places the schematics in here that have structure settings
+pub const STRUCTURE_DIR: &str = "./schematics/structures";
+
+/// Blocks of a box with their own palette of block ids, so files stay valid when blocks are added
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Schematic {
+    pub size: [u32; 3],
+    /// Block ids the blocks point into
+    pub palette: Vec<String>,
+    /// Palette indices, ordered like `RegionBlocks`: by X, then Y, then Z
+    pub blocks: Vec<u16>,
+    #[serde(default)]
+    pub metadata: SchematicMetadata,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchematicMetadata {
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    /// Set if world generation places the schematic as a structure
+    pub structure: Option<StructureSettings>,
+}
+
+/// How often world generation places a schematic and how it sits on the terrain
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StructureSettings {
+    /// Chunks between the places the structure can appear
+    pub spacing: u32,
+    /// Chance to appear at each place, from 0 to 1
+    pub chance: f32,
+    /// Layer of the schematic that is placed one block above the terrain surface
+    #[serde(default)]
+    pub floor: u32,
+    /// Air in the schematic replaces the terrain, otherwise only its blocks are placed
+    #[serde(default)]
+    pub carve: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PasteOptions {
+    /// Counter clockwise seen from above, applied after mirroring
+    pub quarter_turns: u32,
+    pub mirror_x: bool,
+    pub mirror_z: bool,
+    /// Air in the schematic leaves the blocks it would replace
+    pub mask_air: bool,
+}
+
+impl Schematic {
+    pub fn from_region(region: &RegionBlocks, block_data: &StaticBlockData) -> Self {
+        let mut palette = Vec::new();
+        let mut indices = HashMap::<u32, u16>::default();
+        let blocks = region
+            .iter()
+            .map(|(_, block)| {
+                *indices.entry(block.inner()).or_insert_with(|| {
+                    palette.push(block_data.get(&block).id.clone());
+                    (palette.len() - 1) as u16
+                })
+            })
+            .collect();
+
+        Self {
+            size: [region.size.x, region.size.y, region.size.z],
+            palette,
+            blocks,
+            metadata: SchematicMetadata::default(),
+        }
+    }
+
+    pub fn size(&self) -> UVec3 {
+        UVec3::new(self.size[0], self.size[1], self.size[2])
+    }
+
+    /// Size after turning, odd turns swap X and Z
+    pub fn turned_size(&self, quarter_turns: u32) -> UVec3 {
+        let size = self.size();
+        match quarter_turns % 2 {
+            0 => size,
+            _ => UVec3::new(size.z, size.y, size.x),
+        }
+    }
+
+    /// Block of every palette entry, unknown block ids become air
+    pub fn resolve(&self, block_data: &StaticBlockData) -> Vec<BlockHandle> {
+        self.palette
+            .iter()
+            .map(|id| {
+                block_data.get_handle(id).unwrap_or_else(|| {
+                    println!("WARNING: Unknown block \"{}\" in the schematic, using air", id);
+                    Blocks::Air.handle()
+                })
+            })
+            .collect()
+    }
+
+    /// Every block relative to the lowest corner of the transformed box, `palette` comes from `resolve`
+    pub fn placements(&self, palette: &[BlockHandle], options: PasteOptions) -> Vec<(IVec3, BlockHandle)> {
+        let size = self.size();
+        let max = IVec3::new(size.x as i32 - 1, size.y as i32 - 1, size.z as i32 - 1);
+        let transform = |pos: IVec3| {
+            let x = if options.mirror_x { max.x - pos.x } else { pos.x };
+            let z = if options.mirror_z { max.z - pos.z } else { pos.z };
+            rotate_y(IVec3::new(x, pos.y, z), options.quarter_turns)
+        };
+
+        // Turning maps the opposite corners of the box to opposite corners
+        let (a, b) = (transform(IVec3::zero()), transform(max));
+        let min = IVec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+
+        let (size_y, size_z) = (size.y as usize, size.z as usize);
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                let pos = IVec3::new((i / (size_y * size_z)) as i32, (i / size_z % size_y) as i32, (i % size_z) as i32);
+                (transform(pos) - min, palette[*index as usize])
+            })
+            .filter(|(_, block)| !options.mask_air || *block != Blocks::Air.handle())
+            .collect()
+    }
+
+    /// Places the schematic with its lowest corner at `origin`, returns how many blocks were placed.
+    ///
+    /// Blocks in chunks that aren't loaded are skipped.
+    pub fn paste(
+        &self,
+        world: &mut WorldBlocks,
+        origin: IVec3,
+        options: PasteOptions,
+        block_data: &StaticBlockData,
+    ) -> usize {
+        let placements = self.placements(&self.resolve(block_data), options);
+        world.set_blocks(placements.into_iter().map(|(pos, block)| (origin + pos, block)), block_data)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        let schematic = serde_json::from_str::<Self>(&src)?;
+        schematic.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(schematic)
+    }
+
+    /// Compact JSON, pretty printing would put every block on its own line
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let volume = self.size.iter().map(|s| *s as usize).product::<usize>();
+        if self.blocks.len() != volume {
+            return Err(format!("{} blocks for a size of {:?}", self.blocks.len(), self.size));
+        }
+        if let Some(index) = self.blocks.iter().find(|i| **i as usize >= self.palette.len()) {
+            return Err(format!("block {} is outside of the palette of {}", index, self.palette.len()));
+        }
+        Ok(())
+    }
+}
+
+/// Bare names are looked up in `SCHEMATIC_DIR`, with `.json` added if they have no extension
+pub fn schematic_path(name: &str) -> PathBuf {
+    let path = Path::new(name);
+    if path.parent().is_some_and(|p| !p.as_os_str().is_empty()) {
+        return path.to_path_buf();
+    }
+    let path = Path::new(SCHEMATIC_DIR).join(path);
+    match path.extension() {
+        Some(_) => path,
+        None => path.with_extension("json"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 2x1x3 with stone in the corner at the origin and dirt at the far end of Z
+    fn schematic() -> Schematic {
+        Schematic {
+            size: [2, 1, 3],
+            palette: vec!["air".to_string(), "stone".to_string(), "dirt".to_string()],
+            blocks: vec![1, 0, 2, 0, 0, 0],
+            metadata: SchematicMetadata::default(),
+        }
+    }
+
+    fn palette() -> Vec<BlockHandle> {
+        vec![Blocks::Air.handle(), Blocks::Stone.handle(), Blocks::Dirt.handle()]
+    }
+
+    fn block_at(placements: &[(IVec3, BlockHandle)], block: BlockHandle) -> IVec3 {
+        placements.iter().find(|(_, b)| *b == block).unwrap().0
+    }
+
+    #[test]
+    fn paste_transforms() {
+        let schematic = schematic();
+
+        let turned = schematic.placements(&palette(), PasteOptions { quarter_turns: 1, ..Default::default() });
+        assert_eq!(schematic.turned_size(1), UVec3::new(3, 1, 2));
+        assert!(turned.iter().all(|(pos, _)| pos.x >= 0 && pos.x < 3 && pos.z >= 0 && pos.z < 2));
+        // (x, z) turns to (z, -x), so +Z of the schematic points along +X
+        assert_eq!(block_at(&turned, Blocks::Stone.handle()), IVec3::new(0, 0, 1));
+        assert_eq!(block_at(&turned, Blocks::Dirt.handle()), IVec3::new(2, 0, 1));
+
+        let mirrored = schematic.placements(&palette(), PasteOptions { mirror_z: true, ..Default::default() });
+        assert_eq!(block_at(&mirrored, Blocks::Stone.handle()), IVec3::new(0, 0, 2));
+        assert_eq!(block_at(&mirrored, Blocks::Dirt.handle()), IVec3::new(0, 0, 0));
+    }
+
+    #[test]
+    fn masked_air_is_skipped() {
+        let schematic = schematic();
+        assert_eq!(schematic.placements(&palette(), PasteOptions::default()).len(), 6);
+
+        let masked = schematic.placements(&palette(), PasteOptions { mask_air: true, ..Default::default() });
+        assert_eq!(masked.len(), 2);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let schematic = schematic();
+        let parsed = serde_json::from_str::<Schematic>(&serde_json::to_string(&schematic).unwrap()).unwrap();
+        assert_eq!(parsed, schematic);
+        assert!(parsed.validate().is_ok());
+    }
+}
@@ -10,7 +10,7 @@ use crate::{
 
 use super::{
     block_access::BlockAccess,
-    block_data::{BlockHandle, Blocks, StaticBlockData},
+    block_data::{BlockHandle, Blocks, Shape, StaticBlockData},
 };
 
 pub const SECTION_SIZE: UVec3 = UVec3::new(8, 8, 8);
@@ -86,6 +86,7 @@ impl Section {
 
     pub fn update_brickmap(&mut self, block_data: &StaticBlockData) {
         self.brickmap.solid_mask = self.solid_mask(block_data);
+        self.brickmap.transparent_mask = self.transparent_mask(block_data);
     }
 
     pub fn solid_mask(&self, block_data: &StaticBlockData) -> [[u8; 8]; 8] {
@@ -96,27 +97,27 @@ impl Section {
         let mut ret = [[0; 8]; 8];
 
         for ((x, y, z), b) in self.blocks.indexed_iter() {
-            if block_data.get(b).model.is_full() {
+            if block_data.get(b).model.shape().is_some() {
                 ret[x][y] |= 1 << z;
             }
         }
 
         ret
     }
-}
 
-#[derive(Copy, Clone, Debug)]
-enum Neighbor {
-    Boundary,
-    Block(BlockHandle),
-}
+    /// Blocks that are not opaque or do not fill their voxel
+    pub fn transparent_mask(&self, block_data: &StaticBlockData) -> [[u8; 8]; 8] {
+        let mut ret = [[0; 8]; 8];
 
-impl From<Option<BlockHandle>> for Neighbor {
-    fn from(value: Option<BlockHandle>) -> Self {
-        match value {
-            Some(b) => Self::Block(b),
-            None => Self::Boundary,
+        for ((x, y, z), b) in self.blocks.indexed_iter() {
+            let data = block_data.get(b);
+            let partial = matches!(data.model.shape(), Some(shape) if shape != Shape::Cube);
+            if partial || (data.model.is_full() && !data.block_type.is_opaque()) {
+                ret[x][y] |= 1 << z;
+            }
         }
+
+        ret
     }
 }
 
@@ -149,12 +150,12 @@ impl BlockCull {
     }
 
     fn is_culled_num(&self, num: usize) -> bool {
-        self.inner & (1 << (6 - num)) > 0
+        self.inner & (1 << (5 - num)) > 0
     }
 
     pub fn set_face(&mut self, face: usize, b: bool) {
-        let mask = 1 << (6 - face);
-        self.inner = (self.inner & !mask) | (Self::to_u8(&b) << (6 - face));
+        let mask = 1 << (5 - face);
+        self.inner = (self.inner & !mask) | (Self::to_u8(&b) << (5 - face));
     }
 
     pub fn get_bools(&self) -> [bool; 6] {
@@ -162,14 +163,7 @@ impl BlockCull {
     }
 
     pub fn get_unculled(&self) -> Vec<usize> {
-        // There is no mutating occurring here, but removing `map` throws an error.
-        // Maybe investigate this further.
-        (0..6)
-            .filter_map(|f| match self.is_culled_num(f) {
-                true => None,
-                false => Some(f),
-            })
-            .collect()
+        (0..6).filter(|f| !self.is_culled_num(*f)).collect()
     }
 
     fn to_u8(b: &bool) -> u8 {
@@ -179,3 +173,20 @@ impl BlockCull {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn block_cull_bits() {
+        let cull = BlockCull::from_array([true, false, false, false, false, true]);
+        assert!(cull.is_culled(Facing::RIGHT));
+        assert!(cull.is_culled(Facing::BACK));
+        assert_eq!(cull.get_unculled(), vec![1, 2, 3, 4]);
+
+        let mut cull = BlockCull::none();
+        cull.set_face(2, true);
+        assert_eq!(cull.get_bools(), [false, false, true, false, false, false]);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, path::Path};
+
+use ultraviolet::{IVec3, UVec3};
+
+use crate::{
+    render::texture::TextureAtlas,
+    world::{
+        block_data::{BlockHandle, StaticBlockData},
+        map::BlockColors,
+        region::{rotate_y, RegionBlocks},
+        world_blocks::WorldBlocks,
+    },
+};
+
+use super::{
+    palette::{VoxPalette, VOX_PALETTE_PATH},
+    VoxFile, VoxModel, Voxel, MAX_MODEL_SIZE,
+};
+
+/// `.vox` files have `Z` up, the axes are turned so models aren't mirrored
+fn from_file_axes(pos: IVec3) -> IVec3 {
+    IVec3::new(pos.x, pos.z, -pos.y)
+}
+
+/// Places every model of the file with the lowest corner of the scene at `origin`.
+///
+/// `blocks` is the block of every palette index, from `VoxPalette::blocks`. Returns how many blocks were placed,
+/// voxels in chunks that aren't loaded are skipped.
+pub fn place(
+    file: &VoxFile,
+    blocks: &[BlockHandle; 256],
+    world: &mut WorldBlocks,
+    block_data: &StaticBlockData,
+    origin: IVec3,
+    quarter_turns: u32,
+) -> usize {
+    let voxels = file
+        .models
+        .iter()
+        .flat_map(|model| {
+            model.voxels.iter().map(move |v| {
+                let pos = model.offset + IVec3::new(v.pos[0] as i32, v.pos[1] as i32, v.pos[2] as i32);
+                (rotate_y(from_file_axes(pos), quarter_turns), v.color)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let Some(min) = voxels.iter().map(|(pos, _)| *pos).reduce(|a, b| {
+        IVec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+    }) else {
+        return 0;
+    };
+
+    let placed = voxels
+        .into_iter()
+        .map(|(pos, color)| (origin + pos - min, blocks[color as usize]));
+    world.set_blocks(placed, block_data)
+}
+
+/// Converts a region to a `.vox` file, split into several models if it is larger than a model can be.
+///
+/// Blocks without a palette entry get the free indices, the palette colors are the average colors
+/// of the blocks' top textures.
+pub fn from_region(
+    region: &RegionBlocks,
+    palette: &VoxPalette,
+    block_data: &StaticBlockData,
+    atlas: &TextureAtlas,
+) -> Result<VoxFile, String> {
+    let mut indices = palette.indices(block_data);
+    let mut free = (1..=255u8).filter(|i| !palette.blocks.contains_key(i));
+
+    let mut colors = [[0, 0, 0, 255]; 256];
+    let block_colors = BlockColors::new(block_data, atlas);
+    for (index, id) in palette.blocks.iter() {
+        if let Some(block) = block_data.get_handle(id) {
+            colors[*index as usize] = block_colors.get(block);
+        }
+    }
+
+    // Z up in the file, flipped so the file's Y doesn't go negative
+    let file_size = UVec3::new(region.size.x, region.size.z, region.size.y);
+    let mut pieces = BTreeMap::<[u32; 3], Vec<Voxel>>::new();
+
+    for (pos, block) in region.iter() {
+        if block_data.get(&block).model.is_none() {
+            continue;
+        }
+
+        let index = match indices.get(&block.inner()) {
+            Some(index) => *index,
+            None => {
+                let index = free.next().ok_or("the region has more kinds of blocks than the palette has room for")?;
+                indices.insert(block.inner(), index);
+                colors[index as usize] = block_colors.get(block);
+                index
+            }
+        };
+
+        let file_pos = [pos.x as u32, region.size.z - 1 - pos.z as u32, pos.y as u32];
+        let piece = file_pos.map(|v| v / MAX_MODEL_SIZE);
+        let local = file_pos.map(|v| (v % MAX_MODEL_SIZE) as u8);
+        pieces.entry(piece).or_default().push(Voxel { pos: local, color: index });
+    }
+
+    if pieces.is_empty() {
+        return Err("there are no blocks in the region".to_string());
+    }
+
+    let file_size = [file_size.x, file_size.y, file_size.z];
+    let models = pieces
+        .into_iter()
+        .map(|(piece, voxels)| {
+            let offset = piece.map(|p| p * MAX_MODEL_SIZE);
+            let size = [0, 1, 2].map(|i| (file_size[i] - offset[i]).min(MAX_MODEL_SIZE));
+            VoxModel {
+                size: UVec3::new(size[0], size[1], size[2]),
+                offset: IVec3::new(offset[0] as i32, offset[1] as i32, offset[2] as i32),
+                voxels,
+            }
+        })
+        .collect();
+
+    Ok(VoxFile { models, palette: colors })
+}
+
+/// Reads a file and places it with the palette from `VOX_PALETTE_PATH`, returns the message for the console
+pub fn import_file(
+    path: &str,
+    origin: IVec3,
+    quarter_turns: u32,
+    world: &mut WorldBlocks,
+    block_data: &StaticBlockData,
+) -> String {
+    let file = match VoxFile::load(path) {
+        Ok(file) => file,
+        Err(e) => return format!("Could not read {}: {}", path, e),
+    };
+
+    let blocks = VoxPalette::load(VOX_PALETTE_PATH).blocks(block_data);
+    let placed = place(&file, &blocks, world, block_data, origin, quarter_turns);
+    format!("Placed {} blocks from {} models of {}", placed, file.models.len(), path)
+}
+
+/// Writes the blocks between two corners with the palette from `VOX_PALETTE_PATH`, returns the message for the console
+pub fn export_file(
+    path: &str,
+    corners: (IVec3, IVec3),
+    world: &WorldBlocks,
+    block_data: &StaticBlockData,
+    atlas: &TextureAtlas,
+) -> String {
+    let file = RegionBlocks::copy(world, corners.0, corners.1)
+        .map_err(|e| e.to_string())
+        .and_then(|region| from_region(&region, &VoxPalette::load(VOX_PALETTE_PATH), block_data, atlas));
+
+    match file.map(|file| (file.save(Path::new(path)), file.models.len())) {
+        Ok((Ok(()), models)) => format!("Wrote {} models to {}", models, path),
+        Ok((Err(e), _)) => format!("Could not write {}: {}", path, e),
+        Err(e) => format!("Could not export to {}: {}", path, e),
+    }
+}
@@ -0,0 +1,392 @@
+use std::{fs, io, path::Path};
+
+use ahash::HashMap;
+use ultraviolet::{IVec3, UVec3};
+
+pub mod convert;
+pub mod palette;
+
+/// Models can't be larger than this along any axis
+pub const MAX_MODEL_SIZE: u32 = 256;
+const VERSION: u32 = 150;
+/// Scene graphs deeper than this are assumed to be broken
+const MAX_NODE_DEPTH: u32 = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Voxel {
+    pub pos: [u8; 3],
+    /// Palette index, 0 is empty and never stored
+    pub color: u8,
+}
+
+/// One model of a `.vox` file. Coordinates are as in the file, with `Z` up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxModel {
+    pub size: UVec3,
+    /// Position of the lowest corner in the scene, from the transform nodes
+    pub offset: IVec3,
+    pub voxels: Vec<Voxel>,
+}
+
+/// MagicaVoxel `.vox` file with one or more models.
+///
+/// The `SIZE`, `XYZI` and `RGBA` chunks are read along with the translations of the scene graph,
+/// rotations in the scene graph and everything else is ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    /// Color of each palette index, index 0 is unused
+    pub palette: [[u8; 4]; 256],
+}
+
+enum Node {
+    Transform { child: i32, translation: IVec3 },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+impl VoxFile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        Self::parse(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(data);
+        if reader.bytes(4)? != b"VOX " {
+            return Err("not a .vox file".to_string());
+        }
+        let _version = reader.u32()?;
+
+        let (id, _, children) = reader.chunk()?;
+        if id != b"MAIN" {
+            return Err("the file doesn't start with a MAIN chunk".to_string());
+        }
+
+        let mut models = Vec::new();
+        let mut size = None;
+        let mut palette = default_palette();
+        let mut nodes = HashMap::default();
+
+        let mut reader = Reader::new(children);
+        while !reader.is_empty() {
+            let (id, content, _) = reader.chunk()?;
+            let mut c = Reader::new(content);
+            match id {
+                b"SIZE" => size = Some(UVec3::new(c.u32()?, c.u32()?, c.u32()?)),
+                b"XYZI" => {
+                    let size = size.take().ok_or("XYZI chunk without a SIZE chunk")?;
+                    let count = c.u32()?;
+                    let voxels = (0..count)
+                        .map(|_| {
+                            let v = c.bytes(4)?;
+                            Ok(Voxel { pos: [v[0], v[1], v[2]], color: v[3] })
+                        })
+                        .collect::<Result<Vec<_>, String>>()?;
+                    models.push(VoxModel { size, offset: IVec3::zero(), voxels });
+                }
+                // The colors start at index 1
+                b"RGBA" => {
+                    for color in palette.iter_mut().skip(1) {
+                        let rgba = c.bytes(4)?;
+                        *color = [rgba[0], rgba[1], rgba[2], rgba[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let node_id = c.i32()?;
+                    let _attributes = c.dict()?;
+                    let child = c.i32()?;
+                    let _reserved = c.i32()?;
+                    let _layer = c.i32()?;
+                    let frames = c.u32()?;
+                    let translation = match frames {
+                        0 => IVec3::zero(),
+                        _ => parse_translation(c.dict()?.get("_t").map(String::as_str))?,
+                    };
+                    nodes.insert(node_id, Node::Transform { child, translation });
+                }
+                b"nGRP" => {
+                    let node_id = c.i32()?;
+                    let _attributes = c.dict()?;
+                    let count = c.u32()?;
+                    let children = (0..count).map(|_| c.i32()).collect::<Result<_, _>>()?;
+                    nodes.insert(node_id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let node_id = c.i32()?;
+                    let _attributes = c.dict()?;
+                    let count = c.u32()?;
+                    let mut shape_models = Vec::new();
+                    for _ in 0..count {
+                        shape_models.push(c.i32()?);
+                        let _attributes = c.dict()?;
+                    }
+                    nodes.insert(node_id, Node::Shape { models: shape_models });
+                }
+                _ => (),
+            }
+        }
+
+        if nodes.contains_key(&0) {
+            place_models(&nodes, 0, IVec3::zero(), 0, &mut models)?;
+        }
+
+        Ok(Self { models, palette })
+    }
+
+    /// Writes the models with a transform node each, so their offsets are kept
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+        for model in self.models.iter() {
+            let mut size = Vec::new();
+            for v in [model.size.x, model.size.y, model.size.z] {
+                size.extend(v.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size, &[]);
+
+            let mut xyzi = (model.voxels.len() as u32).to_le_bytes().to_vec();
+            for voxel in model.voxels.iter() {
+                xyzi.extend(voxel.pos);
+                xyzi.push(voxel.color);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        }
+
+        // Root transform, a group and a transform and shape for every model
+        let model_count = self.models.len() as i32;
+        let mut content = Vec::new();
+        write_transform(&mut content, 0, 1, None);
+        write_chunk(&mut children, b"nTRN", &content, &[]);
+
+        let mut content = Vec::new();
+        write_i32(&mut content, 1);
+        write_dict(&mut content, &[]);
+        write_i32(&mut content, model_count);
+        for i in 0..model_count {
+            write_i32(&mut content, 2 + i * 2);
+        }
+        write_chunk(&mut children, b"nGRP", &content, &[]);
+
+        for (i, model) in self.models.iter().enumerate() {
+            let i = i as i32;
+            let center = model.offset + half_size(model.size);
+
+            let mut content = Vec::new();
+            write_transform(&mut content, 2 + i * 2, 3 + i * 2, Some(center));
+            write_chunk(&mut children, b"nTRN", &content, &[]);
+
+            let mut content = Vec::new();
+            write_i32(&mut content, 3 + i * 2);
+            write_dict(&mut content, &[]);
+            write_i32(&mut content, 1);
+            write_i32(&mut content, i);
+            write_dict(&mut content, &[]);
+            write_chunk(&mut children, b"nSHP", &content, &[]);
+        }
+
+        let rgba = self.palette[1..].iter().chain([&[0; 4]]).flatten().copied().collect::<Vec<_>>();
+        write_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+        let mut out = b"VOX ".to_vec();
+        out.extend(VERSION.to_le_bytes());
+        write_chunk(&mut out, b"MAIN", &[], &children);
+        out
+    }
+}
+
+/// Sets the offset of every model below `node_id` from the translations on the way there
+fn place_models(
+    nodes: &HashMap<i32, Node>,
+    node_id: i32,
+    translation: IVec3,
+    depth: u32,
+    models: &mut [VoxModel],
+) -> Result<(), String> {
+    if depth > MAX_NODE_DEPTH {
+        return Err("the scene graph is too deep".to_string());
+    }
+
+    match nodes.get(&node_id) {
+        Some(Node::Transform { child, translation: t }) => {
+            place_models(nodes, *child, translation + *t, depth + 1, models)?;
+        }
+        Some(Node::Group { children }) => {
+            for child in children {
+                place_models(nodes, *child, translation, depth + 1, models)?;
+            }
+        }
+        Some(Node::Shape { models: shape_models }) => {
+            for id in shape_models {
+                let model = models.get_mut(*id as usize).ok_or("a shape node uses a missing model")?;
+                model.offset = translation - half_size(model.size);
+            }
+        }
+        None => return Err(format!("node {} is missing", node_id)),
+    }
+    Ok(())
+}
+
+/// Translations point at the center of a model, rounded down
+fn half_size(size: UVec3) -> IVec3 {
+    IVec3::new((size.x / 2) as i32, (size.y / 2) as i32, (size.z / 2) as i32)
+}
+
+fn parse_translation(value: Option<&str>) -> Result<IVec3, String> {
+    let Some(value) = value else { return Ok(IVec3::zero()) };
+    let coords = value
+        .split_whitespace()
+        .map(|v| v.parse::<i32>().map_err(|_| format!("bad translation \"{}\"", value)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match coords.as_slice() {
+        [x, y, z] => Ok(IVec3::new(*x, *y, *z)),
+        _ => Err(format!("bad translation \"{}\"", value)),
+    }
+}
+
+/// Gray ramp for files without an `RGBA` chunk, MagicaVoxel's own default palette isn't stored
+fn default_palette() -> [[u8; 4]; 256] {
+    std::array::from_fn(|i| [i as u8, i as u8, i as u8, 255])
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend(id);
+    out.extend((content.len() as u32).to_le_bytes());
+    out.extend((children.len() as u32).to_le_bytes());
+    out.extend(content);
+    out.extend(children);
+}
+
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend(value.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    out.extend((value.len() as u32).to_le_bytes());
+    out.extend(value.as_bytes());
+}
+
+fn write_dict(out: &mut Vec<u8>, entries: &[(&str, &str)]) {
+    out.extend((entries.len() as u32).to_le_bytes());
+    for (key, value) in entries {
+        write_string(out, key);
+        write_string(out, value);
+    }
+}
+
+/// The root has no translation and isn't on a layer
+fn write_transform(out: &mut Vec<u8>, node_id: i32, child: i32, translation: Option<IVec3>) {
+    write_i32(out, node_id);
+    write_dict(out, &[]);
+    write_i32(out, child);
+    write_i32(out, -1);
+    write_i32(out, if translation.is_some() { 0 } else { -1 });
+    write_i32(out, 1);
+    match translation {
+        Some(t) => write_dict(out, &[("_t", &format!("{} {} {}", t.x, t.y, t.z))]),
+        None => write_dict(out, &[]),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.data.len() {
+            return Err("the file ends early".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, String> {
+        let count = self.u32()?;
+        (0..count).map(|_| Ok((self.string()?, self.string()?))).collect()
+    }
+
+    /// Id, content and children of the next chunk
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8], &'a [u8]), String> {
+        let id = self.bytes(4)?;
+        let content_len = self.u32()? as usize;
+        let children_len = self.u32()? as usize;
+        Ok((id, self.bytes(content_len)?, self.bytes(children_len)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut palette = default_palette();
+        palette[1] = [255, 0, 0, 255];
+
+        let file = VoxFile {
+            models: vec![
+                VoxModel {
+                    size: UVec3::new(2, 2, 2),
+                    offset: IVec3::new(-1, -1, 0),
+                    voxels: vec![Voxel { pos: [0, 0, 0], color: 1 }, Voxel { pos: [1, 1, 1], color: 2 }],
+                },
+                VoxModel {
+                    size: UVec3::new(3, 1, 1),
+                    offset: IVec3::new(4, 0, 8),
+                    voxels: vec![Voxel { pos: [2, 0, 0], color: 255 }],
+                },
+            ],
+            palette,
+        };
+
+        assert_eq!(VoxFile::parse(&file.to_bytes()), Ok(file));
+    }
+
+    #[test]
+    fn parse_without_scene_graph() {
+        let mut children = Vec::new();
+        write_chunk(&mut children, b"SIZE", &[[1, 0, 0, 0], [1, 0, 0, 0], [1, 0, 0, 0]].concat(), &[]);
+        write_chunk(&mut children, b"XYZI", &[1, 0, 0, 0, 0, 0, 0, 7], &[]);
+        let mut data = b"VOX ".to_vec();
+        data.extend(VERSION.to_le_bytes());
+        write_chunk(&mut data, b"MAIN", &[], &children);
+
+        let file = VoxFile::parse(&data).unwrap();
+        assert_eq!(file.models.len(), 1);
+        assert_eq!(file.models[0].offset, IVec3::zero());
+        assert_eq!(file.models[0].voxels, vec![Voxel { pos: [0, 0, 0], color: 7 }]);
+
+        assert!(VoxFile::parse(&data[..data.len() - 1]).is_err());
+        assert!(VoxFile::parse(b"PNG ").is_err());
+    }
+}
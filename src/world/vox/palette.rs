@@ -0,0 +1,85 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use ahash::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::world::block_data::{BlockHandle, Blocks, StaticBlockData};
+
+pub const VOX_PALETTE_PATH: &str = "./vox_palette.json";
+
+/// Which block each `.vox` palette index becomes, read from `VOX_PALETTE_PATH`.
+///
+/// Entries missing from the file keep their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoxPalette {
+    /// Block id of palette indices, from 1 to 255
+    pub blocks: BTreeMap<u8, String>,
+    /// Block id for palette indices without an entry
+    pub default: String,
+}
+
+impl Default for VoxPalette {
+    fn default() -> Self {
+        let blocks = ["stone", "dirt", "grass_block", "leaves", "log", "glass", "water", "grass", "stone_slab"]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (i as u8 + 1, id.to_string()))
+            .collect();
+
+        Self { blocks, default: "stone".to_string() }
+    }
+}
+
+impl VoxPalette {
+    /// Creates the file with the defaults if there is none
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(src) => serde_json::from_str(&src).unwrap_or_else(|e| {
+                println!("WARNING: Could not read the vox palette {:?}, using the defaults: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => {
+                let palette = Self::default();
+                if let Err(e) = palette.save(path) {
+                    println!("WARNING: Could not write the default vox palette {:?}: {}", path, e);
+                }
+                palette
+            }
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Block of every palette index, index 0 is air. Unknown block ids are replaced with the default.
+    pub fn blocks(&self, block_data: &StaticBlockData) -> [BlockHandle; 256] {
+        let default = block_data.get_handle(&self.default).unwrap_or_else(|| {
+            println!("WARNING: Unknown block \"{}\" in the vox palette, using stone", self.default);
+            Blocks::Stone.handle()
+        });
+
+        let mut ret = [default; 256];
+        ret[0] = Blocks::Air.handle();
+        for (index, id) in self.blocks.iter().filter(|(index, _)| **index > 0) {
+            match block_data.get_handle(id) {
+                Some(block) => ret[*index as usize] = block,
+                None => println!("WARNING: Unknown block \"{}\" in the vox palette, using {}", id, self.default),
+            }
+        }
+        ret
+    }
+
+    /// Palette index of every block with an entry, the lowest index wins if a block has several
+    pub fn indices(&self, block_data: &StaticBlockData) -> HashMap<u32, u8> {
+        let mut ret = HashMap::default();
+        for (index, id) in self.blocks.iter().filter(|(index, _)| **index > 0) {
+            if let Some(block) = block_data.get_handle(id) {
+                ret.entry(block.inner()).or_insert(*index);
+            }
+        }
+        ret
+    }
+}
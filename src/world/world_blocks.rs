@@ -1,35 +1,48 @@
-use ahash::HashMap;
-use ultraviolet::{IVec2, Vec2};
+use std::collections::VecDeque;
 
-use crate::util::util::AdditionalSwizzles;
+use ahash::{HashMap, HashSet};
+use ultraviolet::{IVec2, IVec3, UVec3, Vec2};
+
+use crate::{profiler, util::util::AdditionalSwizzles};
 
 use super::{
-    block_data::StaticBlockData, chunk::Chunk, generation::terrain::TerrainGenerator,
-    section::F_SECTION_SIZE,
+    block_access::BlockAccess,
+    block_data::{BlockHandle, StaticBlockData},
+    chunk::Chunk,
+    generation::{config::TerrainConfig, terrain::TerrainGenerator},
+    section::{F_SECTION_SIZE, I_SECTION_SIZE},
 };
 
 pub struct WorldBlocks {
     pub loaded_chunks: HashMap<IVec2, Chunk>,
     pub updated_chunks: Vec<IVec2>,
+    /// Chunks requested through the renderer's feedback buffer, loaded before any others.
+    pub requested_chunks: VecDeque<IVec2>,
+    /// Requests that went out of range before they were loaded, the renderer marks them as not loaded again
+    pub dropped_requests: Vec<IVec2>,
     pub terrain_generator: TerrainGenerator,
     pub player_pos: Vec2,
+    /// Chunks further than this from the player are unloaded
+    pub render_distance: u32,
 }
 
 impl WorldBlocks {
     const CHUNK_UPDATES_PER_FRAME: u32 = 8;
-    const LOAD_DISTANCE: u32 = 32;
-    const RENDER_DISTANCE: u32 = 32;
 
-    pub fn new(block_data: &StaticBlockData) -> Self {
+    pub fn new(terrain_config: TerrainConfig, block_data: &StaticBlockData) -> Self {
         Self {
             loaded_chunks: HashMap::default(),
             updated_chunks: Vec::new(),
-            terrain_generator: TerrainGenerator::new_random(block_data),
+            requested_chunks: VecDeque::new(),
+            dropped_requests: Vec::new(),
+            terrain_generator: TerrainGenerator::new_random(terrain_config, block_data),
             player_pos: Vec2::zero(),
+            render_distance: 32,
         }
     }
 
     pub fn load_chunk(&mut self, chunk_pos: IVec2, block_data: &StaticBlockData) {
+        let _scope = profiler::scope("chunk generation");
         // TODO: Load from storage
         let mut new_chunk = Chunk::generate(chunk_pos, &mut self.terrain_generator);
         new_chunk.update_brickmap(block_data);
@@ -38,8 +51,34 @@ impl WorldBlocks {
         self.updated_chunks.push(chunk_pos);
     }
 
+    /// Queues chunks that rays hit while they were not loaded.
+    ///
+    /// Returns the chunks that were accepted, chunks outside of the load distance are ignored.
+    pub fn request_chunks(&mut self, chunks: Vec<IVec2>) -> Vec<IVec2> {
+        let center_chunk = self.center_chunk();
+        let mut accepted = Vec::new();
+        for pos in chunks {
+            if (pos - center_chunk).abs().component_max() as u32 <= self.render_distance
+                && !self.loaded_chunks.contains_key(&pos)
+                && !self.requested_chunks.contains(&pos)
+            {
+                self.requested_chunks.push_back(pos);
+                accepted.push(pos);
+            }
+        }
+        accepted
+    }
+
     pub fn frame_update(&mut self, block_data: &StaticBlockData) {
-        let to_load = self.get_closest_unloaded_chunks(Self::CHUNK_UPDATES_PER_FRAME.try_into().unwrap());
+        let num = Self::CHUNK_UPDATES_PER_FRAME as usize;
+        let (mut to_load, dropped) = self.take_requested_chunks(num);
+        self.dropped_requests.extend(dropped);
+        let closest = self.get_closest_unloaded_chunks(num - to_load.len());
+        for pos in closest {
+            if !to_load.contains(&pos) {
+                to_load.push(pos);
+            }
+        }
 
         for pos in to_load.into_iter() {
             self.load_chunk(pos, block_data);
@@ -52,9 +91,100 @@ impl WorldBlocks {
         }
     }
 
-    fn get_closest_unloaded_chunks(&self, num: usize) -> Vec<IVec2> {
+    /// Places blocks in loaded chunks and queues the chunks for the renderer, returns how many were placed.
+    ///
+    /// Chunks are generated again once they are unloaded, so the changes don't last.
+    pub fn set_blocks(
+        &mut self,
+        blocks: impl IntoIterator<Item = (IVec3, BlockHandle)>,
+        block_data: &StaticBlockData,
+    ) -> usize {
+        let mut placed = 0;
+        let mut changed_sections = HashSet::default();
+        for (pos, block) in blocks {
+            let chunk_pos = IVec2::new(
+                pos.x.div_euclid(I_SECTION_SIZE.x),
+                pos.z.div_euclid(I_SECTION_SIZE.z),
+            );
+            let Some(chunk) = self.loaded_chunks.get_mut(&chunk_pos) else { continue };
+            if pos.y < 0 {
+                continue;
+            }
+            let section_idx = (pos.y / I_SECTION_SIZE.y) as usize;
+            let Some(section) = chunk.sections.get_mut(section_idx) else { continue };
+
+            let local = UVec3::new(
+                pos.x.rem_euclid(I_SECTION_SIZE.x) as u32,
+                (pos.y % I_SECTION_SIZE.y) as u32,
+                pos.z.rem_euclid(I_SECTION_SIZE.z) as u32,
+            );
+            section.set_block(local, block);
+            changed_sections.insert((chunk_pos, section_idx));
+            placed += 1;
+        }
+
+        let mut changed_chunks = HashSet::default();
+        for (chunk_pos, section_idx) in changed_sections {
+            let chunk = self.loaded_chunks.get_mut(&chunk_pos).unwrap();
+            chunk.sections[section_idx].update_brickmap(block_data);
+            changed_chunks.insert(chunk_pos);
+        }
+        for chunk_pos in changed_chunks {
+            if !self.updated_chunks.contains(&chunk_pos) {
+                self.updated_chunks.push(chunk_pos);
+            }
+        }
+
+        placed
+    }
+
+    /// The block at a world position, `None` if its chunk is not loaded
+    pub fn get_block(&self, pos: IVec3) -> Option<BlockHandle> {
+        let chunk_pos = IVec2::new(
+            pos.x.div_euclid(I_SECTION_SIZE.x),
+            pos.z.div_euclid(I_SECTION_SIZE.z),
+        );
+        let chunk = self.loaded_chunks.get(&chunk_pos)?;
+
+        if pos.y < 0 {
+            return None;
+        }
+        let section = chunk.sections.get((pos.y / I_SECTION_SIZE.y) as usize)?;
+
+        let local = UVec3::new(
+            pos.x.rem_euclid(I_SECTION_SIZE.x) as u32,
+            (pos.y % I_SECTION_SIZE.y) as u32,
+            pos.z.rem_euclid(I_SECTION_SIZE.z) as u32,
+        );
+        Some(section.get_block(local))
+    }
+
+    pub fn center_chunk(&self) -> IVec2 {
         let div_size = self.player_pos / -F_SECTION_SIZE.xz();
-        let center_chunk = IVec2::new(div_size.x.floor() as i32, div_size.y.floor() as i32);
+        IVec2::new(div_size.x.floor() as i32, div_size.y.floor() as i32)
+    }
+
+    /// Returns up to `num` requested chunks to load, and the requests that are out of range by now
+    fn take_requested_chunks(&mut self, num: usize) -> (Vec<IVec2>, Vec<IVec2>) {
+        let center_chunk = self.center_chunk();
+        let mut ret = Vec::new();
+        let mut dropped = Vec::new();
+        while ret.len() < num {
+            let Some(pos) = self.requested_chunks.pop_front() else { break };
+
+            // The player may have moved away since the request was made
+            let in_range = (pos - center_chunk).abs().component_max() as u32 <= self.render_distance;
+            if !in_range {
+                dropped.push(pos);
+            } else if !self.loaded_chunks.contains_key(&pos) {
+                ret.push(pos);
+            }
+        }
+        (ret, dropped)
+    }
+
+    fn get_closest_unloaded_chunks(&self, num: usize) -> Vec<IVec2> {
+        let center_chunk = self.center_chunk();
 
         let mut check = center_chunk;
         let mut step = SpiralStep::Right;
@@ -65,7 +195,7 @@ impl WorldBlocks {
         let mut ret = Vec::new();
 
         while ret.len() < num {
-            if self.loaded_chunks.get(&check).is_none() {
+            if !self.loaded_chunks.contains_key(&check) {
                 ret.push(check);
             }
 
@@ -76,7 +206,7 @@ impl WorldBlocks {
                 SpiralStep::Down => check.y -= 1,
             }
 
-            if (check - center_chunk).abs().component_max() as u32 > Self::LOAD_DISTANCE {
+            if (check - center_chunk).abs().component_max() as u32 > self.render_distance {
                 break;
             }
 
@@ -94,12 +224,11 @@ impl WorldBlocks {
     }
 
     fn get_chunks_to_unload(&self) -> Vec<IVec2> {
-        let div_size = self.player_pos / -F_SECTION_SIZE.xz();
-        let player_pos = IVec2::new(div_size.x.floor() as i32, div_size.y.floor() as i32);
+        let player_pos = self.center_chunk();
 
         let mut ret = Vec::new();
         for pos in self.loaded_chunks.keys() {
-            if (*pos - player_pos).abs().component_max() as u32 > Self::RENDER_DISTANCE + 1 {
+            if (*pos - player_pos).abs().component_max() as u32 > self.render_distance + 1 {
                 ret.push(*pos);
             }
         }
@@ -124,3 +253,53 @@ impl SpiralStep {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::render::texture::TextureAtlas;
+
+    use super::*;
+
+    fn world() -> WorldBlocks {
+        let mut block_data = StaticBlockData::empty();
+        block_data.init(&TextureAtlas::from_folder("./resources"));
+        let mut world = WorldBlocks::new(TerrainConfig::default(), &block_data);
+        world.render_distance = 2;
+        world.loaded_chunks.insert(IVec2::new(1, 0), Chunk::empty(IVec2::new(1, 0)));
+        world
+    }
+
+    #[test]
+    fn request_chunks() {
+        let mut world = world();
+        let accepted = world.request_chunks(vec![
+            IVec2::new(0, 1),
+            IVec2::new(1, 0),
+            IVec2::new(3, 0),
+            IVec2::new(0, 1),
+            IVec2::new(-2, -2),
+        ]);
+        assert_eq!(accepted, vec![IVec2::new(0, 1), IVec2::new(-2, -2)]);
+        assert_eq!(world.request_chunks(vec![IVec2::new(0, 1)]), vec![]);
+        assert_eq!(world.requested_chunks, [IVec2::new(0, 1), IVec2::new(-2, -2)]);
+    }
+
+    #[test]
+    fn take_requested_chunks() {
+        let mut world = world();
+        world.request_chunks(vec![IVec2::new(0, 1), IVec2::new(2, 2), IVec2::new(-1, 0), IVec2::new(0, -1)]);
+        // Loaded after it was requested
+        world.loaded_chunks.insert(IVec2::new(-1, 0), Chunk::empty(IVec2::new(-1, 0)));
+        // The player moved, so (2, 2) is out of range
+        world.render_distance = 1;
+
+        let (to_load, dropped) = world.take_requested_chunks(2);
+        assert_eq!(to_load, vec![IVec2::new(0, 1), IVec2::new(0, -1)]);
+        assert_eq!(dropped, vec![IVec2::new(2, 2)]);
+        assert!(world.requested_chunks.is_empty());
+
+        world.request_chunks(vec![IVec2::new(1, 1), IVec2::new(-1, 1)]);
+        assert_eq!(world.take_requested_chunks(1), (vec![IVec2::new(1, 1)], vec![]));
+        assert_eq!(world.requested_chunks, [IVec2::new(-1, 1)]);
+    }
+}